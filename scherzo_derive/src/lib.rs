@@ -53,6 +53,26 @@ pub fn impl_db_methods(input: TokenStream) -> TokenStream {
             self. #input .contains_key(key.as_ref()).await.map_err(ServerError::DbError)
         }
 
+        pub async fn compare_and_swap(&self, key: impl AsRef<[u8]>, old: Option<&[u8]>, new: Option<&[u8]>) -> Result<bool, ServerError> {
+            self. #input .compare_and_swap(key.as_ref(), old, new).await.map_err(ServerError::DbError)
+        }
+
+        /// Atomically replaces the value of `key` with what `f` returns for the
+        /// current value, retrying if it was changed in between. Returns the new value.
+        pub async fn update_and_fetch<F>(&self, key: impl AsRef<[u8]>, mut f: F) -> Result<Option<Vec<u8>>, ServerError>
+        where
+            F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+        {
+            let key = key.as_ref();
+            loop {
+                let old = self.get(key).await?;
+                let new = f(old.as_deref());
+                if self.compare_and_swap(key, old.as_deref(), new.as_deref()).await? {
+                    return Ok(new);
+                }
+            }
+        }
+
         pub async fn scan_prefix<'a>(&'a self, prefix: impl AsRef<[u8]>) -> impl Iterator<Item = Result<(EVec, EVec), ServerError>> + 'a {
             self. #input .scan_prefix(prefix.as_ref()).await.map(|res| res.map_err(ServerError::DbError))
         }
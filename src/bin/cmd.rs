@@ -3,7 +3,11 @@ use std::{error::Error, fmt::Display, io::prelude::*, mem::size_of};
 use scherzo::{
     config::DbConfig,
     db::deser_guild,
-    impls::{auth::AuthTree, chat::ChatTree},
+    impls::{
+        auth::{registration_token::describe_registration_token, AuthTree},
+        chat::ChatTree,
+    },
+    utils::get_time_secs,
 };
 
 #[tokio::main]
@@ -63,8 +67,44 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     )?;
                 }
             }
+            "registration-tokens" => {
+                let tokens = auth_tree
+                    .list_registration_tokens_logic(get_time_secs())
+                    .await?;
+
+                let mut stdout = std::io::stdout();
+                for (token_id, info) in tokens {
+                    writeln!(stdout, "{}", describe_registration_token(token_id, &info))?;
+                }
+            }
             _ => exit_with_msg("no such list", 1),
         },
+        "revoke" => match args
+            .get(1)
+            .map(String::as_str)
+            .ok_or("need what to revoke")?
+        {
+            "registration-token" => {
+                let token_id = args
+                    .get(2)
+                    .map(String::as_str)
+                    .ok_or("need token id")?
+                    .parse::<u64>()?;
+                if auth_tree.revoke_registration_token_logic(token_id).await? {
+                    writeln!(std::io::stdout(), "revoked token {}", token_id)?;
+                } else {
+                    exit_with_msg("no such token", 1);
+                }
+            }
+            _ => exit_with_msg("cant revoke that", 1),
+        },
+        "rebuild" => match args.get(1).map(String::as_str).ok_or("need index name")? {
+            "search-index" => {
+                let indexed = chat_tree.rebuild_search_index_logic().await?;
+                writeln!(std::io::stdout(), "indexed {} messages", indexed)?;
+            }
+            _ => exit_with_msg("no such index", 1),
+        },
         _ => exit_with_msg("no such command", 1),
     }
 
@@ -3,7 +3,8 @@ use std::{collections::HashMap, path::PathBuf};
 use hrpc::BoxError;
 use itertools::Itertools;
 use scherzo::{
-    db::{self, sled::shared as sled, sqlite::shared as sqlite, Batch},
+    config::{DbBackend, DbConfig},
+    db::{self, Batch, Db},
     utils::evec::EVec,
 };
 
@@ -15,8 +16,12 @@ fn main() -> Result<(), BoxError> {
 
     if args.len() < 4 {
         println!(
-            "usage: {} <db path> <db type> <db target type>\nexample: {} ./db sled sqlite",
-            args[0], args[0]
+            "usage: {0} <db path> <db type> <db target type> [target db path]\n\
+            example: {0} ./db sled sqlite\n\
+            example: {0} ./db sled postgres postgres://scherzo@localhost/scherzo\n\
+            if the target db path is not given, the db is moved to a backup path\n\
+            and the target db is created at the original path",
+            args[0]
         );
         std::process::exit(1);
     }
@@ -27,33 +32,30 @@ fn main() -> Result<(), BoxError> {
     let db_path = args.pop().expect("expected db path");
     let db_type = args.pop().expect("expected db type to migrate to");
     let db_target = args.pop().expect("expected db type to migrate to");
-    let db_config = scherzo::config::DbConfig::default();
+    let db_target_path = args.pop();
 
-    let mut db_backup_path = PathBuf::from(db_path.clone());
-    db_backup_path.set_file_name(format!(
-        "{}_migrate_backup",
-        db_backup_path.file_name().unwrap().to_string_lossy()
-    ));
+    let (src_path, target_path) = match db_target_path {
+        Some(target_path) => (db_path, target_path),
+        None => {
+            let mut db_backup_path = PathBuf::from(db_path.clone());
+            db_backup_path.set_file_name(format!(
+                "{}_migrate_backup",
+                db_backup_path.file_name().unwrap().to_string_lossy()
+            ));
 
-    std::fs::rename(&db_path, &db_backup_path)?;
+            std::fs::rename(&db_path, &db_backup_path)?;
 
-    let db_backup_path = db_backup_path.into_os_string().into_string().unwrap();
+            let db_backup_path = db_backup_path.into_os_string().into_string().unwrap();
+            (db_backup_path, db_path)
+        }
+    };
 
     rt.block_on(async move {
-        let src_db = match db_type.as_str() {
-            "sled" => Db::Sled(sled::open_database(db_backup_path, db_config.clone()).await?),
-            "sqlite" => Db::Sqlite(sqlite::open_database(db_backup_path, db_config.clone()).await?),
-            _ => panic!("no such db"),
-        };
-
-        let target_db = match db_target.as_str() {
-            "sled" => Db::Sled(sled::open_database(db_path, db_config).await?),
-            "sqlite" => Db::Sqlite(sqlite::open_database(db_path, db_config).await?),
-            _ => panic!("no such db"),
-        };
-
-        let vals = src_db.iter().await?;
-        target_db.insert(vals).await?;
+        let src_db = db::open_database(src_path, make_db_config(&db_type)).await?;
+        let target_db = db::open_database(target_path, make_db_config(&db_target)).await?;
+
+        let vals = read_all(&src_db).await?;
+        write_all(&target_db, vals).await?;
         target_db.flush().await?;
 
         Ok(())
@@ -62,70 +64,42 @@ fn main() -> Result<(), BoxError> {
 
 type ValueMap = HashMap<&'static [u8], Vec<(EVec, EVec)>, ahash::RandomState>;
 
-enum Db {
-    Sled(sled::Db),
-    Sqlite(sqlite::Db),
-}
-
-impl Db {
-    async fn iter(&self) -> Result<ValueMap, BoxError> {
-        let mut treemap = HashMap::with_hasher(ahash::RandomState::new());
-        for name in db::TREES {
-            match self {
-                Self::Sled(db) => {
-                    let tree = db.open_tree(name).await?;
-                    treemap.insert(
-                        name,
-                        tree.iter().await.fold_ok(Vec::new(), |mut all, item| {
-                            all.push(item);
-                            all
-                        })?,
-                    );
-                }
-                Self::Sqlite(db) => {
-                    let tree = db.open_tree(name).await?;
-                    treemap.insert(
-                        name,
-                        tree.iter().await.fold_ok(Vec::new(), |mut all, item| {
-                            all.push(item);
-                            all
-                        })?,
-                    );
-                }
-            }
-        }
-        Ok(treemap)
+fn make_db_config(db_type: &str) -> DbConfig {
+    let backend = match db_type {
+        "sled" => DbBackend::Sled,
+        "sqlite" => DbBackend::Sqlite,
+        "postgres" => DbBackend::Postgres,
+        _ => panic!("no such db"),
+    };
+    DbConfig {
+        backend,
+        ..Default::default()
     }
+}
 
-    async fn insert(&self, mut values: ValueMap) -> Result<(), BoxError> {
-        for name in db::TREES {
-            match self {
-                Self::Sled(db) => {
-                    let tree = db.open_tree(name).await?;
-                    let mut batch = Batch::default();
-                    for (k, v) in values.remove(name).expect("no such tree") {
-                        batch.insert(k, v);
-                    }
-                    tree.apply_batch(batch).await?;
-                }
-                Self::Sqlite(db) => {
-                    let tree = db.open_tree(name).await?;
-                    let mut batch = Batch::default();
-                    for (k, v) in values.remove(name).expect("no such tree") {
-                        batch.insert(k, v);
-                    }
-                    tree.apply_batch(batch).await?;
-                }
-            }
-        }
-        Ok(())
+async fn read_all(db: &Db) -> Result<ValueMap, BoxError> {
+    let mut treemap = HashMap::with_hasher(ahash::RandomState::new());
+    for name in db::TREES {
+        let tree = db.open_tree(name).await?;
+        treemap.insert(
+            name,
+            tree.iter().await.fold_ok(Vec::new(), |mut all, item| {
+                all.push(item);
+                all
+            })?,
+        );
     }
+    Ok(treemap)
+}
 
-    async fn flush(&self) -> Result<(), BoxError> {
-        match self {
-            Self::Sled(db) => db.flush().await?,
-            Self::Sqlite(db) => db.flush().await?,
+async fn write_all(db: &Db, mut values: ValueMap) -> Result<(), BoxError> {
+    for name in db::TREES {
+        let tree = db.open_tree(name).await?;
+        let mut batch = Batch::default();
+        for (k, v) in values.remove(name).expect("no such tree") {
+            batch.insert(k, v);
         }
-        Ok(())
+        tree.apply_batch(batch).await?;
     }
+    Ok(())
 }
@@ -1,4 +1,8 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
 use lettre::transport::smtp::authentication::Credentials;
 use serde::{Deserialize, Serialize};
@@ -35,6 +39,9 @@ pub struct Config {
     pub listen_on_localhost: bool,
     #[serde(default)]
     pub log_headers: bool,
+    /// Whether to serve metrics in the Prometheus text format at `/_harmony/metrics`
+    #[serde(default)]
+    pub metrics: bool,
     #[serde(default = "port_default")]
     pub port: u16,
     #[serde(default)]
@@ -49,6 +56,12 @@ pub struct Config {
     pub federation: Option<FederationConfig>,
     #[serde(default)]
     pub email: Option<EmailConfig>,
+    #[serde(default)]
+    pub event_bus: Option<EventBusConfig>,
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    #[serde(default)]
+    pub ldap: Option<LdapConfig>,
 }
 
 impl Default for Config {
@@ -59,6 +72,7 @@ impl Default for Config {
             server_description: String::new(),
             motd: String::new(),
             log_headers: false,
+            metrics: false,
             listen_on_localhost: listen_on_localhost_default(),
             port: port_default(),
             policy: PolicyConfig::default(),
@@ -67,14 +81,31 @@ impl Default for Config {
             tls: None,
             federation: federation_config_default(),
             email: None,
+            event_bus: None,
+            oidc: None,
+            ldap: None,
         }
     }
 }
 
+impl Config {
+    /// Whether this node runs the periodic tasks, which is always the case if
+    /// there is only one node.
+    pub fn runs_periodic_tasks(&self) -> bool {
+        self.event_bus
+            .as_ref()
+            .map_or(true, |event_bus| event_bus.run_periodic_tasks)
+    }
+}
+
 const fn max_concurrent_requests_default() -> usize {
     512
 }
 
+const fn max_bots_per_user_default() -> u32 {
+    10
+}
+
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct RateLimitConfig {
     #[serde(default)]
@@ -96,6 +127,20 @@ pub struct PolicyConfig {
     pub disable_registration_email_validation: bool,
     #[serde(default = "max_concurrent_requests_default")]
     pub max_concurrent_requests: usize,
+    /// default message retention, can be overriden per guild and per channel
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    #[serde(default)]
+    pub event_log: EventLogConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default)]
+    pub login_lockout: LoginLockoutConfig,
+    /// 0 means users can't create bots
+    #[serde(default = "max_bots_per_user_default")]
+    pub max_bots_per_user: u32,
+    #[serde(default)]
+    pub guests: GuestConfig,
 }
 
 impl Default for PolicyConfig {
@@ -105,6 +150,215 @@ impl Default for PolicyConfig {
             disable_registration: false,
             disable_registration_email_validation: false,
             max_concurrent_requests: max_concurrent_requests_default(),
+            retention: RetentionPolicy::default(),
+            event_log: EventLogConfig::default(),
+            sessions: SessionsConfig::default(),
+            login_lockout: LoginLockoutConfig::default(),
+            max_bots_per_user: max_bots_per_user_default(),
+            guests: GuestConfig::default(),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RetentionPolicy {
+    /// This is in seconds
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// Max amount of messages kept per channel
+    #[serde(default)]
+    pub max_count: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Fills the limits that aren't set with the ones from `fallback`.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            max_age: self.max_age.or(fallback.max_age),
+            max_count: self.max_count.or(fallback.max_count),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_none() && self.max_count.is_none()
+    }
+}
+
+const fn event_log_max_events_default() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EventLogConfig {
+    /// Max amount of events kept per guild, 0 disables the event log
+    #[serde(default = "event_log_max_events_default")]
+    pub max_events: u64,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self {
+            max_events: event_log_max_events_default(),
+        }
+    }
+}
+
+const fn sessions_idle_timeout_default() -> u64 {
+    60 * 60 * 24 * 2
+}
+
+const fn sessions_refresh_token_lifetime_default() -> u64 {
+    60 * 60 * 24 * 30
+}
+
+const fn sessions_auth_flow_ttl_default() -> u64 {
+    60 * 30
+}
+
+/// All of these are in seconds, and bot sessions never expire.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionsConfig {
+    /// How long a session can go unused before it expires, 0 disables this
+    #[serde(default = "sessions_idle_timeout_default")]
+    pub idle_timeout: u64,
+    /// How long a session token is valid for after it was issued, even if
+    /// it's used, 0 disables this
+    #[serde(default)]
+    pub max_lifetime: u64,
+    /// How long a refresh token can be exchanged for a new session token
+    /// after it was issued
+    #[serde(default = "sessions_refresh_token_lifetime_default")]
+    pub refresh_token_lifetime: u64,
+    /// How long an in-progress login or registration is kept after its last step
+    #[serde(default = "sessions_auth_flow_ttl_default")]
+    pub auth_flow_ttl: u64,
+}
+
+impl SessionsConfig {
+    /// Whether a session token issued at `issued_at` and last used at
+    /// `last_seen` has expired at `now`.
+    pub fn is_token_expired(&self, issued_at: u64, last_seen: u64, now: u64) -> bool {
+        let idle_expired =
+            self.idle_timeout != 0 && now.saturating_sub(last_seen) >= self.idle_timeout;
+        let lifetime_expired =
+            self.max_lifetime != 0 && now.saturating_sub(issued_at) >= self.max_lifetime;
+        idle_expired || lifetime_expired
+    }
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: sessions_idle_timeout_default(),
+            max_lifetime: 0,
+            refresh_token_lifetime: sessions_refresh_token_lifetime_default(),
+            auth_flow_ttl: sessions_auth_flow_ttl_default(),
+        }
+    }
+}
+
+const fn guests_lifetime_default() -> u64 {
+    60 * 60 * 24 * 7
+}
+
+fn guests_permissions_default() -> Vec<String> {
+    [
+        "messages.send",
+        "messages.view",
+        "roles.get",
+        "roles.user.get",
+    ]
+    .iter()
+    .map(ToString::to_string)
+    .collect()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuestConfig {
+    /// Whether users can log in as a guest without registering
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long a guest account exists for after it was created, in seconds
+    #[serde(default = "guests_lifetime_default")]
+    pub lifetime: u64,
+    /// Permissions guests can have in guilds. They still need their roles to
+    /// give them these, guests just can't have any others.
+    #[serde(default = "guests_permissions_default")]
+    pub permissions: Vec<String>,
+}
+
+impl Default for GuestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lifetime: guests_lifetime_default(),
+            permissions: guests_permissions_default(),
+        }
+    }
+}
+
+const fn login_lockout_max_failures_default() -> u32 {
+    5
+}
+
+const fn login_lockout_ip_max_failures_default() -> u32 {
+    20
+}
+
+const fn login_lockout_duration_default() -> u64 {
+    60 * 15
+}
+
+const fn login_lockout_max_delay_default() -> u64 {
+    60
+}
+
+const fn login_lockout_notify_by_email_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoginLockoutConfig {
+    /// Failed logins after which an account is locked, 0 disables this
+    #[serde(default = "login_lockout_max_failures_default")]
+    pub max_failures: u32,
+    /// Failed logins after which an IP is locked out, 0 disables this
+    #[serde(default = "login_lockout_ip_max_failures_default")]
+    pub ip_max_failures: u32,
+    /// How long a lockout lasts. Failed logins are also forgotten if there
+    /// weren't any others for this long.
+    #[serde(default = "login_lockout_duration_default")]
+    pub duration: u64,
+    /// Max delay between failed logins, the delay doubles with every failure
+    /// starting from a second
+    #[serde(default = "login_lockout_max_delay_default")]
+    pub max_delay: u64,
+    /// Whether to email users when their account gets locked
+    #[serde(default = "login_lockout_notify_by_email_default")]
+    pub notify_by_email: bool,
+}
+
+impl LoginLockoutConfig {
+    /// How long to wait after the last failed login, when there were `failures` of them.
+    pub fn delay_for(&self, failures: u32) -> u64 {
+        match failures {
+            0 => 0,
+            failures => 1_u64
+                .checked_shl(failures - 1)
+                .unwrap_or(u64::MAX)
+                .min(self.max_delay),
+        }
+    }
+}
+
+impl Default for LoginLockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: login_lockout_max_failures_default(),
+            ip_max_failures: login_lockout_ip_max_failures_default(),
+            duration: login_lockout_duration_default(),
+            max_delay: login_lockout_max_delay_default(),
+            notify_by_email: login_lockout_notify_by_email_default(),
         }
     }
 }
@@ -113,8 +367,41 @@ const fn sled_load_to_cache_on_startup_default() -> bool {
     true
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbBackend {
+    Sled,
+    Sqlite,
+    Postgres,
+}
+
+impl DbBackend {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            DbBackend::Sled => "sled",
+            DbBackend::Sqlite => "sqlite",
+            DbBackend::Postgres => "postgres",
+        }
+    }
+}
+
+impl Default for DbBackend {
+    /// The first backend scherzo was compiled with, preferring sled.
+    fn default() -> Self {
+        if cfg!(feature = "sled") {
+            DbBackend::Sled
+        } else if cfg!(feature = "sqlite") {
+            DbBackend::Sqlite
+        } else {
+            DbBackend::Postgres
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DbConfig {
+    #[serde(default)]
+    pub backend: DbBackend,
     /// This is in MiB
     #[serde(default = "db_cache_limit_default")]
     pub db_cache_limit: u64,
@@ -129,6 +416,7 @@ pub struct DbConfig {
 impl Default for DbConfig {
     fn default() -> Self {
         Self {
+            backend: DbBackend::default(),
             db_cache_limit: db_cache_limit_default(),
             db_backup_path: None,
             sled_throughput_at_storage_cost: false,
@@ -203,6 +491,27 @@ impl Default for FederationConfig {
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EventBusConfig {
+    /// Address to listen on for events sent by other nodes
+    pub listen: SocketAddr,
+    /// Addresses of the other nodes to send events to
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Secret every node must share, used to authenticate connections. It is
+    /// never sent over the network
+    pub secret: String,
+    /// Whether this node runs the periodic tasks that change the database,
+    /// like pruning messages. Exactly one node should run them, otherwise
+    /// they do the same work on every node
+    #[serde(default = "run_periodic_tasks_default")]
+    pub run_periodic_tasks: bool,
+}
+
+const fn run_periodic_tasks_default() -> bool {
+    true
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EmailConfig {
     pub server: String,
@@ -240,10 +549,74 @@ impl From<EmailCredentials> for Credentials {
     }
 }
 
+fn oidc_scopes_default() -> Vec<String> {
+    ["openid", "profile"].map(ToString::to_string).to_vec()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OidcConfig {
+    /// Metadata of the provider is fetched from `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Must point to `/_harmony/oidc/callback` of this server
+    pub redirect_url: String,
+    #[serde(default = "oidc_scopes_default")]
+    pub scopes: Vec<String>,
+}
+
+fn ldap_user_filter_default() -> String {
+    "(mail={email})".to_string()
+}
+
+fn ldap_username_attribute_default() -> String {
+    "uid".to_string()
+}
+
+fn ldap_group_attribute_default() -> String {
+    "memberOf".to_string()
+}
+
+fn ldap_timeout_default() -> u64 {
+    5
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LdapConfig {
+    /// eg. `ldap://localhost:389` or `ldaps://ldap.example.com`
+    pub url: String,
+    /// DN to bind as when searching for users. Binds anonymously if not set.
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    #[serde(default)]
+    pub bind_password: Option<String>,
+    /// DN to search for users under
+    pub base_dn: String,
+    /// Filter used to find a user, `{email}` is replaced with the escaped email
+    #[serde(default = "ldap_user_filter_default")]
+    pub user_filter: String,
+    /// Attribute to take the username of new users from
+    #[serde(default = "ldap_username_attribute_default")]
+    pub username_attribute: String,
+    /// Attribute that lists the DNs of the groups a user is in
+    #[serde(default = "ldap_group_attribute_default")]
+    pub group_attribute: String,
+    /// Members of these groups are added to the admin guild, and removed from
+    /// it if they aren't in any of them anymore
+    #[serde(default)]
+    pub admin_groups: Vec<String>,
+    /// Guilds that members of a group join when their account is created
+    #[serde(default)]
+    pub group_guilds: HashMap<String, Vec<u64>>,
+    /// Timeout for connecting to the server, in seconds
+    #[serde(default = "ldap_timeout_default")]
+    pub timeout: u64,
+}
+
 #[cfg(test)]
 #[allow(clippy::field_reassign_with_default)]
 mod test {
-    use super::FederationConfig;
+    use super::{FederationConfig, SessionsConfig};
 
     #[test]
     fn host_allowed() {
@@ -270,4 +643,24 @@ mod test {
         assert!(fed_conf.is_host_allowed("not_test").is_err());
         assert!(fed_conf.is_host_allowed("hello").is_ok());
     }
+
+    #[test]
+    fn session_token_expiry() {
+        let policy = SessionsConfig {
+            idle_timeout: 10,
+            max_lifetime: 100,
+            refresh_token_lifetime: 1000,
+            ..SessionsConfig::default()
+        };
+        assert!(!policy.is_token_expired(0, 50, 55));
+        assert!(policy.is_token_expired(0, 50, 60));
+        assert!(policy.is_token_expired(0, 95, 100));
+
+        let policy = SessionsConfig {
+            idle_timeout: 0,
+            max_lifetime: 0,
+            ..policy
+        };
+        assert!(!policy.is_token_expired(0, 0, u64::MAX));
+    }
 }
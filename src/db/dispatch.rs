@@ -0,0 +1,160 @@
+//! Backend dispatching `Db` and `Tree` types, so that the backend can be
+//! chosen at runtime from the config out of the ones scherzo was compiled with.
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    config::{DbBackend, DbConfig},
+    utils::evec::EVec,
+};
+
+use super::{Batch, DbError, DbResult};
+
+#[cfg(not(any(feature = "sled", feature = "sqlite", feature = "postgres")))]
+compile_error!("at least one of the `sled`, `sqlite` or `postgres` features must be enabled");
+
+pub type DbIter<'a> = Box<dyn Iterator<Item = DbResult<(EVec, EVec)>> + Send + 'a>;
+pub type DbRangeIter<'a> = Box<dyn DoubleEndedIterator<Item = DbResult<(EVec, EVec)>> + Send + 'a>;
+
+macro_rules! dispatch {
+    ($this:expr, $inner:ident => $body:expr) => {
+        match $this {
+            #[cfg(feature = "sled")]
+            Self::Sled($inner) => $body,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite($inner) => $body,
+            #[cfg(feature = "postgres")]
+            Self::Postgres($inner) => $body,
+        }
+    };
+}
+
+/// Opens the database using the backend set in the config. Errors if scherzo
+/// wasn't compiled with that backend.
+pub async fn open_database(db_path: String, db_config: DbConfig) -> DbResult<Db> {
+    match db_config.backend {
+        #[cfg(feature = "sled")]
+        DbBackend::Sled => super::sled::shared::open_database(db_path, db_config)
+            .await
+            .map(Db::Sled),
+        #[cfg(feature = "sqlite")]
+        DbBackend::Sqlite => super::sqlite::shared::open_database(db_path, db_config)
+            .await
+            .map(Db::Sqlite),
+        #[cfg(feature = "postgres")]
+        DbBackend::Postgres => super::postgres::shared::open_database(db_path, db_config)
+            .await
+            .map(Db::Postgres),
+        #[allow(unreachable_patterns)]
+        backend => Err(DbError {
+            inner: format!(
+                "scherzo was not compiled with the `{}` database backend",
+                backend.as_str()
+            )
+            .into(),
+        }),
+    }
+}
+
+#[cfg(feature = "sled")]
+pub fn open_temp() -> Db {
+    Db::Sled(super::sled::shared::open_temp())
+}
+
+#[derive(Debug, Clone)]
+pub enum Db {
+    #[cfg(feature = "sled")]
+    Sled(super::sled::shared::Db),
+    #[cfg(feature = "sqlite")]
+    Sqlite(super::sqlite::shared::Db),
+    #[cfg(feature = "postgres")]
+    Postgres(super::postgres::shared::Db),
+}
+
+impl Db {
+    pub async fn open_tree(&self, name: &[u8]) -> DbResult<Tree> {
+        dispatch!(self, db => db.open_tree(name).await.map(Into::into))
+    }
+
+    pub async fn flush(&self) -> DbResult<()> {
+        dispatch!(self, db => db.flush().await)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Tree {
+    #[cfg(feature = "sled")]
+    Sled(super::sled::shared::Tree),
+    #[cfg(feature = "sqlite")]
+    Sqlite(super::sqlite::shared::Tree),
+    #[cfg(feature = "postgres")]
+    Postgres(super::postgres::shared::Tree),
+}
+
+#[cfg(feature = "sled")]
+impl From<super::sled::shared::Tree> for Tree {
+    fn from(tree: super::sled::shared::Tree) -> Self {
+        Tree::Sled(tree)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<super::sqlite::shared::Tree> for Tree {
+    fn from(tree: super::sqlite::shared::Tree) -> Self {
+        Tree::Sqlite(tree)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<super::postgres::shared::Tree> for Tree {
+    fn from(tree: super::postgres::shared::Tree) -> Self {
+        Tree::Postgres(tree)
+    }
+}
+
+impl Tree {
+    pub async fn get(&self, key: &[u8]) -> DbResult<Option<EVec>> {
+        dispatch!(self, tree => tree.get(key).await)
+    }
+
+    pub async fn insert(&self, key: &[u8], value: impl AsRef<[u8]>) -> DbResult<Option<EVec>> {
+        dispatch!(self, tree => tree.insert(key, value.as_ref()).await)
+    }
+
+    pub async fn remove(&self, key: &[u8]) -> DbResult<Option<EVec>> {
+        dispatch!(self, tree => tree.remove(key).await)
+    }
+
+    pub async fn contains_key(&self, key: &[u8]) -> DbResult<bool> {
+        dispatch!(self, tree => tree.contains_key(key).await)
+    }
+
+    pub async fn compare_and_swap(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> DbResult<bool> {
+        dispatch!(self, tree => tree.compare_and_swap(key, old, new).await)
+    }
+
+    pub async fn apply_batch(&self, batch: Batch) -> DbResult<()> {
+        dispatch!(self, tree => tree.apply_batch(batch).await)
+    }
+
+    pub async fn iter(&self) -> DbIter<'_> {
+        dispatch!(self, tree => Box::new(tree.iter().await))
+    }
+
+    pub async fn scan_prefix<'a>(&'a self, prefix: &[u8]) -> DbIter<'a> {
+        dispatch!(self, tree => Box::new(tree.scan_prefix(prefix).await))
+    }
+
+    pub async fn range<'a>(&'a self, range: RangeInclusive<&[u8]>) -> DbRangeIter<'a> {
+        dispatch!(self, tree => Box::new(tree.range(range).await))
+    }
+
+    pub async fn verify_integrity(&self) -> DbResult<()> {
+        dispatch!(self, tree => tree.verify_integrity().await)
+    }
+}
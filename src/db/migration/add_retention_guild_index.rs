@@ -0,0 +1,49 @@
+use super::*;
+
+use db::{
+    chat::{
+        make_chan_retention_key, make_guild_chan_ordering_key, make_guild_retention_key,
+        make_retention_guild_key,
+    },
+    Batch,
+};
+
+pub(super) fn migrate(db: &Db) -> BoxFuture<'_, DbResult<()>> {
+    let fut = async move {
+        let chat_tree = db.open_tree(b"chat").await?;
+
+        // only guild keys are exactly one u64 long
+        let mut guild_ids = Vec::new();
+        for res in chat_tree.iter().await {
+            let (key, _) = res?;
+            if key.len() == 8 {
+                guild_ids.push(db::deser_id(key));
+            }
+        }
+
+        let mut batch = Batch::default();
+        for guild_id in guild_ids {
+            let mut has_policy = chat_tree
+                .contains_key(&make_guild_retention_key(guild_id))
+                .await?;
+            if let Some(raw) = chat_tree
+                .get(&make_guild_chan_ordering_key(guild_id))
+                .await?
+            {
+                for channel_id in raw.chunks_exact(8).map(db::deser_id) {
+                    has_policy |= chat_tree
+                        .contains_key(&make_chan_retention_key(guild_id, channel_id))
+                        .await?;
+                }
+            }
+            if has_policy {
+                batch.insert(make_retention_guild_key(guild_id), []);
+            }
+        }
+        chat_tree.apply_batch(batch).await?;
+
+        Ok(())
+    };
+
+    Box::pin(fut)
+}
@@ -0,0 +1,68 @@
+use super::*;
+
+use db::{
+    auth::{make_session_key, SessionInfo, AUTH_PREFIX},
+    rkyv_ser, Batch,
+};
+
+use crate::utils::{gen_rand_u64, get_time_secs};
+
+const TOKEN_PREFIX: &[u8] = b"token_";
+const ATIME_PREFIX: &[u8] = b"atime_";
+const USER_KEY_LEN: usize = TOKEN_PREFIX.len() + 8;
+
+pub(super) fn migrate(db: &Db) -> BoxFuture<'_, DbResult<()>> {
+    let fut = async move {
+        let auth_tree = db.open_tree(b"auth").await?;
+        let mut batch = Batch::default();
+
+        // remove the old per user token and atime keys, keeping the atimes around
+        // so we can use them as the last seen time of the converted sessions
+        let mut atimes = Vec::new();
+        for prefix in [TOKEN_PREFIX, ATIME_PREFIX] {
+            for res in auth_tree.scan_prefix(prefix).await {
+                let (key, val) = res?;
+                if key.len() == USER_KEY_LEN {
+                    if prefix == ATIME_PREFIX {
+                        atimes.push((db::deser_id(&key[prefix.len()..]), db::deser_id(val)));
+                    }
+                    batch.remove(key);
+                }
+            }
+        }
+
+        // every valid auth token becomes its own session
+        let now = get_time_secs();
+        for res in auth_tree.scan_prefix(AUTH_PREFIX).await {
+            let (key, val) = res?;
+            if val.len() != 8 {
+                continue;
+            }
+            let user_id = db::deser_id(&val);
+            let session_id = gen_rand_u64();
+            let last_seen = atimes
+                .iter()
+                .find_map(|(id, atime)| (*id == user_id).then(|| *atime))
+                .unwrap_or(now);
+            let session = SessionInfo {
+                token: String::from_utf8_lossy(&key[AUTH_PREFIX.len()..]).into_owned(),
+                device_name: String::new(),
+                ip: None,
+                created_at: last_seen,
+                last_seen,
+                issued_at: last_seen,
+                refresh_token: None,
+                refresh_expires_at: 0,
+            };
+            batch.insert(make_session_key(user_id, session_id), rkyv_ser(&session));
+            batch.insert(
+                key,
+                [user_id.to_be_bytes(), session_id.to_be_bytes()].concat(),
+            );
+        }
+
+        auth_tree.apply_batch(batch).await
+    };
+
+    Box::pin(fut)
+}
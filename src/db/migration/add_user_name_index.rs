@@ -0,0 +1,41 @@
+use super::*;
+
+use db::{
+    profile::{
+        make_local_to_foreign_user_key, make_user_name_key, make_user_profile_key, USER_PREFIX,
+    },
+    rkyv_arch, Batch,
+};
+
+use crate::api::profile::Profile;
+
+pub(super) fn migrate(db: &Db) -> BoxFuture<'_, DbResult<()>> {
+    let fut = async move {
+        let profile_tree = db.open_tree(b"profile").await?;
+        let mut batch = Batch::default();
+        for res in profile_tree.scan_prefix(USER_PREFIX).await {
+            let (key, val) = res?;
+            if key.len() != make_user_profile_key(0).len() {
+                continue;
+            }
+            let user_id = db::deser_id(&key[USER_PREFIX.len()..]);
+            let raw_foreign = profile_tree
+                .get(&make_local_to_foreign_user_key(user_id))
+                .await?;
+            let host = raw_foreign
+                .as_ref()
+                .and_then(|raw| raw.get(8..))
+                .and_then(|raw_host| std::str::from_utf8(raw_host).ok())
+                .unwrap_or_default();
+            let profile = rkyv_arch::<Profile>(&val);
+            batch.insert(
+                make_user_name_key(profile.user_name.as_str(), host),
+                user_id.to_be_bytes(),
+            );
+        }
+        profile_tree.apply_batch(batch).await?;
+        Ok(())
+    };
+
+    Box::pin(fut)
+}
@@ -9,18 +9,24 @@ use super::{Db, DbResult};
 
 mod add_account_kind;
 mod add_next_msg_ids;
+mod add_retention_guild_index;
+mod add_sessions;
+mod add_user_name_index;
 mod initial_db_version;
 mod remove_log_chan_id_from_admin_keys;
 mod timestamps_are_milliseconds;
 
 type Migration = for<'a> fn(&'a Db) -> BoxFuture<'a, DbResult<()>>;
 
-pub const MIGRATIONS: [Migration; 5] = [
+pub const MIGRATIONS: [Migration; 8] = [
     initial_db_version::migrate,
     add_next_msg_ids::migrate,
     remove_log_chan_id_from_admin_keys::migrate,
     add_account_kind::migrate,
     timestamps_are_milliseconds::migrate,
+    add_sessions::migrate,
+    add_user_name_index::migrate,
+    add_retention_guild_index::migrate,
 ];
 
 pub async fn get_db_version(db: &Db) -> DbResult<(usize, bool)> {
@@ -46,16 +46,16 @@ use rkyv::{
 };
 use tracing::Instrument;
 
+pub mod dispatch;
 pub mod migration;
+#[cfg(feature = "postgres")]
+pub mod postgres;
 #[cfg(feature = "sled")]
 pub mod sled;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 
-#[cfg(feature = "sled")]
-pub use self::sled::shared::*;
-#[cfg(all(feature = "sqlite", not(feature = "sled")))]
-pub use self::sqlite::shared::*;
+pub use self::dispatch::*;
 
 pub const TREES: [&[u8]; 6] = [b"auth", b"chat", b"sync", b"version", b"profile", b"emote"];
 
@@ -69,7 +69,7 @@ pub async fn open_db(db_path: String, db_config: DbConfig) -> Db {
         match db_result {
             Ok(db) => db,
             Err(err) => {
-                tracing::error!("cannot open database: {}; aborting", err);
+                tracing::error!("cannot open database: {}; aborting", err.inner);
 
                 std::process::exit(1);
             }
@@ -113,6 +113,15 @@ impl StdError for DbError {
 
 pub type DbResult<T> = Result<T, DbError>;
 
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+impl From<sqlx::Error> for DbError {
+    fn from(err: sqlx::Error) -> Self {
+        DbError {
+            inner: Box::new(err),
+        }
+    }
+}
+
 pub fn make_u64_iter_logic(raw: &[u8]) -> impl Iterator<Item = u64> + '_ {
     raw.chunks_exact(size_of::<u64>()).map(deser_id)
 }
@@ -122,6 +131,9 @@ pub mod profile {
 
     pub const USER_PREFIX: &[u8] = b"user_";
     pub const FOREIGN_PREFIX: &[u8] = b"fuser_";
+    pub const OIDC_USER_PREFIX: &[u8] = b"oidcuser_";
+    pub const BOT_PREFIX: &[u8] = b"bot_";
+    pub const USER_NAME_PREFIX: &[u8] = b"username_";
 
     pub const fn make_local_to_foreign_user_key(local_id: u64) -> [u8; 15] {
         concat_static(&[FOREIGN_PREFIX, &local_id.to_be_bytes(), &[2]])
@@ -137,6 +149,53 @@ pub mod profile {
         .concat()
     }
 
+    /// The value is the local user ID.
+    pub fn make_oidc_to_local_user_key(issuer: &str, sub: &str) -> Vec<u8> {
+        [
+            OIDC_USER_PREFIX,
+            &[1],
+            issuer.as_bytes(),
+            &[0],
+            sub.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// The value is the key made by [`make_oidc_to_local_user_key`].
+    pub const fn make_local_to_oidc_user_key(local_id: u64) -> [u8; 18] {
+        concat_static(&[OIDC_USER_PREFIX, &[2], &local_id.to_be_bytes()])
+    }
+
+    /// The value is the ID of the user that owns the bot.
+    pub const fn make_bot_owner_key(bot_id: u64) -> [u8; 13] {
+        concat_static(&[BOT_PREFIX, &[1], &bot_id.to_be_bytes()])
+    }
+
+    pub const fn make_user_bots_prefix(owner_id: u64) -> [u8; 13] {
+        concat_static(&[BOT_PREFIX, &[2], &owner_id.to_be_bytes()])
+    }
+
+    pub const fn make_user_bot_key(owner_id: u64, bot_id: u64) -> [u8; 21] {
+        concat_static(&[
+            BOT_PREFIX,
+            &[2],
+            &owner_id.to_be_bytes(),
+            &bot_id.to_be_bytes(),
+        ])
+    }
+
+    /// The value is the local ID of the user. `host` is empty for local users.
+    pub fn make_user_name_key(user_name: &str, host: &str) -> Vec<u8> {
+        [
+            USER_NAME_PREFIX,
+            host.as_bytes(),
+            // hosts can't contain a null byte, so we use it as a separator
+            &[0],
+            user_name.as_bytes(),
+        ]
+        .concat()
+    }
+
     pub const fn make_user_profile_key(user_id: u64) -> [u8; 13] {
         concat_static(&[USER_PREFIX, &user_id.to_be_bytes()])
     }
@@ -182,10 +241,19 @@ pub mod emote {
 }
 
 pub mod chat {
+    use rkyv::{Archive, Deserialize, Serialize};
+
     use super::concat_static;
 
     pub const INVITE_PREFIX: &[u8] = b"invite_";
+    pub const USER_INVITE_PREFIX: &[u8] = b"user_invite_";
     pub const ADMIN_GUILD_KEY: &[u8] = b"admin_guild_key_data";
+    pub const BAN_EXPIRY_PREFIX: &[u8] = b"ban_expiry_";
+    pub const TIMEOUT_EXPIRY_PREFIX: &[u8] = b"timeout_expiry_";
+    pub const RETENTION_GUILD_PREFIX: &[u8] = b"retention_guild_";
+    // these can't start with `INVITE_PREFIX`, since invites are listed by scanning it
+    pub const INVITE_EXPIRES_PREFIX: &[u8] = b"inv_expires_";
+    pub const INVITE_EXPIRY_PREFIX: &[u8] = b"inv_expiry_";
 
     // perms
 
@@ -237,6 +305,32 @@ pub mod chat {
         concat_static(&[&make_chan_key(guild_id, channel_id), &[6]])
     }
 
+    pub const fn make_chan_retention_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[5]])
+    }
+
+    /// The value is the slow mode interval of the channel in seconds.
+    pub const fn make_chan_slow_mode_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[4]])
+    }
+
+    pub const fn make_chan_slow_mode_users_prefix(guild_id: u64, channel_id: u64) -> [u8; 18] {
+        concat_static(&[&make_chan_key(guild_id, channel_id), &[3]])
+    }
+
+    /// The value is when the user last sent a message in the channel while
+    /// it was in slow mode.
+    pub const fn make_chan_slow_mode_user_key(
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+    ) -> [u8; 26] {
+        concat_static(&[
+            &make_chan_slow_mode_users_prefix(guild_id, channel_id),
+            &user_id.to_be_bytes(),
+        ])
+    }
+
     pub const fn make_next_msg_id_key(guild_id: u64, channel_id: u64) -> [u8; 18] {
         concat_static(&[&make_chan_key(guild_id, channel_id), &[7]])
     }
@@ -288,6 +382,8 @@ pub mod chat {
         concat_static(&[&make_guild_mem_prefix(guild_id), &user_id.to_be_bytes()])
     }
 
+    /// The value is when the user was banned, followed by when the ban
+    /// expires if it's temporary.
     pub const fn make_banned_member_key(guild_id: u64, user_id: u64) -> [u8; 17] {
         concat_static(&[
             &make_guild_banned_mem_prefix(guild_id),
@@ -331,6 +427,149 @@ pub mod chat {
         concat_static(&[&guild_id.to_be_bytes(), &[1, 1]])
     }
 
+    pub const fn make_guild_retention_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 5]])
+    }
+
+    /// Marks a guild as having a retention policy set on it or one of its
+    /// channels. Entries can outlive the policies, and are removed when
+    /// messages are pruned.
+    pub const fn make_retention_guild_key(guild_id: u64) -> [u8; 24] {
+        concat_static(&[RETENTION_GUILD_PREFIX, &guild_id.to_be_bytes()])
+    }
+
+    // event log
+
+    pub const fn make_event_log_prefix(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 7]])
+    }
+
+    pub const fn make_event_log_key(guild_id: u64, sequence: u64) -> [u8; 18] {
+        concat_static(&[&make_event_log_prefix(guild_id), &sequence.to_be_bytes()])
+    }
+
+    pub const fn make_event_log_seq_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 8]])
+    }
+
+    // event log
+
+    // audit log
+
+    pub const fn make_audit_log_prefix(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 10]])
+    }
+
+    pub const fn make_audit_log_key(guild_id: u64, entry_id: u64) -> [u8; 18] {
+        concat_static(&[&make_audit_log_prefix(guild_id), &entry_id.to_be_bytes()])
+    }
+
+    pub const fn make_audit_log_seq_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 11]])
+    }
+
+    /// The value is the ID of the channel new audit log entries are posted to.
+    pub const fn make_audit_log_channel_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 12]])
+    }
+
+    // audit log
+
+    // timeouts
+
+    pub const fn make_guild_timeout_prefix(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 13]])
+    }
+
+    /// The value is when the timeout expires.
+    pub const fn make_timeout_key(guild_id: u64, user_id: u64) -> [u8; 18] {
+        concat_static(&[&make_guild_timeout_prefix(guild_id), &user_id.to_be_bytes()])
+    }
+
+    // timeouts
+
+    // automod
+
+    /// The value is the automod rules of the guild.
+    pub const fn make_automod_rules_key(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 14]])
+    }
+
+    /// The value is a hash of the last message the user sent in the guild,
+    /// when they sent it and how many times in a row they sent it.
+    pub const fn make_automod_repeats_key(guild_id: u64, user_id: u64) -> [u8; 18] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 15], &user_id.to_be_bytes()])
+    }
+
+    // automod
+
+    // expiry
+
+    /// Invites sorted by when they expire.
+    pub fn make_invite_expiry_key(expires_at: u64, name: &str) -> Vec<u8> {
+        [
+            INVITE_EXPIRY_PREFIX,
+            expires_at.to_be_bytes().as_ref(),
+            name.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Temporary bans sorted by when they expire, so that expired ones can be
+    /// found without going through every guild.
+    pub const fn make_ban_expiry_key(expires_at: u64, guild_id: u64, user_id: u64) -> [u8; 35] {
+        concat_static(&[
+            BAN_EXPIRY_PREFIX,
+            &expires_at.to_be_bytes(),
+            &guild_id.to_be_bytes(),
+            &user_id.to_be_bytes(),
+        ])
+    }
+
+    /// Timeouts sorted by when they expire.
+    pub const fn make_timeout_expiry_key(expires_at: u64, guild_id: u64, user_id: u64) -> [u8; 39] {
+        concat_static(&[
+            TIMEOUT_EXPIRY_PREFIX,
+            &expires_at.to_be_bytes(),
+            &guild_id.to_be_bytes(),
+            &user_id.to_be_bytes(),
+        ])
+    }
+
+    // expiry
+
+    // search
+
+    pub const fn make_search_index_prefix(guild_id: u64) -> [u8; 10] {
+        concat_static(&[&guild_id.to_be_bytes(), &[1, 6]])
+    }
+
+    pub fn make_search_term_prefix(guild_id: u64, term: &str) -> Vec<u8> {
+        [
+            make_search_index_prefix(guild_id).as_ref(),
+            term.as_bytes(),
+            // terms are alphanumeric, so they can't contain a null byte
+            &[0],
+        ]
+        .concat()
+    }
+
+    pub fn make_search_index_key(
+        guild_id: u64,
+        term: &str,
+        channel_id: u64,
+        message_id: u64,
+    ) -> Vec<u8> {
+        [
+            make_search_term_prefix(guild_id, term).as_ref(),
+            channel_id.to_be_bytes().as_ref(),
+            message_id.to_be_bytes().as_ref(),
+        ]
+        .concat()
+    }
+
+    // search
+
     pub fn make_guild_list_key(user_id: u64, guild_id: u64, host: &str) -> Vec<u8> {
         [
             make_guild_list_key_prefix(user_id).as_ref(),
@@ -349,26 +588,376 @@ pub mod chat {
     pub fn make_invite_key(name: &str) -> Vec<u8> {
         [INVITE_PREFIX, name.as_bytes()].concat()
     }
+
+    pub fn make_user_invite_key(name: &str) -> Vec<u8> {
+        [USER_INVITE_PREFIX, name.as_bytes()].concat()
+    }
+
+    /// The value is when the invite expires.
+    pub fn make_invite_expires_key(name: &str) -> Vec<u8> {
+        [INVITE_EXPIRES_PREFIX, name.as_bytes()].concat()
+    }
+
+    /// The name is prefixed with its length, so that the uses of an invite
+    /// don't show up when scanning the uses of another one it's a prefix of.
+    pub fn make_invite_uses_prefix(guild_id: u64, name: &str) -> Vec<u8> {
+        [
+            guild_id.to_be_bytes().as_ref(),
+            &[1, 16],
+            (name.len() as u64).to_be_bytes().as_ref(),
+            name.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// The value is when the user joined through the invite.
+    pub fn make_invite_use_key(guild_id: u64, name: &str, user_id: u64) -> Vec<u8> {
+        [
+            make_invite_uses_prefix(guild_id, name).as_ref(),
+            user_id.to_be_bytes().as_ref(),
+        ]
+        .concat()
+    }
+
+    // pending invites
+
+    pub const fn make_pending_invite_prefix(user_id: u64) -> [u8; 10] {
+        concat_static(&[&user_id.to_be_bytes(), &[1, 4]])
+    }
+
+    pub fn make_pending_invite_key(user_id: u64, server_id: &str, invite_id: &str) -> Vec<u8> {
+        [
+            make_pending_invite_prefix(user_id).as_ref(),
+            server_id.as_bytes(),
+            // hosts can't contain a null byte, so we use it as a separator
+            &[0],
+            invite_id.as_bytes(),
+        ]
+        .concat()
+    }
+
+    // pending invites
+
+    // guests
+
+    /// Marks a user as a guest, which limits their permissions in guilds.
+    pub const fn make_guest_key(user_id: u64) -> [u8; 10] {
+        concat_static(&[&user_id.to_be_bytes(), &[1, 9]])
+    }
+
+    // guests
+
+    /// A moderation action done in a guild.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    pub enum AuditAction {
+        Ban,
+        Kick,
+        Unban,
+        UserRolesUpdated,
+        RoleModified,
+        PermissionsSet,
+        ChannelDeleted,
+        MessageDeleted,
+        Timeout,
+        TimeoutRemoved,
+    }
+
+    impl AuditAction {
+        pub const fn as_str(&self) -> &'static str {
+            match self {
+                AuditAction::Ban => "ban",
+                AuditAction::Kick => "kick",
+                AuditAction::Unban => "unban",
+                AuditAction::UserRolesUpdated => "user-roles-updated",
+                AuditAction::RoleModified => "role-modified",
+                AuditAction::PermissionsSet => "permissions-set",
+                AuditAction::ChannelDeleted => "channel-deleted",
+                AuditAction::MessageDeleted => "message-deleted",
+                AuditAction::Timeout => "timeout",
+                AuditAction::TimeoutRemoved => "timeout-removed",
+            }
+        }
+    }
+
+    /// An entry in the audit log of a guild.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    pub struct AuditLogEntry {
+        /// User that did the action
+        pub actor_id: u64,
+        pub action: AuditAction,
+        /// User, role or channel the action was done to, depending on the action.
+        /// For deleted messages, this is the author of the message
+        pub target_id: u64,
+        /// Channel the action was done in, if any
+        pub channel_id: Option<u64>,
+        pub reason: Option<String>,
+        /// In seconds since unix epoch
+        pub created_at: u64,
+    }
+
+    pub fn deser_audit_log_entry(data: impl AsRef<[u8]>) -> AuditLogEntry {
+        super::rkyv_arch::<AuditLogEntry>(data.as_ref())
+            .deserialize(&mut rkyv::Infallible)
+            .expect("failed to deserialize audit log entry")
+    }
+
+    /// What an automod rule looks for in messages.
+    #[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(tag = "kind", rename_all = "kebab-case")]
+    pub enum AutomodTrigger {
+        /// Any of the words, ignoring case
+        BannedWords { words: Vec<String> },
+        /// Text matching the regex
+        Regex { pattern: String },
+        /// Links to hosts other than the allowed ones
+        Links { allowed_hosts: Vec<String> },
+        /// Guild invite links
+        Invites,
+        /// More user and role mentions than the limit
+        MentionSpam { max_mentions: u32 },
+        /// The same message sent more times in a row than the limit
+        Duplicates { max_repeats: u32 },
+    }
+
+    /// What automod does with a message that breaks a rule.
+    #[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(tag = "kind", rename_all = "kebab-case")]
+    pub enum AutomodAction {
+        /// Rejects the message
+        Block,
+        /// Rejects the message, deletes it if it was being edited and posts a
+        /// warning in its channel
+        DeleteAndWarn,
+        /// Rejects the message and times out its author, for `duration` seconds
+        Timeout { duration: u64 },
+        /// Lets the message through, but posts about it in a channel
+        LogToChannel { channel_id: u64 },
+    }
+
+    /// A content rule set by the admins of a guild.
+    #[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    pub struct AutomodRule {
+        pub name: String,
+        pub trigger: AutomodTrigger,
+        pub action: AutomodAction,
+    }
+
+    pub fn deser_automod_rules(data: impl AsRef<[u8]>) -> Vec<AutomodRule> {
+        super::rkyv_arch::<Vec<AutomodRule>>(data.as_ref())
+            .deserialize(&mut rkyv::Infallible)
+            .expect("failed to deserialize automod rules")
+    }
 }
 
 pub mod auth {
+    use rkyv::{Archive, Deserialize, Serialize};
+
     use super::concat_static;
 
-    pub const ATIME_PREFIX: &[u8] = b"atime_";
-    pub const TOKEN_PREFIX: &[u8] = b"token_";
     pub const AUTH_PREFIX: &[u8] = b"auth_";
     pub const SU_TOKEN_PREFIX: &[u8] = b"reg_token_";
+    pub const SESSION_PREFIX: &[u8] = b"session_";
+    pub const TOTP_PREFIX: &[u8] = b"totp_";
+    pub const REFRESH_PREFIX: &[u8] = b"refresh_";
+    pub const AUTH_FLOW_PREFIX: &[u8] = b"flow_";
+    pub const LOGIN_FAILURES_PREFIX: &[u8] = b"login_fail_";
+    pub const IP_LOGIN_FAILURES_PREFIX: &[u8] = b"ip_login_fail_";
+    pub const OIDC_STATE_PREFIX: &[u8] = b"oidc_state_";
+    pub const OIDC_VERIFIER_PREFIX: &[u8] = b"oidc_verifier_";
+    pub const LDAP_USER_PREFIX: &[u8] = b"ldap_user_";
+    pub const REG_TOKEN_PREFIX: &[u8] = b"regtoken_";
+    pub const REG_TOKEN_HASH_PREFIX: &[u8] = b"regtoken-hash_";
+    pub const GUEST_PREFIX: &[u8] = b"guest_";
+    pub const GUEST_TOKEN_PREFIX: &[u8] = b"guest-token_";
+    pub const STREAM_RESUME_PREFIX: &[u8] = b"stream_resume_";
+
+    /// Information about a logged in session of a user.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    pub struct SessionInfo {
+        /// Empty if the token expired, and the session is waiting to be
+        /// refreshed with its refresh token
+        pub token: String,
+        /// Taken from the `User-Agent` header of the request that logged in
+        pub device_name: String,
+        pub ip: Option<String>,
+        /// In seconds since unix epoch
+        pub created_at: u64,
+        /// In seconds since unix epoch
+        pub last_seen: u64,
+        /// When the current token was issued, in seconds since unix epoch
+        pub issued_at: u64,
+        /// Hashed refresh token
+        pub refresh_token: Option<String>,
+        /// In seconds since unix epoch
+        pub refresh_expires_at: u64,
+    }
+
+    /// TOTP second factor settings of a user.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    pub struct TotpInfo {
+        pub secret: Vec<u8>,
+        /// Whether the user confirmed enrollment with a code. Unconfirmed
+        /// secrets aren't asked for when logging in.
+        pub confirmed: bool,
+        /// The last time step a code was accepted for, so codes can't be reused
+        pub last_used_step: u64,
+        /// Hashed one-time recovery codes
+        pub recovery_codes: Vec<String>,
+    }
+
+    /// Failed logins of an account or an IP.
+    #[derive(Debug, Default, Clone, Copy, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    pub struct LoginFailures {
+        /// Failures since the last lockout
+        pub count: u32,
+        /// In seconds since unix epoch
+        pub last_failure: u64,
+        /// In seconds since unix epoch, 0 if not locked
+        pub locked_until: u64,
+    }
+
+    /// A registration token that admins created for letting users register
+    /// while registration is disabled.
+    #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes))]
+    pub struct RegistrationToken {
+        pub token_hashed: String,
+        /// User that created the token, `None` if it was created with `scherzo_cmd`
+        pub creator_id: Option<u64>,
+        /// In seconds since unix epoch
+        pub created_at: u64,
+        /// In seconds since unix epoch, `None` if it never expires
+        pub expires_at: Option<u64>,
+        /// `None` if it can be used any number of times
+        pub max_uses: Option<u32>,
+        pub uses: u32,
+        /// Guilds that users who register with the token join
+        pub guild_ids: Vec<u64>,
+    }
 
+    impl RegistrationToken {
+        pub fn is_usable(&self, now: u64) -> bool {
+            self.expires_at.map_or(true, |expires_at| expires_at > now)
+                && self.max_uses.map_or(true, |max_uses| self.uses < max_uses)
+        }
+    }
+
+    pub fn deser_registration_token(data: impl AsRef<[u8]>) -> RegistrationToken {
+        super::rkyv_arch::<RegistrationToken>(data.as_ref())
+            .deserialize(&mut rkyv::Infallible)
+            .expect("failed to deserialize registration token")
+    }
+
+    pub fn deser_login_failures(data: impl AsRef<[u8]>) -> LoginFailures {
+        super::rkyv_arch::<LoginFailures>(data.as_ref())
+            .deserialize(&mut rkyv::Infallible)
+            .expect("failed to deserialize login failures")
+    }
+
+    pub fn deser_totp(data: impl AsRef<[u8]>) -> TotpInfo {
+        super::rkyv_arch::<TotpInfo>(data.as_ref())
+            .deserialize(&mut rkyv::Infallible)
+            .expect("failed to deserialize totp info")
+    }
+
+    pub fn deser_session(data: impl AsRef<[u8]>) -> SessionInfo {
+        super::rkyv_arch::<SessionInfo>(data.as_ref())
+            .deserialize(&mut rkyv::Infallible)
+            .expect("failed to deserialize session info")
+    }
+
+    /// The value of an auth key is the user ID followed by the session ID.
     pub fn auth_key(token: &str) -> Vec<u8> {
         [AUTH_PREFIX, token.as_bytes()].concat()
     }
 
-    pub const fn token_key(user_id: u64) -> [u8; 14] {
-        concat_static(&[TOKEN_PREFIX, &user_id.to_be_bytes()])
+    pub const fn make_sessions_prefix(user_id: u64) -> [u8; 16] {
+        concat_static(&[SESSION_PREFIX, &user_id.to_be_bytes()])
+    }
+
+    pub const fn make_session_key(user_id: u64, session_id: u64) -> [u8; 24] {
+        concat_static(&[
+            SESSION_PREFIX,
+            &user_id.to_be_bytes(),
+            &session_id.to_be_bytes(),
+        ])
+    }
+
+    /// The value is a list of guild ID and event sequence pairs, the last
+    /// events the latest event stream of the session went through.
+    pub const fn make_stream_resume_key(user_id: u64, session_id: u64) -> [u8; 30] {
+        concat_static(&[
+            STREAM_RESUME_PREFIX,
+            &user_id.to_be_bytes(),
+            &session_id.to_be_bytes(),
+        ])
+    }
+
+    pub const fn make_totp_key(user_id: u64) -> [u8; 13] {
+        concat_static(&[TOTP_PREFIX, &user_id.to_be_bytes()])
+    }
+
+    /// The value is the expiry time followed by the auth ID the state is for.
+    pub fn oidc_state_key(state: &str) -> Vec<u8> {
+        [OIDC_STATE_PREFIX, state.as_bytes()].concat()
+    }
+
+    /// The value is the expiry time followed by the PKCE code verifier of the auth flow.
+    pub fn oidc_verifier_key(auth_id: &str) -> Vec<u8> {
+        [OIDC_VERIFIER_PREFIX, auth_id.as_bytes()].concat()
+    }
+
+    /// The value is the DN of the directory user that a local user was created for.
+    pub const fn make_ldap_user_key(user_id: u64) -> [u8; 18] {
+        concat_static(&[LDAP_USER_PREFIX, &user_id.to_be_bytes()])
+    }
+
+    pub const fn make_login_failures_key(user_id: u64) -> [u8; 19] {
+        concat_static(&[LOGIN_FAILURES_PREFIX, &user_id.to_be_bytes()])
+    }
+
+    pub fn ip_login_failures_key(ip: &str) -> Vec<u8> {
+        [IP_LOGIN_FAILURES_PREFIX, ip.as_bytes()].concat()
+    }
+
+    /// The value of a refresh token key is the user ID followed by the session ID.
+    pub fn refresh_token_key(token_hashed: &[u8]) -> Vec<u8> {
+        [REFRESH_PREFIX, token_hashed].concat()
+    }
+
+    pub fn auth_flow_key(auth_id: &str) -> Vec<u8> {
+        [AUTH_FLOW_PREFIX, auth_id.as_bytes()].concat()
+    }
+
+    pub const fn make_registration_token_key(token_id: u64) -> [u8; 17] {
+        concat_static(&[REG_TOKEN_PREFIX, &token_id.to_be_bytes()])
+    }
+
+    /// The value is the ID of the registration token.
+    pub fn registration_token_hash_key(token_hashed: &[u8]) -> Vec<u8> {
+        [REG_TOKEN_HASH_PREFIX, token_hashed].concat()
+    }
+
+    /// The value is the time the guest account expires at, followed by its
+    /// hashed guest token.
+    pub const fn make_guest_key(user_id: u64) -> [u8; 14] {
+        concat_static(&[GUEST_PREFIX, &user_id.to_be_bytes()])
     }
 
-    pub const fn atime_key(user_id: u64) -> [u8; 14] {
-        concat_static(&[ATIME_PREFIX, &user_id.to_be_bytes()])
+    /// The value is the ID of the guest user.
+    pub fn guest_token_key(token_hashed: &[u8]) -> Vec<u8> {
+        [GUEST_TOKEN_PREFIX, token_hashed].concat()
     }
 
     pub fn single_use_token_key(token_hashed: &[u8]) -> Vec<u8> {
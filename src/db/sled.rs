@@ -136,6 +136,20 @@ pub mod shared {
             ready(self.inner.contains_key(key).map_err(Into::into))
         }
 
+        pub fn compare_and_swap(
+            &self,
+            key: &[u8],
+            old: Option<&[u8]>,
+            new: Option<&[u8]>,
+        ) -> SledFut<bool> {
+            ready(
+                self.inner
+                    .compare_and_swap(key, old, new)
+                    .map_err(Into::into)
+                    .map(|res| res.is_ok()),
+            )
+        }
+
         pub fn range<'a>(
             &'a self,
             range: RangeInclusive<&[u8]>,
@@ -69,6 +69,18 @@ pub mod shared {
                 )
                 .into(),
                 iter_query: format!("SELECT key, value FROM {} ORDER BY key ASC", name).into(),
+                cas_insert_query: format!(
+                    "INSERT OR IGNORE INTO {} (key, value) VALUES (?, ?)",
+                    name
+                )
+                .into(),
+                cas_update_query: format!(
+                    "UPDATE {} SET value = ? WHERE key = ? AND value = ?",
+                    name
+                )
+                .into(),
+                cas_remove_query: format!("DELETE FROM {} WHERE key = ? AND value = ?", name)
+                    .into(),
             })
         }
 
@@ -86,6 +98,9 @@ pub mod shared {
         contains_key_query: SmolStr,
         iter_from_query: SmolStr,
         iter_query: SmolStr,
+        cas_insert_query: SmolStr,
+        cas_update_query: SmolStr,
+        cas_remove_query: SmolStr,
     }
 
     impl Tree {
@@ -134,6 +149,36 @@ pub mod shared {
             Ok(row.get(0))
         }
 
+        /// Sets `key` to `new` if its current value is `old`, where `None` means
+        /// the key doesn't exist. Returns whether the swap happened.
+        pub async fn compare_and_swap(
+            &self,
+            key: &[u8],
+            old: Option<&[u8]>,
+            new: Option<&[u8]>,
+        ) -> DbResult<bool> {
+            let query = match (old, new) {
+                (None, None) => return Ok(!self.contains_key(key).await?),
+                (None, Some(new)) => sqlx::query(self.cas_insert_query.as_str())
+                    .bind(key)
+                    .bind(new),
+                (Some(old), Some(new)) => sqlx::query(self.cas_update_query.as_str())
+                    .bind(new)
+                    .bind(key)
+                    .bind(old),
+                (Some(old), None) => sqlx::query(self.cas_remove_query.as_str())
+                    .bind(key)
+                    .bind(old),
+            };
+
+            let mut conn = self.pool.acquire().await?;
+
+            // each query only touches the row if it still has the old value
+            let res = query.execute(&mut conn).await?;
+
+            Ok(res.rows_affected() == 1)
+        }
+
         pub async fn apply_batch(&self, batch: Batch) -> DbResult<()> {
             let mut txn = self.pool.begin().await?;
 
@@ -256,11 +301,3 @@ pub mod shared {
         }
     }
 }
-
-impl From<sqlx::Error> for DbError {
-    fn from(err: sqlx::Error) -> Self {
-        DbError {
-            inner: Box::new(err),
-        }
-    }
-}
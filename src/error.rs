@@ -74,6 +74,7 @@ pub enum ServerError {
     UnderSpecifiedChannels,
     NoSuchInvite(SmolStr),
     NoSuchUser(u64),
+    NoSuchSession(u64),
     InternalServerError,
     SessionExpired,
     NotEnoughPermissions {
@@ -128,6 +129,40 @@ pub enum ServerError {
     InvalidEmailConfig(toml::de::Error),
     FailedToFetchLink(reqwest::Error),
     FailedToDownload(reqwest::Error),
+    EmptySearchQuery,
+    InvalidJsonBody(serde_json::Error),
+    InvalidResumePoints,
+    TotpAlreadyEnabled,
+    TotpNotEnrolled,
+    WrongTotpCode,
+    InvalidRefreshToken,
+    AccountLocked {
+        /// In seconds since unix epoch
+        until: u64,
+    },
+    OidcLoginNotFinished,
+    OidcProviderError(String),
+    LdapError(String),
+    BotsCantOwnBots,
+    TooManyBots(u32),
+    InvalidUsername,
+    GuestsNotAllowed,
+    EventGapTooLarge {
+        guild_id: u64,
+        since: u64,
+    },
+    InvalidDuration,
+    TimedOut {
+        guild_id: u64,
+        /// In seconds since unix epoch
+        until: u64,
+    },
+    InvalidAutomodRule(String),
+    BlockedByAutomod {
+        rule: String,
+    },
+    /// Max length of the body in bytes
+    BodyTooLarge(usize),
 }
 
 impl StdError for ServerError {
@@ -144,6 +179,7 @@ impl StdError for ServerError {
             ServerError::FailedToFetchLink(err) => Some(err),
             ServerError::FailedToDownload(err) => Some(err),
             ServerError::InvalidEmailConfig(err) => Some(err),
+            ServerError::InvalidJsonBody(err) => Some(err),
             _ => None,
         }
     }
@@ -228,6 +264,7 @@ impl Display for ServerError {
                 message_id, channel_id, guild_id
             ),
             ServerError::NoSuchInvite(id) => write!(f, "no such invite with id {}", id),
+            ServerError::NoSuchSession(id) => write!(f, "no such session with id {}", id),
             ServerError::InternalServerError => f.write_str("internal server error"),
             ServerError::SessionExpired => f.write_str("session expired"),
             ServerError::NotEnoughPermissions {
@@ -304,6 +341,59 @@ impl Display for ServerError {
             }
             ServerError::FailedToFetchLink(_) => f.write_str("failed to fetch link"),
             ServerError::FailedToDownload(_) => f.write_str("failed to download"),
+            ServerError::EmptySearchQuery => f.write_str("search query can't be empty"),
+            ServerError::InvalidJsonBody(err) => write!(f, "invalid JSON body: {}", err),
+            ServerError::InvalidResumePoints => {
+                f.write_str("invalid resume points, must be a list of `guild_id:sequence` pairs")
+            }
+            ServerError::TotpAlreadyEnabled => {
+                f.write_str("two-factor authentication is already enabled")
+            }
+            ServerError::TotpNotEnrolled => {
+                f.write_str("two-factor authentication enrollment wasn't started")
+            }
+            ServerError::WrongTotpCode => f.write_str("wrong two-factor authentication code"),
+            ServerError::InvalidRefreshToken => {
+                f.write_str("refresh token is invalid or has expired")
+            }
+            ServerError::AccountLocked { until } => write!(
+                f,
+                "account is locked because of too many failed logins, try again after {}",
+                until
+            ),
+            ServerError::OidcLoginNotFinished => {
+                f.write_str("login with the identity provider wasn't finished")
+            }
+            ServerError::OidcProviderError(err) => {
+                write!(f, "couldn't log in with the identity provider: {}", err)
+            }
+            ServerError::LdapError(err) => write!(f, "error from the LDAP server: {}", err),
+            ServerError::BotsCantOwnBots => f.write_str("bots can't create bots"),
+            ServerError::TooManyBots(max) => write!(f, "users can't have more than {} bots", max),
+            ServerError::InvalidUsername => f.write_str("username can't be empty"),
+            ServerError::GuestsNotAllowed => {
+                f.write_str("guests can't do this, upgrade to a full account first")
+            }
+            ServerError::EventGapTooLarge { guild_id, since } => write!(
+                f,
+                "events of guild {} after sequence {} are no longer available",
+                guild_id, since
+            ),
+            ServerError::InvalidDuration => {
+                f.write_str("duration must be a positive number of seconds")
+            }
+            ServerError::TimedOut { guild_id, until } => {
+                write!(f, "you are timed out in guild {} until {}", guild_id, until)
+            }
+            ServerError::InvalidAutomodRule(reason) => {
+                write!(f, "invalid automod rule: {}", reason)
+            }
+            ServerError::BlockedByAutomod { rule } => {
+                write!(f, "message was blocked by automod rule {}", rule)
+            }
+            ServerError::BodyTooLarge(max) => {
+                write!(f, "request body can't be bigger than {} bytes", max)
+            }
         }
     }
 }
@@ -318,6 +408,8 @@ impl ServerError {
             | ServerError::WrongStep { .. }
             | ServerError::WrongTypeForField { .. }
             | ServerError::WrongEmailOrPassword { .. }
+            | ServerError::WrongTotpCode
+            | ServerError::InvalidRefreshToken
             | ServerError::UserAlreadyExists
             | ServerError::UserNotInGuild { .. }
             | ServerError::Unauthenticated
@@ -325,6 +417,7 @@ impl ServerError {
             | ServerError::NoSuchInvite(_)
             | ServerError::NoSuchMessage { .. }
             | ServerError::NoSuchUser(_)
+            | ServerError::NoSuchSession(_)
             | ServerError::NotEnoughPermissions { .. }
             | ServerError::SessionExpired
             | ServerError::UserBanned
@@ -365,8 +458,24 @@ impl ServerError {
             )
             | ServerError::MustNotBeLastOwner
             | ServerError::ContentCantBeSentByUser
+            | ServerError::EmptySearchQuery
+            | ServerError::InvalidJsonBody(_)
+            | ServerError::InvalidResumePoints
+            | ServerError::TotpAlreadyEnabled
+            | ServerError::TotpNotEnrolled
+            | ServerError::OidcLoginNotFinished
+            | ServerError::BotsCantOwnBots
+            | ServerError::TooManyBots(_)
+            | ServerError::InvalidUsername
+            | ServerError::EventGapTooLarge { .. }
+            | ServerError::InvalidDuration
+            | ServerError::InvalidAutomodRule(_)
             | ServerError::InvalidProtoMessage(_) => StatusCode::BAD_REQUEST,
-            ServerError::FederationDisabled | ServerError::HostNotAllowed => StatusCode::FORBIDDEN,
+            ServerError::FederationDisabled
+            | ServerError::HostNotAllowed
+            | ServerError::GuestsNotAllowed
+            | ServerError::TimedOut { .. }
+            | ServerError::BlockedByAutomod { .. } => StatusCode::FORBIDDEN,
             ServerError::IoError(_)
             | ServerError::InternalServerError
             | ServerError::HttpError(_)
@@ -379,9 +488,15 @@ impl ServerError {
             | ServerError::InvalidEmailConfig(_)
             | ServerError::FailedToFetchLink(_)
             | ServerError::FailedToDownload(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::TooFast(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServerError::OidcProviderError(_) | ServerError::LdapError(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            ServerError::TooFast(_) | ServerError::AccountLocked { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
             ServerError::MediaNotFound | ServerError::LinkNotFound(_) => StatusCode::NOT_FOUND,
             ServerError::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            ServerError::BodyTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
         }
     }
 
@@ -431,6 +546,7 @@ impl ServerError {
             ServerError::NoSuchGuild(_) => "h.bad-guild-id",
             ServerError::NoSuchInvite(_) | ServerError::InviteNameEmpty => "h.bad-invite-id",
             ServerError::NoSuchUser(_) => "h.bad-user-id",
+            ServerError::NoSuchSession(_) => "h.bad-session-id",
             ServerError::SessionExpired => "h.bad-session",
             ServerError::EmptyPermissionQuery => "h.permission-query-empty",
             ServerError::NoSuchRole { .. } => "h.bad-role-id",
@@ -466,6 +582,27 @@ impl ServerError {
             ServerError::InvalidRegistrationToken => "h.invalid-registration-token",
             ServerError::MustNotBeLastOwner => "h.last-owner-in-guild",
             ServerError::ContentCantBeSentByUser => "h.content-not-allowed-for-user",
+            ServerError::EmptySearchQuery => "h.search-query-empty",
+            ServerError::InvalidJsonBody(_) => "h.bad-json",
+            ServerError::InvalidResumePoints => "h.bad-resume-points",
+            ServerError::TotpAlreadyEnabled => "h.totp-already-enabled",
+            ServerError::TotpNotEnrolled => "h.totp-not-enrolled",
+            ServerError::WrongTotpCode => "h.wrong-totp-code",
+            ServerError::InvalidRefreshToken => "h.bad-refresh-token",
+            ServerError::AccountLocked { .. } => "h.account-locked",
+            ServerError::OidcLoginNotFinished => "h.oidc-not-finished",
+            ServerError::OidcProviderError(_) => "h.oidc-provider-error",
+            ServerError::LdapError(_) => "h.ldap-error",
+            ServerError::BotsCantOwnBots => "h.bots-cant-own-bots",
+            ServerError::TooManyBots(_) => "h.too-many-bots",
+            ServerError::InvalidUsername => "h.invalid-username",
+            ServerError::GuestsNotAllowed => "h.guests-not-allowed",
+            ServerError::EventGapTooLarge { .. } => "h.event-gap-too-large",
+            ServerError::InvalidDuration => "h.invalid-duration",
+            ServerError::TimedOut { .. } => "h.timed-out",
+            ServerError::InvalidAutomodRule(_) => "h.invalid-automod-rule",
+            ServerError::BlockedByAutomod { .. } => "h.blocked-by-automod",
+            ServerError::BodyTooLarge(_) => "h.body-too-large",
         }
     }
 
@@ -475,7 +612,11 @@ impl ServerError {
         }
 
         match id {
-            "h.federation-disabled" | "h.host-not-allowed" => Some(StatusCode::FORBIDDEN),
+            "h.federation-disabled"
+            | "h.host-not-allowed"
+            | "h.guests-not-allowed"
+            | "h.timed-out"
+            | "h.blocked-by-automod" => Some(StatusCode::FORBIDDEN),
             _ => Some(StatusCode::BAD_REQUEST),
         }
     }
@@ -1,13 +1,23 @@
 use super::*;
 
+use crate::{
+    config::RetentionPolicy, impls::auth::registration_token::describe_registration_token,
+};
+
 pub const HELP_TEXT: &str = r#"
 all commands should be prefixed with `/`.
 
 commands are:
-`generate token` -> generates a single use token
+`generate token [max_uses|none] [valid_for_secs|none] [guild_ids...]` -> generates a registration token, which can be used once and never expires by default. users that register with it join the guilds
+`list tokens` -> lists registration tokens that can still be used
+`revoke token <token_id>` -> deletes a registration token
 `check token <token>` -> checks if a token is valid without using it
 `delete user <user_id>` -> deletes a user from the server
+`reset totp <user_id>` -> disables two-factor authentication for a user, eg. if they lost their device and recovery codes
+`unlock account <user_id>` -> unlocks an account that was locked because of failed logins
 `set motd <new_motd>` -> sets the server MOTD
+`set retention <guild_id> <max_age_secs|none> <max_count|none>` -> sets the message retention policy of a guild
+`set channel retention <guild_id> <channel_id> <max_age_secs|none> <max_count|none>` -> sets the message retention policy of a channel
 `help` -> shows help
 "#;
 
@@ -17,25 +27,112 @@ pub struct AdminActionError;
 pub enum AdminAction {
     SetMotd(String),
     DeleteUser(u64),
-    GenerateToken,
+    ResetTotp(u64),
+    UnlockAccount(u64),
+    GenerateToken {
+        max_uses: Option<u32>,
+        valid_for: Option<u64>,
+        guild_ids: Vec<u64>,
+    },
+    ListTokens,
+    RevokeToken(u64),
     CheckToken(String),
+    SetRetention {
+        guild_id: u64,
+        channel_id: Option<u64>,
+        policy: RetentionPolicy,
+    },
     Help,
 }
 
+fn parse_limit(s: Option<&str>) -> Result<Option<u64>, AdminActionError> {
+    match s.ok_or(AdminActionError)? {
+        "none" => Ok(None),
+        limit => limit
+            .parse::<u64>()
+            .map(|limit| (limit != 0).then(|| limit))
+            .map_err(|_| AdminActionError),
+    }
+}
+
+fn parse_retention_policy<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+) -> Result<RetentionPolicy, AdminActionError> {
+    Ok(RetentionPolicy {
+        max_age: parse_limit(args.next())?,
+        max_count: parse_limit(args.next())?,
+    })
+}
+
+fn parse_id(s: Option<&str>) -> Result<u64, AdminActionError> {
+    s.ok_or(AdminActionError)?
+        .parse::<u64>()
+        .map_err(|_| AdminActionError)
+}
+
 impl FromStr for AdminAction {
     type Err = AdminActionError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let act = match s.strip_prefix('/').ok_or(AdminActionError)? {
-            "generate token" => AdminAction::GenerateToken,
+            "list tokens" => AdminAction::ListTokens,
             "help" => AdminAction::Help,
             s => {
-                if let Some(s) = s.strip_prefix("delete user") {
+                if let Some(s) = s.strip_prefix("generate token") {
+                    let mut args = s.split_whitespace();
+                    let max_uses = match args.next() {
+                        Some(arg) => parse_limit(Some(arg))?
+                            .map(u32::try_from)
+                            .transpose()
+                            .map_err(|_| AdminActionError)?,
+                        None => Some(1),
+                    };
+                    let valid_for = match args.next() {
+                        Some(arg) => parse_limit(Some(arg))?,
+                        None => None,
+                    };
+                    let guild_ids = args
+                        .map(|arg| parse_id(Some(arg)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    AdminAction::GenerateToken {
+                        max_uses,
+                        valid_for,
+                        guild_ids,
+                    }
+                } else if let Some(s) = s.strip_prefix("revoke token") {
+                    let token_id = parse_id(Some(s.trim()))?;
+                    AdminAction::RevokeToken(token_id)
+                } else if let Some(s) = s.strip_prefix("delete user") {
                     let user_id = s.trim().parse::<u64>().map_err(|_| AdminActionError)?;
                     AdminAction::DeleteUser(user_id)
+                } else if let Some(s) = s.strip_prefix("reset totp") {
+                    let user_id = s.trim().parse::<u64>().map_err(|_| AdminActionError)?;
+                    AdminAction::ResetTotp(user_id)
+                } else if let Some(s) = s.strip_prefix("unlock account") {
+                    let user_id = s.trim().parse::<u64>().map_err(|_| AdminActionError)?;
+                    AdminAction::UnlockAccount(user_id)
                 } else if let Some(s) = s.strip_prefix("set motd") {
                     let new_motd = s.trim().to_string();
                     AdminAction::SetMotd(new_motd)
+                } else if let Some(s) = s.strip_prefix("set retention") {
+                    let mut args = s.split_whitespace();
+                    let guild_id = parse_id(args.next())?;
+                    let policy = parse_retention_policy(args)?;
+                    AdminAction::SetRetention {
+                        guild_id,
+                        channel_id: None,
+                        policy,
+                    }
+                } else if let Some(s) = s.strip_prefix("set channel retention") {
+                    let mut args = s.split_whitespace();
+                    let guild_id = parse_id(args.next())?;
+                    let channel_id = parse_id(args.next())?;
+                    let policy = parse_retention_policy(args)?;
+                    AdminAction::SetRetention {
+                        guild_id,
+                        channel_id: Some(channel_id),
+                        policy,
+                    }
                 } else if let Some(s) = s.strip_prefix("check token") {
                     let token = s.trim();
                     AdminAction::CheckToken(token.to_string())
@@ -48,26 +145,94 @@ impl FromStr for AdminAction {
     }
 }
 
-pub async fn run_str(deps: &Dependencies, action: &str) -> ServerResult<String> {
+/// Runs an admin action sent by a user in the command channel.
+pub async fn run_str(deps: &Dependencies, user_id: u64, action: &str) -> ServerResult<String> {
     let maybe_action = AdminAction::from_str(action);
     match maybe_action {
-        Ok(action) => run(deps, action).await,
+        Ok(action) => run(deps, Some(user_id), action).await,
         Err(_) => Ok(format!("invalid command: `{}`", action)),
     }
 }
 
-pub async fn run(deps: &Dependencies, action: AdminAction) -> ServerResult<String> {
+/// Runs an admin action. `user_id` is the admin that is running it, if any.
+pub async fn run(
+    deps: &Dependencies,
+    user_id: Option<u64>,
+    action: AdminAction,
+) -> ServerResult<String> {
     match action {
-        AdminAction::GenerateToken => {
-            let token = deps.auth_tree.generate_single_use_token([]).await?;
-            Ok(token.into())
+        AdminAction::GenerateToken {
+            max_uses,
+            valid_for,
+            guild_ids,
+        } => {
+            let now = get_time_secs();
+            let (token_id, token) = deps
+                .auth_tree
+                .create_registration_token_logic(
+                    user_id,
+                    max_uses,
+                    valid_for.map(|valid_for| now + valid_for),
+                    guild_ids,
+                    now,
+                )
+                .await?;
+            Ok(format!("generated token {} with ID {}", token, token_id))
+        }
+        AdminAction::ListTokens => {
+            let tokens = deps
+                .auth_tree
+                .list_registration_tokens_logic(get_time_secs())
+                .await?;
+            if tokens.is_empty() {
+                return Ok("there are no registration tokens".to_string());
+            }
+            let msg = tokens
+                .iter()
+                .map(|(token_id, info)| describe_registration_token(*token_id, info))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Ok(msg)
+        }
+        AdminAction::RevokeToken(token_id) => {
+            let msg = if deps
+                .auth_tree
+                .revoke_registration_token_logic(token_id)
+                .await?
+            {
+                format!("revoked token {}", token_id)
+            } else {
+                format!("token {} doesn't exist", token_id)
+            };
+            Ok(msg)
         }
         AdminAction::DeleteUser(user_id) => {
             auth::delete_user::logic(deps, user_id).await?;
             Ok(format!("deleted user {}", user_id))
         }
+        AdminAction::ResetTotp(user_id) => {
+            let msg = if deps.auth_tree.reset_totp_logic(user_id).await? {
+                format!("reset two-factor authentication for user {}", user_id)
+            } else {
+                format!("user {} doesn't have two-factor authentication", user_id)
+            };
+            Ok(msg)
+        }
+        AdminAction::UnlockAccount(user_id) => {
+            let msg = if deps.auth_tree.unlock_account_logic(user_id).await? {
+                format!("unlocked user {}", user_id)
+            } else {
+                format!("user {} isn't locked", user_id)
+            };
+            Ok(msg)
+        }
         AdminAction::CheckToken(token) => {
-            let token_valid = deps.auth_tree.check_single_use_token(token).await.is_ok();
+            let token_valid = deps
+                .auth_tree
+                .check_registration_token_logic(token.as_bytes(), get_time_secs())
+                .await?
+                .is_some()
+                || deps.auth_tree.check_single_use_token(token).await.is_ok();
             let msg = token_valid
                 .then(|| "token is valid")
                 .unwrap_or("token is invalid");
@@ -77,6 +242,16 @@ pub async fn run(deps: &Dependencies, action: AdminAction) -> ServerResult<Strin
             deps.runtime_config.lock().motd = new_motd;
             Ok("new MOTD set".to_string())
         }
+        AdminAction::SetRetention {
+            guild_id,
+            channel_id,
+            policy,
+        } => {
+            deps.chat_tree
+                .set_retention_policy_logic(guild_id, channel_id, policy)
+                .await?;
+            Ok(format!("retention policy set to {:?}", policy))
+        }
         AdminAction::Help => Ok(HELP_TEXT.to_string()),
     }
 }
@@ -4,13 +4,16 @@ pub async fn handler(
     svc: &AuthServer,
     _: Request<BeginAuthRequest>,
 ) -> ServerResult<Response<BeginAuthResponse>> {
-    let auth_id = gen_rand_inline_str();
-
-    // [tag:step_stack_non_empty]
-    svc.step_map
-        .entry(auth_id.clone())
-        .and_modify(|s| *s = vec![initial_auth_step()])
-        .or_insert_with(|| vec![initial_auth_step()]);
+    let config = &svc.deps.config;
+    let auth_id = svc
+        .deps
+        .auth_tree
+        .create_auth_flow_logic(
+            initial_auth_step(config),
+            config.policy.sessions.auth_flow_ttl,
+            get_time_secs(),
+        )
+        .await?;
 
     tracing::debug!("new auth session {}", auth_id);
 
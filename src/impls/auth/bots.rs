@@ -0,0 +1,153 @@
+//! Bot accounts owned by users. Bots don't have an email or a password, they
+//! authenticate with a long-lived token that their owner can rotate. Their
+//! sessions never expire.
+
+use db::{
+    chat::make_guild_mem_prefix,
+    profile::{make_bot_owner_key, make_user_bot_key},
+};
+
+use super::*;
+
+fn bot_client_info() -> ClientInfo {
+    ClientInfo {
+        device_name: "bot token".to_string(),
+        ip: None,
+    }
+}
+
+/// Errors with [`ServerError::NoSuchUser`] if the bot isn't owned by the user,
+/// so that users can't find out about bots of others.
+async fn check_bot_owner(
+    deps: &Dependencies,
+    owner_id: u64,
+    bot_id: u64,
+) -> Result<(), ServerError> {
+    match deps.profile_tree.get_bot_owner_logic(bot_id).await? {
+        Some(id) if id == owner_id => Ok(()),
+        _ => Err(ServerError::NoSuchUser(bot_id)),
+    }
+}
+
+/// Creates a bot owned by a user, returning its ID and token.
+pub async fn create_bot(
+    deps: &Dependencies,
+    owner_id: u64,
+    username: String,
+) -> Result<(u64, SmolStr), ServerError> {
+    let profile_tree = &deps.profile_tree;
+
+    if profile_tree.get_profile_logic(owner_id).await?.is_bot {
+        return Err(ServerError::BotsCantOwnBots);
+    }
+    // bots of guests would get around their limits
+    deps.chat_tree.check_not_guest(owner_id).await?;
+
+    let max_bots = deps.config.policy.max_bots_per_user;
+    if profile_tree.get_user_bots_logic(owner_id).await?.len() >= max_bots as usize {
+        return Err(ServerError::TooManyBots(max_bots));
+    }
+
+    if username.is_empty() {
+        return Err(ServerError::InvalidUsername);
+    }
+    if profile_tree.does_username_exist(&username).await? {
+        return Err(ServerError::UserAlreadyExists);
+    }
+
+    let mut bot_id = gen_rand_u64();
+    while deps.auth_tree.contains_key(bot_id.to_be_bytes()).await?
+        || profile_tree
+            .contains_key(make_user_profile_key(bot_id))
+            .await?
+    {
+        bot_id = gen_rand_u64();
+    }
+
+    let mut batch = Batch::default();
+    batch.insert(make_bot_owner_key(bot_id), owner_id.to_be_bytes());
+    batch.insert(make_user_bot_key(owner_id, bot_id), []);
+    batch.insert(make_user_name_key(&username, ""), bot_id.to_be_bytes());
+    let profile = Profile {
+        user_name: username,
+        is_bot: true,
+        ..Default::default()
+    };
+    batch.insert(make_user_profile_key(bot_id), rkyv_ser(&profile));
+    profile_tree.apply_batch(batch).await?;
+
+    let token = deps.auth_tree.gen_session_token().await?;
+    deps.auth_tree
+        .create_session_logic(bot_id, &token, &bot_client_info())
+        .await?;
+
+    tracing::debug!("user {} created bot {}", owner_id, bot_id);
+
+    Ok((bot_id, token))
+}
+
+/// Revokes all tokens of a bot and issues a new one.
+pub async fn rotate_bot_token(
+    deps: &Dependencies,
+    owner_id: u64,
+    bot_id: u64,
+) -> Result<SmolStr, ServerError> {
+    check_bot_owner(deps, owner_id, bot_id).await?;
+
+    deps.auth_tree.revoke_all_sessions_logic(bot_id).await?;
+    deps.chat_event_sender.cancel(StreamCancel::user(bot_id));
+
+    let token = deps.auth_tree.gen_session_token().await?;
+    deps.auth_tree
+        .create_session_logic(bot_id, &token, &bot_client_info())
+        .await?;
+
+    tracing::debug!("user {} rotated the token of bot {}", owner_id, bot_id);
+
+    Ok(token)
+}
+
+pub async fn delete_bot(
+    deps: &Dependencies,
+    owner_id: u64,
+    bot_id: u64,
+) -> Result<(), ServerError> {
+    check_bot_owner(deps, owner_id, bot_id).await?;
+    delete_user::logic(deps, bot_id).await?;
+
+    tracing::debug!("user {} deleted bot {}", owner_id, bot_id);
+
+    Ok(())
+}
+
+/// Gets the bots a user owns, along with their profiles.
+pub async fn list_bots(
+    deps: &Dependencies,
+    owner_id: u64,
+) -> Result<Vec<(u64, Profile)>, ServerError> {
+    let mut bots = Vec::new();
+    for bot_id in deps.profile_tree.get_user_bots_logic(owner_id).await? {
+        let profile = deps.profile_tree.get_profile_logic(bot_id).await?;
+        bots.push((bot_id, profile));
+    }
+    Ok(bots)
+}
+
+/// Gets the bots that are members of a guild, along with their owners. Bots
+/// that were made before bots had owners don't have one.
+pub async fn list_guild_bots(
+    deps: &Dependencies,
+    guild_id: u64,
+) -> Result<Vec<(u64, Option<u64>)>, ServerError> {
+    let prefix = make_guild_mem_prefix(guild_id);
+    let mut bots = Vec::new();
+    for res in deps.chat_tree.scan_prefix(prefix).await {
+        let (key, _) = res?;
+        let user_id = deser_id(&key[prefix.len()..]);
+        if deps.profile_tree.get_profile_logic(user_id).await?.is_bot {
+            let owner_id = deps.profile_tree.get_bot_owner_logic(user_id).await?;
+            bots.push((user_id, owner_id));
+        }
+    }
+    Ok(bots)
+}
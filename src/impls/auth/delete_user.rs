@@ -1,24 +1,44 @@
 use super::*;
 
-pub async fn logic(deps: &Dependencies, user_id: u64) -> ServerResult<()> {
+pub async fn logic(deps: &Dependencies, user_id: u64) -> Result<(), ServerError> {
+    // bots are deleted along with their owner
+    for bot_id in deps.profile_tree.get_user_bots_logic(user_id).await? {
+        delete_user_data(deps, bot_id).await?;
+    }
+
+    delete_user_data(deps, user_id).await
+}
+
+async fn delete_user_data(deps: &Dependencies, user_id: u64) -> Result<(), ServerError> {
     // delete from auth first
     let mut batch = Batch::default();
     batch.remove(user_id.to_be_bytes());
-    batch.remove(token_key(user_id));
-    batch.remove(atime_key(user_id));
+    batch.remove(make_totp_key(user_id));
+    batch.remove(make_ldap_user_key(user_id));
 
     deps.auth_tree.apply_batch(batch).await?;
+    deps.auth_tree.revoke_all_sessions_logic(user_id).await?;
+    deps.auth_tree.remove_guest_logic(user_id).await?;
+
+    // remove the oidc user mapping, so logging in with the provider creates a new user
+    let oidc_key = db::profile::make_local_to_oidc_user_key(user_id);
+    if let Some(oidc_to_local_key) = deps.profile_tree.get(oidc_key).await? {
+        let mut batch = Batch::default();
+        batch.remove(oidc_key);
+        batch.remove(oidc_to_local_key);
+        deps.profile_tree.apply_batch(batch).await?;
+    }
+
+    // remove bot ownership
+    if let Some(owner_id) = deps.profile_tree.get_bot_owner_logic(user_id).await? {
+        let mut batch = Batch::default();
+        batch.remove(db::profile::make_bot_owner_key(user_id));
+        batch.remove(db::profile::make_user_bot_key(owner_id, user_id));
+        deps.profile_tree.apply_batch(batch).await?;
+    }
 
     // set profile to deleted
-    deps.profile_tree
-        .update_profile_logic(
-            user_id,
-            Some("Deleted User".to_string()),
-            Some("".to_string()),
-            Some(UserStatus::OfflineUnspecified.into()),
-            Some(false),
-        )
-        .await?;
+    deps.profile_tree.set_deleted_profile_logic(user_id).await?;
 
     // remove metadata
     db::batch_delete_prefix(
@@ -33,9 +53,12 @@ pub async fn logic(deps: &Dependencies, user_id: u64) -> ServerResult<()> {
         db::chat::make_guild_list_key_prefix(user_id),
     )
     .await?;
+    deps.chat_tree
+        .remove(db::chat::make_guest_key(user_id))
+        .await?;
 
     // end stream event
-    let _ = deps.chat_event_canceller.send(user_id);
+    deps.chat_event_sender.cancel(StreamCancel::user(user_id));
 
     Ok(())
 }
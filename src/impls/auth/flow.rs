@@ -0,0 +1,202 @@
+//! In-progress auth flows (logins, registrations etc.). These are stored in the
+//! auth tree so that they survive restarts, and expire if they aren't continued.
+
+use super::*;
+
+/// An auth flow that hasn't reached a session step yet.
+#[derive(Debug, Clone)]
+pub struct AuthFlow {
+    /// In seconds since unix epoch
+    pub expires_at: u64,
+    /// User that passed the password check and needs to input a TOTP code
+    pub totp_user_id: Option<u64>,
+    /// The steps the client went through, the last one being the current step
+    pub steps: Vec<AuthStep>,
+}
+
+impl AuthFlow {
+    pub fn current_step(&self) -> &AuthStep {
+        // Safety: step stack can never be empty [ref:step_stack_non_empty]
+        unsafe { self.steps.last().unwrap_unchecked() }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.expires_at.to_be_bytes());
+        // user IDs are never 0, so we can use it for "no user"
+        buf.extend_from_slice(&self.totp_user_id.unwrap_or(0).to_be_bytes());
+        for step in &self.steps {
+            step.encode_length_delimited(&mut buf)
+                .expect("vec can grow, so encoding can't fail");
+        }
+        buf
+    }
+
+    fn decode(raw: &[u8]) -> Option<Self> {
+        if raw.len() < size_of::<u64>() * 2 {
+            return None;
+        }
+        let (expires_at, rest) = raw.split_at(size_of::<u64>());
+        let (totp_user_id, mut rest) = rest.split_at(size_of::<u64>());
+
+        let mut steps = Vec::new();
+        while rest.is_empty().not() {
+            let step = AuthStep::decode_length_delimited(&mut rest).ok()?;
+            // our steps always have an inner step contained in them
+            step.step.as_ref()?;
+            steps.push(step);
+        }
+        // [tag:step_stack_non_empty]
+        if steps.is_empty() {
+            return None;
+        }
+
+        let totp_user_id = deser_id(totp_user_id);
+        Some(Self {
+            expires_at: deser_id(expires_at),
+            totp_user_id: (totp_user_id != 0).then(|| totp_user_id),
+            steps,
+        })
+    }
+}
+
+impl AuthTree {
+    /// Starts a new auth flow at the initial step, returning its auth ID.
+    pub async fn create_auth_flow_logic(
+        &self,
+        initial_step: AuthStep,
+        ttl: u64,
+        now: u64,
+    ) -> Result<SmolStr, ServerError> {
+        let mut auth_id = gen_rand_inline_str();
+        while self.contains_key(&auth_flow_key(&auth_id)).await? {
+            auth_id = gen_rand_inline_str();
+        }
+
+        let flow = AuthFlow {
+            expires_at: now + ttl,
+            totp_user_id: None,
+            steps: vec![initial_step],
+        };
+        self.insert(auth_flow_key(&auth_id), flow.encode()).await?;
+
+        Ok(auth_id)
+    }
+
+    /// Gets an auth flow. Errors with [`ServerError::InvalidAuthId`] if it
+    /// doesn't exist or has expired.
+    pub async fn get_auth_flow_logic(
+        &self,
+        auth_id: &str,
+        now: u64,
+    ) -> Result<AuthFlow, ServerError> {
+        let key = auth_flow_key(auth_id);
+        let Some(raw) = self.get(&key).await? else {
+            bail!(ServerError::InvalidAuthId);
+        };
+
+        match AuthFlow::decode(&raw) {
+            Some(flow) if flow.expires_at > now => Ok(flow),
+            _ => {
+                self.remove(&key).await?;
+                Err(ServerError::InvalidAuthId)
+            }
+        }
+    }
+
+    /// Saves an auth flow, extending its expiry by `ttl`.
+    pub async fn update_auth_flow_logic(
+        &self,
+        auth_id: &str,
+        flow: &mut AuthFlow,
+        ttl: u64,
+        now: u64,
+    ) -> Result<(), ServerError> {
+        flow.expires_at = now + ttl;
+        self.insert(auth_flow_key(auth_id), flow.encode()).await?;
+        Ok(())
+    }
+
+    pub async fn remove_auth_flow_logic(&self, auth_id: &str) -> Result<(), ServerError> {
+        self.remove(auth_flow_key(auth_id)).await?;
+        Ok(())
+    }
+
+    /// Removes all auth flows that expired, returning their auth IDs.
+    pub async fn expire_auth_flows_logic(&self, now: u64) -> Result<Vec<SmolStr>, ServerError> {
+        let mut batch = Batch::default();
+        let mut expired = Vec::new();
+        for res in self.scan_prefix(AUTH_FLOW_PREFIX).await {
+            let (key, value) = res?;
+            let is_expired = AuthFlow::decode(&value).map_or(true, |flow| flow.expires_at <= now);
+            if is_expired {
+                let auth_id = &key[AUTH_FLOW_PREFIX.len()..];
+                expired.push(SmolStr::new(String::from_utf8_lossy(auth_id)));
+                batch.remove(key);
+            }
+        }
+        self.apply_batch(batch).await?;
+        Ok(expired)
+    }
+
+    /// Counts the auth flows that haven't expired yet.
+    pub async fn count_auth_flows_logic(&self, now: u64) -> Result<u64, ServerError> {
+        let mut count = 0;
+        for res in self.scan_prefix(AUTH_FLOW_PREFIX).await {
+            let (_, value) = res?;
+            if value.len() >= size_of::<u64>() && deser_id(&value[..size_of::<u64>()]) > now {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn flows_expire() {
+        const TTL: u64 = 60;
+        const NOW: u64 = 1_700_000_000;
+
+        let db = db::open_temp();
+        let auth_tree = AuthTree::new(&db).await.unwrap();
+
+        let initial_step = initial_auth_step(&Config::default());
+        let auth_id = auth_tree
+            .create_auth_flow_logic(initial_step.clone(), TTL, NOW)
+            .await
+            .unwrap();
+        let mut flow = auth_tree.get_auth_flow_logic(&auth_id, NOW).await.unwrap();
+        assert_eq!(flow.current_step(), &initial_step);
+        assert_eq!(flow.totp_user_id, None);
+        assert_eq!(auth_tree.count_auth_flows_logic(NOW).await.unwrap(), 1);
+
+        // taking a step extends the expiry
+        flow.totp_user_id = Some(1);
+        let later = NOW + TTL - 1;
+        auth_tree
+            .update_auth_flow_logic(&auth_id, &mut flow, TTL, later)
+            .await
+            .unwrap();
+        let flow = auth_tree
+            .get_auth_flow_logic(&auth_id, NOW + TTL)
+            .await
+            .unwrap();
+        assert_eq!(flow.totp_user_id, Some(1));
+
+        let expired = auth_tree
+            .expire_auth_flows_logic(later + TTL)
+            .await
+            .unwrap();
+        assert_eq!(expired, vec![auth_id.clone()]);
+        assert!(matches!(
+            auth_tree.get_auth_flow_logic(&auth_id, NOW).await,
+            Err(ServerError::InvalidAuthId)
+        ));
+        assert_eq!(auth_tree.count_auth_flows_logic(NOW).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,226 @@
+//! Guest accounts, for using the server without registering. Guests get a
+//! guest token along with their session, which they can use to upgrade to a
+//! full account that keeps their user ID. Guest accounts that aren't upgraded
+//! are deleted once they expire.
+
+use crate::{api::profile::AccountKind, impls::chat::ChatServer};
+
+use db::chat::make_guest_key as make_guest_marker_key;
+
+use super::*;
+
+impl AuthTree {
+    /// Saves a guest account, returning its guest token.
+    pub async fn create_guest_logic(
+        &self,
+        user_id: u64,
+        expires_at: u64,
+    ) -> Result<SmolStr, ServerError> {
+        let mut token = gen_rand_inline_str();
+        let mut token_hashed = hash_token(token.as_bytes());
+        while self
+            .contains_key(guest_token_key(token_hashed.as_bytes()))
+            .await?
+        {
+            token = gen_rand_inline_str();
+            token_hashed = hash_token(token.as_bytes());
+        }
+
+        let mut batch = Batch::default();
+        batch.insert(
+            make_guest_key(user_id),
+            [&expires_at.to_be_bytes()[..], token_hashed.as_bytes()].concat(),
+        );
+        batch.insert(
+            guest_token_key(token_hashed.as_bytes()),
+            user_id.to_be_bytes(),
+        );
+        self.apply_batch(batch).await?;
+
+        Ok(token)
+    }
+
+    /// Gets the guest a guest token belongs to, if it hasn't expired.
+    pub async fn get_guest_by_token_logic(
+        &self,
+        token: &[u8],
+        now: u64,
+    ) -> Result<Option<u64>, ServerError> {
+        let token_hashed = hash_token(token);
+        let Some(raw_id) = self.get(guest_token_key(token_hashed.as_bytes())).await? else {
+            return Ok(None);
+        };
+
+        let user_id = deser_id(raw_id);
+        let Some(raw) = self.get(make_guest_key(user_id)).await? else {
+            return Ok(None);
+        };
+        let expires_at = deser_id(&raw[..size_of::<u64>()]);
+
+        Ok((expires_at > now).then(|| user_id))
+    }
+
+    /// Forgets that a user is a guest. Returns `false` if they weren't one.
+    pub async fn remove_guest_logic(&self, user_id: u64) -> Result<bool, ServerError> {
+        let key = make_guest_key(user_id);
+        let Some(raw) = self.get(key).await? else {
+            return Ok(false);
+        };
+
+        let mut batch = Batch::default();
+        batch.remove(key);
+        batch.remove(guest_token_key(&raw[size_of::<u64>()..]));
+        self.apply_batch(batch).await?;
+
+        Ok(true)
+    }
+
+    /// Gets the guests whose accounts have expired.
+    pub async fn get_expired_guests_logic(&self, now: u64) -> Result<Vec<u64>, ServerError> {
+        let mut expired = Vec::new();
+        for res in self.scan_prefix(GUEST_PREFIX).await {
+            let (key, value) = res?;
+            if deser_id(&value[..size_of::<u64>()]) <= now {
+                expired.push(deser_id(&key[GUEST_PREFIX.len()..]));
+            }
+        }
+        Ok(expired)
+    }
+}
+
+/// Creates a guest account with a generated username and logs in as it.
+pub async fn create_guest(svc: &AuthServer, client: &ClientInfo) -> ServerResult<AuthStep> {
+    let config = &svc.deps.config.policy.guests;
+    if config.enabled.not() {
+        bail!(ServerError::GuestsNotAllowed);
+    }
+
+    let mut username = format!("guest_{}", gen_rand_str::<6>());
+    while svc.deps.profile_tree.does_username_exist(&username).await? {
+        username = format!("guest_{}", gen_rand_str::<6>());
+    }
+
+    let user_id = svc.gen_user_id().await?;
+    let guest_token = svc
+        .deps
+        .auth_tree
+        .create_guest_logic(user_id, get_time_secs() + config.lifetime)
+        .await?;
+    svc.deps
+        .chat_tree
+        .insert(make_guest_marker_key(user_id), [])
+        .await?;
+
+    let mut batch = Batch::default();
+    batch.insert(make_user_name_key(&username, ""), user_id.to_be_bytes());
+    let buf = rkyv_ser(&Profile {
+        user_name: username,
+        account_kind: AccountKind::Guest.into(),
+        ..Default::default()
+    });
+    batch.insert(make_user_profile_key(user_id), buf);
+    svc.deps.profile_tree.apply_batch(batch).await?;
+
+    let session_token = svc.gen_auth_token().await?;
+    svc.deps
+        .auth_tree
+        .create_session_logic(user_id, session_token.as_str(), client)
+        .await?;
+
+    tracing::debug!("new guest {} created", user_id);
+
+    Ok(AuthStep {
+        can_go_back: false,
+        fallback_url: String::default(),
+        step: Some(auth_step::Step::Session(Session {
+            user_id,
+            session_token: session_token.into(),
+            guest_token: Some(guest_token.into()),
+        })),
+    })
+}
+
+/// Turns a guest into a full user, keeping their user ID and guilds.
+pub async fn upgrade_guest(
+    deps: &Dependencies,
+    user_id: u64,
+    username: String,
+) -> Result<(), ServerError> {
+    deps.auth_tree.remove_guest_logic(user_id).await?;
+    deps.chat_tree
+        .remove(make_guest_marker_key(user_id))
+        .await?;
+
+    let key = make_user_profile_key(user_id);
+    let mut profile = deps.profile_tree.get_profile_logic(user_id).await?;
+    let mut batch = Batch::default();
+    batch.remove(make_user_name_key(&profile.user_name, ""));
+    batch.insert(make_user_name_key(&username, ""), user_id.to_be_bytes());
+    profile.user_name = username;
+    profile.account_kind = AccountKind::FullUnspecified.into();
+    batch.insert(key, rkyv_ser(&profile));
+    deps.profile_tree.apply_batch(batch).await?;
+
+    tracing::debug!("guest {} upgraded to a full account", user_id);
+
+    Ok(())
+}
+
+/// Deletes guest accounts that have expired, making them leave their guilds.
+pub async fn expire_guests(deps: &Arc<Dependencies>) -> ServerResult<()> {
+    let expired = deps
+        .auth_tree
+        .get_expired_guests_logic(get_time_secs())
+        .await?;
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    let chat = ChatServer::new(deps.clone());
+    for user_id in expired {
+        for entry in deps.chat_tree.get_user_guilds(user_id).await? {
+            if entry.server_id.is_empty() {
+                chat.remove_member(entry.guild_id, user_id).await?;
+            }
+        }
+        delete_user::logic(deps, user_id).await?;
+        tracing::debug!("guest {} expired", user_id);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn guest_tokens_expire() {
+        const NOW: u64 = 1_700_000_000;
+        const USER_ID: u64 = 1;
+
+        let db = db::open_temp();
+        let auth_tree = AuthTree::new(&db).await.unwrap();
+
+        let token = auth_tree
+            .create_guest_logic(USER_ID, NOW + 60)
+            .await
+            .unwrap();
+        let get = |now| auth_tree.get_guest_by_token_logic(token.as_bytes(), now);
+        assert_eq!(get(NOW).await.unwrap(), Some(USER_ID));
+        assert_eq!(get(NOW + 60).await.unwrap(), None);
+        assert_eq!(
+            auth_tree.get_expired_guests_logic(NOW + 60).await.unwrap(),
+            vec![USER_ID]
+        );
+
+        assert!(auth_tree.remove_guest_logic(USER_ID).await.unwrap());
+        assert_eq!(get(NOW).await.unwrap(), None);
+        assert!(auth_tree
+            .get_expired_guests_logic(NOW + 60)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
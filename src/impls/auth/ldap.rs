@@ -0,0 +1,254 @@
+//! Logging in with the password of an LDAP directory user.
+//!
+//! When a user logs in, the directory is searched for the email they used. If
+//! they are found there, the password is checked by binding as them instead of
+//! against the local hash, and a local user is created for them the first time
+//! this succeeds. Directory groups can make users admins (by adding them to
+//! the admin guild) or make new users join some guilds.
+
+use crate::{config::LdapConfig, impls::chat::ChatServer};
+
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+
+use super::*;
+
+/// Result code for a bind with a wrong DN or password.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// A user found in the directory.
+#[derive(Debug)]
+pub struct LdapUser {
+    pub dn: String,
+    pub username: Option<String>,
+    /// DNs of the groups the user is in
+    pub groups: Vec<String>,
+}
+
+impl LdapUser {
+    fn is_in_group(&self, group: &str) -> bool {
+        self.groups.iter().any(|dn| dn.eq_ignore_ascii_case(group))
+    }
+}
+
+fn ldap_error(err: LdapError) -> ServerError {
+    ServerError::LdapError(err.to_string())
+}
+
+/// Attribute names are case insensitive, so servers can return them in
+/// another case than the one we asked for.
+fn take_attr(attrs: &mut HashMap<String, Vec<String>>, name: &str) -> Vec<String> {
+    let key = attrs
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(name))
+        .cloned();
+    key.and_then(|key| attrs.remove(&key)).unwrap_or_default()
+}
+
+async fn connect(config: &LdapConfig) -> Result<Ldap, ServerError> {
+    let settings = LdapConnSettings::new().set_conn_timeout(Duration::from_secs(config.timeout));
+    let (conn, ldap) = LdapConnAsync::with_settings(settings, &config.url)
+        .await
+        .map_err(ldap_error)?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.drive().await {
+            tracing::error!("ldap connection error: {}", err);
+        }
+    });
+    Ok(ldap)
+}
+
+/// Searches the directory for a user with an email. Returns `None` if there
+/// isn't exactly one user matching it.
+pub async fn find_user(config: &LdapConfig, email: &str) -> Result<Option<LdapUser>, ServerError> {
+    let mut ldap = connect(config).await?;
+
+    if let Some(bind_dn) = &config.bind_dn {
+        ldap.simple_bind(bind_dn, config.bind_password.as_deref().unwrap_or_default())
+            .await
+            .and_then(|res| res.success())
+            .map_err(ldap_error)?;
+    }
+
+    let filter = config.user_filter.replace("{email}", &ldap_escape(email));
+    let attrs = [
+        config.username_attribute.as_str(),
+        config.group_attribute.as_str(),
+    ];
+    let res = ldap
+        .search(&config.base_dn, Scope::Subtree, &filter, attrs.to_vec())
+        .await
+        .and_then(|res| res.success());
+    let _ = ldap.unbind().await;
+    let (mut entries, _) = res.map_err(ldap_error)?;
+
+    // don't guess which user is meant if the filter is ambiguous
+    if entries.len() > 1 {
+        tracing::warn!("found {} ldap users for email {}", entries.len(), email);
+        return Ok(None);
+    }
+    let Some(entry) = entries.pop() else {
+        return Ok(None);
+    };
+
+    let SearchEntry { dn, mut attrs, .. } = SearchEntry::construct(entry);
+    Ok(Some(LdapUser {
+        username: take_attr(&mut attrs, &config.username_attribute)
+            .into_iter()
+            .next(),
+        groups: take_attr(&mut attrs, &config.group_attribute),
+        dn,
+    }))
+}
+
+/// Checks a password by binding as the user.
+pub async fn verify_password(
+    config: &LdapConfig,
+    dn: &str,
+    password: &str,
+) -> Result<bool, ServerError> {
+    // binding with an empty password is an unauthenticated bind, which
+    // succeeds for any DN
+    if password.is_empty() {
+        return Ok(false);
+    }
+
+    let mut ldap = connect(config).await?;
+    let res = ldap.simple_bind(dn, password).await;
+    let _ = ldap.unbind().await;
+    let res = res.map_err(ldap_error)?;
+
+    if res.rc == INVALID_CREDENTIALS {
+        return Ok(false);
+    }
+    res.success().map_err(ldap_error)?;
+    Ok(true)
+}
+
+async fn create_user(svc: &AuthServer, email: &str, user: &LdapUser) -> ServerResult<u64> {
+    let profile_tree = &svc.deps.profile_tree;
+
+    let base_username = user
+        .username
+        .clone()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or(email).to_string());
+    let mut username = base_username.clone();
+    while profile_tree.does_username_exist(&username).await? {
+        username = format!("{}_{}", base_username, gen_rand_str::<4>());
+    }
+
+    let user_id = svc.gen_user_id().await?;
+
+    let mut batch = Batch::default();
+    batch.insert(email.as_bytes(), user_id.to_be_bytes());
+    batch.insert(make_ldap_user_key(user_id), user.dn.as_bytes());
+    svc.deps.auth_tree.apply_batch(batch).await?;
+
+    let mut batch = Batch::default();
+    batch.insert(make_user_name_key(&username, ""), user_id.to_be_bytes());
+    let buf = rkyv_ser(&Profile {
+        user_name: username,
+        ..Default::default()
+    });
+    batch.insert(make_user_profile_key(user_id), buf);
+    profile_tree.apply_batch(batch).await?;
+
+    tracing::info!("created user {} for ldap user {}", user_id, user.dn);
+
+    Ok(user_id)
+}
+
+/// Gets the local user of a directory user that logged in, creating it if
+/// needed, and updates their admin status from their groups.
+pub async fn sync_user(
+    svc: &AuthServer,
+    config: &LdapConfig,
+    email: &str,
+    user: &LdapUser,
+    user_id: Option<u64>,
+) -> ServerResult<u64> {
+    let chat = ChatServer::new(svc.deps.clone());
+
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => {
+            let user_id = create_user(svc, email, user).await?;
+            let guild_ids = config
+                .group_guilds
+                .iter()
+                .filter(|(group, _)| user.is_in_group(group))
+                .flat_map(|(_, guild_ids)| guild_ids);
+            for guild_id in guild_ids {
+                if let Err(err) = chat.add_member(*guild_id, user_id).await {
+                    tracing::error!(
+                        "couldnt add user {} to guild {}: {}",
+                        user_id,
+                        guild_id,
+                        err
+                    );
+                }
+            }
+            user_id
+        }
+    };
+
+    let admin_guild = svc.deps.chat_tree.admin_guild_keys.get();
+    if let Some(keys) = admin_guild.filter(|_| config.admin_groups.is_empty().not()) {
+        let is_admin = config
+            .admin_groups
+            .iter()
+            .any(|group| user.is_in_group(group));
+        // only users that were created for directory users are managed by it
+        let is_managed = svc
+            .deps
+            .auth_tree
+            .contains_key(make_ldap_user_key(user_id))
+            .await?;
+
+        if is_admin {
+            if chat.add_member(keys.guild_id, user_id).await? {
+                tracing::info!("added user {} to the admin guild", user_id);
+            }
+        } else if is_managed && chat.remove_member(keys.guild_id, user_id).await? {
+            tracing::info!("removed user {} from the admin guild", user_id);
+        }
+    }
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against a local server, eg. `docker run -p 1389:1389 bitnami/openldap`,
+    /// which has the users `user01` and `user02` with the password `bitnami`.
+    #[tokio::test]
+    #[ignore = "needs a local ldap server"]
+    async fn bind_as_directory_user() {
+        let config = LdapConfig {
+            url: std::env::var("SCHERZO_TEST_LDAP_URL")
+                .unwrap_or_else(|_| "ldap://localhost:1389".to_string()),
+            bind_dn: Some("cn=admin,dc=example,dc=org".to_string()),
+            bind_password: Some("adminpassword".to_string()),
+            base_dn: "ou=users,dc=example,dc=org".to_string(),
+            user_filter: "(uid={email})".to_string(),
+            username_attribute: "uid".to_string(),
+            group_attribute: "memberOf".to_string(),
+            admin_groups: Vec::new(),
+            group_guilds: HashMap::new(),
+            timeout: 5,
+        };
+
+        let user = find_user(&config, "user01")
+            .await
+            .unwrap()
+            .expect("user exists");
+        assert_eq!(user.username.as_deref(), Some("user01"));
+        assert!(verify_password(&config, &user.dn, "bitnami").await.unwrap());
+        assert!(!verify_password(&config, &user.dn, "wrong").await.unwrap());
+        assert!(!verify_password(&config, &user.dn, "").await.unwrap());
+
+        assert!(find_user(&config, "nobody").await.unwrap().is_none());
+        assert!(find_user(&config, "*").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,276 @@
+//! Tracking of failed logins per account and per IP, so that passwords can't
+//! be brute forced. Every failure doubles the delay before the next login is
+//! allowed, and too many of them lock the account or IP out for a while.
+
+use crate::{config::LoginLockoutConfig, impls::send_email};
+
+use super::*;
+
+/// How many seconds to wait before another login is allowed.
+fn wait_time(failures: &LoginFailures, policy: &LoginLockoutConfig, now: u64) -> u64 {
+    if failures.locked_until > now {
+        return failures.locked_until - now;
+    }
+    (failures.last_failure + policy.delay_for(failures.count)).saturating_sub(now)
+}
+
+/// Whether the failures can be forgotten, because the lockout is over and
+/// there weren't any new failures for a while.
+fn is_stale(failures: &LoginFailures, policy: &LoginLockoutConfig, now: u64) -> bool {
+    failures.locked_until <= now && now.saturating_sub(failures.last_failure) >= policy.duration
+}
+
+impl AuthTree {
+    async fn get_login_failures(
+        &self,
+        key: &[u8],
+        policy: &LoginLockoutConfig,
+        now: u64,
+    ) -> Result<LoginFailures, ServerError> {
+        Ok(self
+            .get(key)
+            .await?
+            .map(deser_login_failures)
+            .filter(|failures| is_stale(failures, policy, now).not())
+            .unwrap_or_default())
+    }
+
+    /// Records a failed login, returning whether it got locked because of it.
+    async fn record_login_failure(
+        &self,
+        key: &[u8],
+        max_failures: u32,
+        policy: &LoginLockoutConfig,
+        now: u64,
+    ) -> Result<bool, ServerError> {
+        let mut locked = false;
+        self.update_and_fetch(key, |raw| {
+            let mut failures = raw
+                .map(deser_login_failures)
+                .filter(|failures| is_stale(failures, policy, now).not())
+                .unwrap_or_default();
+            failures.count += 1;
+            failures.last_failure = now;
+
+            locked = max_failures != 0 && failures.count >= max_failures;
+            if locked {
+                failures.count = 0;
+                failures.locked_until = now + policy.duration;
+            }
+            Some(rkyv_ser(&failures).into_vec())
+        })
+        .await?;
+
+        Ok(locked)
+    }
+
+    /// Errors with [`ServerError::TooFast`] if logins from an IP are
+    /// delayed or locked out.
+    pub async fn check_ip_login_logic(
+        &self,
+        ip: IpAddr,
+        policy: &LoginLockoutConfig,
+        now: u64,
+    ) -> Result<(), ServerError> {
+        let key = ip_login_failures_key(&ip.to_string());
+        let failures = self.get_login_failures(&key, policy, now).await?;
+        match wait_time(&failures, policy, now) {
+            0 => Ok(()),
+            wait => Err(ServerError::TooFast(Duration::from_secs(wait))),
+        }
+    }
+
+    /// Errors with [`ServerError::AccountLocked`] if an account is locked, or
+    /// [`ServerError::TooFast`] if logins are delayed for it. An account that
+    /// has failed logins can only be checked once per delay, so concurrent
+    /// logins can't guess passwords without waiting for it.
+    pub async fn check_account_login_logic(
+        &self,
+        user_id: u64,
+        policy: &LoginLockoutConfig,
+        now: u64,
+    ) -> Result<(), ServerError> {
+        let key = make_login_failures_key(user_id);
+        loop {
+            let raw = self.get(key).await?;
+            let mut failures = raw
+                .as_ref()
+                .map(deser_login_failures)
+                .filter(|failures| is_stale(failures, policy, now).not())
+                .unwrap_or_default();
+            if failures.locked_until > now {
+                bail!(ServerError::AccountLocked {
+                    until: failures.locked_until,
+                });
+            }
+            match wait_time(&failures, policy, now) {
+                0 if failures.count == 0 => return Ok(()),
+                0 => {}
+                wait => bail!(ServerError::TooFast(Duration::from_secs(wait))),
+            }
+
+            // claim this attempt, the delay starts over until it's recorded
+            failures.last_failure = now;
+            let new = rkyv_ser(&failures);
+            if self
+                .compare_and_swap(key, raw.as_deref(), Some(new.as_ref()))
+                .await?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Records a failed login for an account (if it exists) and for the IP it
+    /// came from. Returns whether the account got locked because of it.
+    pub async fn record_failed_login_logic(
+        &self,
+        user_id: Option<u64>,
+        ip: Option<IpAddr>,
+        policy: &LoginLockoutConfig,
+        now: u64,
+    ) -> Result<bool, ServerError> {
+        if let Some(ip) = ip {
+            let key = ip_login_failures_key(&ip.to_string());
+            if self
+                .record_login_failure(&key, policy.ip_max_failures, policy, now)
+                .await?
+            {
+                tracing::warn!("locked out logins from {} because of failed logins", ip);
+            }
+        }
+
+        match user_id {
+            Some(user_id) => {
+                let key = make_login_failures_key(user_id);
+                self.record_login_failure(&key, policy.max_failures, policy, now)
+                    .await
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Forgets failed logins of an account, and unlocks it if it was locked.
+    /// Returns whether it was locked.
+    pub async fn unlock_account_logic(&self, user_id: u64) -> Result<bool, ServerError> {
+        let was_locked = self
+            .remove(make_login_failures_key(user_id))
+            .await?
+            .map_or(false, |raw| {
+                deser_login_failures(raw).locked_until > get_time_secs()
+            });
+        Ok(was_locked)
+    }
+
+    /// Removes failed login records that can be forgotten.
+    pub async fn expire_login_failures_logic(
+        &self,
+        policy: &LoginLockoutConfig,
+        now: u64,
+    ) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        for prefix in [LOGIN_FAILURES_PREFIX, IP_LOGIN_FAILURES_PREFIX] {
+            for res in self.scan_prefix(prefix).await {
+                let (key, value) = res?;
+                if is_stale(&deser_login_failures(value), policy, now) {
+                    batch.remove(key);
+                }
+            }
+        }
+        self.apply_batch(batch).await
+    }
+}
+
+/// Emails a user that their account got locked, if enabled in the config.
+pub async fn notify_account_locked(deps: &Dependencies, email: &str) {
+    let config = &deps.config;
+    if config.policy.login_lockout.notify_by_email.not()
+        || deps.email.is_none()
+        || config.email.is_none()
+    {
+        return;
+    }
+
+    let subject = format!("Harmony - account locked on {}", config.host);
+    let body = format!(
+        "Your account on {} was locked for {} minutes because of too many failed login attempts.\n\
+        If this wasn't you, someone might be trying to guess your password. Consider changing it.\n",
+        config.host,
+        config.policy.login_lockout.duration / 60,
+    );
+    if let Err(err) = send_email(deps, email, subject, body, None, Vec::new()).await {
+        tracing::error!("couldnt send account locked email: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_until_max() {
+        let policy = LoginLockoutConfig {
+            max_delay: 10,
+            ..LoginLockoutConfig::default()
+        };
+        let delays = (0..6).map(|n| policy.delay_for(n)).collect::<Vec<_>>();
+        assert_eq!(delays, [0, 1, 2, 4, 8, 10]);
+        assert_eq!(policy.delay_for(u32::MAX), 10);
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn account_locks_after_max_failures() {
+        const USER_ID: u64 = 1;
+        const NOW: u64 = 1_700_000_000;
+
+        let db = db::open_temp();
+        let auth_tree = AuthTree::new(&db).await.unwrap();
+        let policy = LoginLockoutConfig {
+            max_failures: 3,
+            ..LoginLockoutConfig::default()
+        };
+
+        let mut now = NOW;
+        for _ in 0..2 {
+            let locked = auth_tree
+                .record_failed_login_logic(Some(USER_ID), None, &policy, now)
+                .await
+                .unwrap();
+            assert!(!locked);
+            assert!(matches!(
+                auth_tree
+                    .check_account_login_logic(USER_ID, &policy, now)
+                    .await,
+                Err(ServerError::TooFast(_))
+            ));
+            now += policy.max_delay;
+        }
+        auth_tree
+            .check_account_login_logic(USER_ID, &policy, now)
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_tree
+                .check_account_login_logic(USER_ID, &policy, now)
+                .await,
+            Err(ServerError::TooFast(_))
+        ));
+
+        let locked = auth_tree
+            .record_failed_login_logic(Some(USER_ID), None, &policy, now)
+            .await
+            .unwrap();
+        assert!(locked);
+        assert!(matches!(
+            auth_tree
+                .check_account_login_logic(USER_ID, &policy, now + policy.max_delay)
+                .await,
+            Err(ServerError::AccountLocked { .. })
+        ));
+        auth_tree
+            .check_account_login_logic(USER_ID, &policy, now + policy.duration)
+            .await
+            .unwrap();
+    }
+}
@@ -6,6 +6,15 @@ pub async fn handler(
     svc: &AuthServer,
     request: Request<LoginFederatedRequest>,
 ) -> Result<Response<LoginFederatedResponse>, HrpcServerError> {
+    let client = ClientInfo::from_request(
+        &request,
+        svc.deps
+            .config
+            .policy
+            .ratelimit
+            .client_ip_header_name
+            .as_deref(),
+    );
     let LoginFederatedRequest {
         auth_token,
         server_id,
@@ -45,6 +54,10 @@ pub async fn handler(
                 make_foreign_to_local_user_key(foreign_id, &server_id),
                 local_id.to_be_bytes().to_vec(),
             );
+            batch.insert(
+                make_user_name_key(&username, &server_id),
+                local_id.to_be_bytes().to_vec(),
+            );
             // Add the profile entry
             let profile = Profile {
                 is_bot: false,
@@ -73,10 +86,7 @@ pub async fn handler(
         };
         svc.deps
             .auth_tree
-            .insert(
-                auth_key(session_token.as_str()),
-                local_user_id.to_be_bytes(),
-            )
+            .create_session_logic(local_user_id, session_token.as_str(), &client)
             .await?;
 
         return Ok((LoginFederatedResponse {
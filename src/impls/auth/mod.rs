@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{collections::HashMap, net::IpAddr, ops::Not, time::Duration};
 
 use crate::api::{
     auth::{next_step_request::form_fields::Field, *},
@@ -12,28 +12,46 @@ use rand::{Rng, SeedableRng};
 use tokio::sync::mpsc::{self, Sender};
 use tracing::Instrument;
 
-use crate::key::{self as keys, Manager as KeyManager};
+use crate::{
+    config::Config,
+    key::{self as keys, Manager as KeyManager},
+};
+
+use super::{
+    chat::{event_log::ResumePoints, StreamCancel},
+    gen_rand_arr, gen_rand_inline_str, gen_rand_u64, get_time_secs,
+    prelude::*,
+};
 
-use super::{gen_rand_arr, gen_rand_inline_str, gen_rand_u64, get_time_secs, prelude::*};
+use flow::AuthFlow;
 
 use db::{
     auth::*,
     profile::{
-        make_foreign_to_local_user_key, make_local_to_foreign_user_key, make_user_profile_key,
+        make_foreign_to_local_user_key, make_local_to_foreign_user_key, make_user_name_key,
+        make_user_profile_key,
     },
 };
 
 pub mod begin_auth;
+pub mod bots;
 pub mod check_logged_in;
 pub mod delete_user;
 pub mod federate;
+pub mod flow;
+pub mod guest;
 pub mod key;
+pub mod ldap;
+pub mod lockout;
 pub mod login_federated;
 pub mod next_step;
+pub mod oidc;
+pub mod registration_token;
 pub mod step_back;
 pub mod stream_steps;
+pub mod totp;
 
-const SESSION_EXPIRE: u64 = 60 * 60 * 24 * 2;
+const SESSION_EXPIRE_CHECK_PERIOD: Duration = Duration::from_secs(60 * 5);
 
 pub fn get_token_from_header_map(headers: &HeaderMap) -> &str {
     headers
@@ -52,8 +70,18 @@ pub fn get_token_from_header_map(headers: &HeaderMap) -> &str {
 }
 
 pub trait AuthExt {
-    fn auth_with<'a>(&'a self, auth_id: &'a str) -> BoxFuture<'a, Result<u64, ServerError>>;
-    fn auth<'a, T>(&'a self, request: &'a Request<T>) -> BoxFuture<'a, Result<u64, ServerError>> {
+    /// Authenticates a token, returning the user ID and session ID it belongs to.
+    fn auth_session_with<'a>(
+        &'a self,
+        auth_id: &'a str,
+    ) -> BoxFuture<'a, Result<(u64, u64), ServerError>>;
+    fn auth_with<'a>(&'a self, auth_id: &'a str) -> BoxFuture<'a, Result<u64, ServerError>> {
+        Box::pin(async move { self.auth_session_with(auth_id).await.map(|(id, _)| id) })
+    }
+    fn auth_session<'a, T>(
+        &'a self,
+        request: &'a Request<T>,
+    ) -> BoxFuture<'a, Result<(u64, u64), ServerError>> {
         let auth_id = request
             .header_map()
             .map_or(Err(ServerError::Unauthenticated), |headers| {
@@ -61,25 +89,61 @@ pub trait AuthExt {
             });
 
         match auth_id {
-            Ok(auth_id) => self.auth_with(auth_id),
+            Ok(auth_id) => self.auth_session_with(auth_id),
             Err(err) => Box::pin(std::future::ready(Err(err))),
         }
     }
+    fn auth<'a, T>(&'a self, request: &'a Request<T>) -> BoxFuture<'a, Result<u64, ServerError>> {
+        let fut = self.auth_session(request);
+        Box::pin(async move { fut.await.map(|(id, _)| id) })
+    }
 }
 
 impl AuthExt for Dependencies {
-    fn auth_with<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<u64, ServerError>> {
+    fn auth_session_with<'a>(
+        &'a self,
+        token: &'a str,
+    ) -> BoxFuture<'a, Result<(u64, u64), ServerError>> {
         Box::pin(async move {
-            self.auth_tree
-                .get(auth_key(token))
-                .await?
-                .map_or(Err(ServerError::Unauthenticated), |raw| Ok(deser_id(raw)))
+            let (user_id, session_id) = self.auth_tree.get(auth_key(token)).await?.map_or(
+                Err(ServerError::Unauthenticated),
+                |raw| {
+                    let (user_id, session_id) = raw.split_at(size_of::<u64>());
+                    Ok((deser_id(user_id), deser_id(session_id)))
+                },
+            )?;
+            // this is written to the session later by the session expiration check
+            self.session_activity
+                .insert((user_id, session_id), get_time_secs());
+            Ok((user_id, session_id))
         })
     }
 }
+
+/// Information about the client that is logging in, saved with the session.
+#[derive(Debug, Default, Clone)]
+pub struct ClientInfo {
+    pub device_name: String,
+    pub ip: Option<IpAddr>,
+}
+
+impl ClientInfo {
+    pub fn from_request<T>(request: &Request<T>, client_ip_header_name: Option<&str>) -> Self {
+        let device_name = request
+            .header_map()
+            .and_then(|headers| headers.get(http::header::USER_AGENT))
+            .and_then(|val| val.to_str().ok())
+            .unwrap_or("unknown device")
+            .to_string();
+        Self {
+            device_name,
+            ip: ratelimit::get_client_ip(request, client_ip_header_name),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AuthServer {
-    step_map: Arc<DashMap<SmolStr, Vec<AuthStep>, RandomState>>,
     send_step: Arc<DashMap<SmolStr, Sender<AuthStep>, RandomState>>,
     queued_steps: Arc<DashMap<SmolStr, Vec<AuthStep>, RandomState>>,
     disable_ratelimits: bool,
@@ -88,96 +152,78 @@ pub struct AuthServer {
 
 impl AuthServer {
     pub fn new(deps: Arc<Dependencies>) -> Self {
-        let att = deps.auth_tree.clone();
-        let ptt = deps.profile_tree.clone();
+        let queued_steps: Arc<DashMap<SmolStr, Vec<AuthStep>, RandomState>> =
+            DashMap::default().into();
+
+        let sweep_deps = deps.clone();
+        let sweep_queued_steps = queued_steps.clone();
 
         tokio::spawn(
             (async move {
-                tracing::info!("starting auth session expiration check thread");
-
-                async fn scan_tree_for(
-                    att: &Tree,
-                    prefix: &[u8],
-                ) -> ServerResult<Vec<(u64, EVec)>> {
-                    let len = prefix.len();
-                    att.scan_prefix(prefix)
-                        .await
-                        .try_fold(Vec::new(), move |mut all, res| {
-                            let (key, val) = res.map_err(ServerError::from)?;
-                            all.push((
-                                // Safety: the right portion of the key after split at the prefix length MUST be a valid u64
-                                deser_id(key.split_at(len).1),
-                                val,
-                            ));
-                            ServerResult::Ok(all)
-                        })
-                }
+                tracing::info!("starting auth session expiration check task");
 
                 loop {
-                    // Safety: we never insert non u64 keys for tokens [tag:token_u64_key]
-                    let tokens = scan_tree_for(&att.inner, TOKEN_PREFIX).await;
-                    // Safety: we never insert non u64 keys for atimes [tag:atime_u64_key]
-                    let atimes = scan_tree_for(&att.inner, ATIME_PREFIX).await;
-
-                    match tokens.and_then(|tokens| Ok((tokens, atimes?))) {
-                        Ok((tokens, atimes)) => {
-                            let mut batch = Batch::default();
-                            for (id, raw_token) in tokens {
-                                if let Ok(profile) = ptt.get_profile_logic(id).await {
-                                    for (oid, raw_atime) in &atimes {
-                                        if id.eq(oid) {
-                                            // Safety: raw_atime's we store are always u64s [tag:atime_u64_value]
-                                            let secs = deser_id(raw_atime);
-                                            let auth_how_old = get_time_secs() - secs;
-                                            // Safety: all of our tokens are valid str's, we never generate invalid ones [ref:alphanumeric_auth_token_gen]
-                                            let token = unsafe {
-                                                std::str::from_utf8_unchecked(raw_token.as_ref())
-                                            };
-                                            let auth_key = auth_key(token);
-
-                                            let Ok(token_exists) = att.contains_key(&auth_key).await else { continue; };
-                                            if token_exists {
-                                                // [ref:atime_u64_key] [ref:atime_u64_value]
-                                                batch.insert(
-                                                    atime_key(id),
-                                                    get_time_secs().to_be_bytes(),
-                                                );
-                                            } else if !profile.is_bot
-                                                && auth_how_old >= SESSION_EXPIRE
-                                            {
-                                                tracing::debug!("user {} session has expired", id);
-                                                batch.remove(auth_key);
-                                                batch.remove(token_key(id));
-                                                batch.remove(atime_key(id));
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            if let Err(err) = att
-                                .inner
-                                .apply_batch(batch)
-                                .await
-                                .map_err(ServerError::DbError)
-                            {
-                                tracing::error!("error applying auth token batch: {}", err);
+                    if let Err(err) = expire_sessions(&sweep_deps).await {
+                        tracing::error!("error checking sessions for expiry: {}", err);
+                    }
+
+                    match sweep_deps
+                        .auth_tree
+                        .expire_auth_flows_logic(get_time_secs())
+                        .await
+                    {
+                        Ok(expired) => {
+                            for auth_id in expired {
+                                tracing::debug!("auth session {} expired", auth_id);
+                                sweep_queued_steps.remove(&auth_id);
                             }
                         }
-                        Err(err) => {
-                            tracing::error!("error scanning tree for tokens: {}", err);
+                        Err(err) => tracing::error!("error expiring auth sessions: {}", err),
+                    }
+
+                    if let Err(err) = sweep_deps
+                        .auth_tree
+                        .expire_login_failures_logic(
+                            &sweep_deps.config.policy.login_lockout,
+                            get_time_secs(),
+                        )
+                        .await
+                    {
+                        tracing::error!("error expiring failed logins: {}", err);
+                    }
+
+                    if let Err(err) = sweep_deps
+                        .auth_tree
+                        .expire_oidc_logic(get_time_secs())
+                        .await
+                    {
+                        tracing::error!("error expiring oidc logins: {}", err);
+                    }
+
+                    if let Err(err) = sweep_deps
+                        .auth_tree
+                        .expire_registration_tokens_logic(get_time_secs())
+                        .await
+                    {
+                        tracing::error!("error expiring registration tokens: {}", err);
+                    }
+
+                    // removing guests sends events, so only one node should do it
+                    if sweep_deps.config.runs_periodic_tasks() {
+                        if let Err(err) = guest::expire_guests(&sweep_deps).await {
+                            tracing::error!("error expiring guests: {}", err);
                         }
                     }
 
-                    std::thread::sleep(Duration::from_secs(60 * 5));
+                    tokio::time::sleep(SESSION_EXPIRE_CHECK_PERIOD).await;
                 }
             })
             .instrument(tracing::info_span!("auth_session_check")),
         );
 
         Self {
-            step_map: DashMap::default().into(),
             send_step: DashMap::default().into(),
-            queued_steps: DashMap::default().into(),
+            queued_steps,
             disable_ratelimits: deps.config.policy.ratelimit.disable,
             deps,
         }
@@ -264,6 +310,132 @@ impl auth_service_server::AuthService for AuthServer {
     }
 }
 
+/// Writes the recorded session activity to the sessions, and expires session
+/// tokens according to the session policy. Sessions are removed once both
+/// their token and refresh token are expired. Bot sessions never expire.
+async fn expire_sessions(deps: &Dependencies) -> ServerResult<()> {
+    let policy = &deps.config.policy.sessions;
+
+    let activity = deps
+        .session_activity
+        .iter()
+        .map(|entry| (*entry.key(), *entry.value()))
+        .collect::<HashMap<_, _, RandomState>>();
+    for (key, last_seen) in &activity {
+        deps.session_activity
+            .remove_if(key, |_, seen| seen == last_seen);
+    }
+
+    let now = get_time_secs();
+    let mut is_bot = HashMap::<u64, bool, RandomState>::default();
+    let mut batch = Batch::default();
+    let mut expired = Vec::new();
+    for res in deps.auth_tree.scan_prefix(SESSION_PREFIX).await {
+        let (key, val) = res?;
+        let (user_id, session_id) = key
+            .split_at(SESSION_PREFIX.len())
+            .1
+            .split_at(size_of::<u64>());
+        let (user_id, session_id) = (deser_id(user_id), deser_id(session_id));
+        let mut session = deser_session(&val);
+
+        let seen = activity.get(&(user_id, session_id));
+        if let Some(last_seen) = seen {
+            session.last_seen = *last_seen;
+        }
+
+        let is_bot = match is_bot.get(&user_id) {
+            Some(is_bot) => *is_bot,
+            None => {
+                let Ok(profile) = deps.profile_tree.get_profile_logic(user_id).await else {
+                    continue;
+                };
+                is_bot.insert(user_id, profile.is_bot);
+                profile.is_bot
+            }
+        };
+
+        let has_token = session.token.is_empty().not();
+        let token_expired = is_bot.not()
+            && has_token
+            && policy.is_token_expired(session.issued_at, session.last_seen, now);
+        let can_refresh = session.refresh_token.is_some() && session.refresh_expires_at > now;
+
+        // the session could have been refreshed or revoked since it was read,
+        // so it's only written back if it's still the same
+        let swapped = if is_bot.not() && (token_expired || has_token.not()) && can_refresh.not() {
+            let swapped = deps
+                .auth_tree
+                .compare_and_swap(&key, Some(val.as_ref()), None)
+                .await?;
+            if swapped {
+                tracing::debug!("user {} session {} has expired", user_id, session_id);
+                batch_remove_session(&mut batch, user_id, session_id, &session);
+            }
+            swapped
+        } else if token_expired {
+            let token = std::mem::take(&mut session.token);
+            let new = rkyv_ser(&session);
+            let swapped = deps
+                .auth_tree
+                .compare_and_swap(&key, Some(val.as_ref()), Some(new.as_ref()))
+                .await?;
+            if swapped {
+                tracing::debug!("user {} session {} token has expired", user_id, session_id);
+                batch.remove(auth_key(&token));
+            }
+            swapped
+        } else if seen.is_some() {
+            let new = rkyv_ser(&session);
+            deps.auth_tree
+                .compare_and_swap(&key, Some(val.as_ref()), Some(new.as_ref()))
+                .await?
+        } else {
+            true
+        };
+
+        if swapped.not() {
+            // keep the activity for the next check
+            if let Some(last_seen) = seen {
+                deps.session_activity
+                    .entry((user_id, session_id))
+                    .and_modify(|seen| *seen = (*seen).max(*last_seen))
+                    .or_insert(*last_seen);
+            }
+        } else if token_expired {
+            expired.push(StreamCancel::session(user_id, session_id));
+        }
+    }
+    deps.auth_tree.apply_batch(batch).await?;
+
+    for cancel in expired {
+        deps.chat_event_sender.cancel(cancel);
+    }
+
+    Ok(())
+}
+
+fn batch_remove_session(batch: &mut Batch, user_id: u64, session_id: u64, session: &SessionInfo) {
+    if session.token.is_empty().not() {
+        batch.remove(auth_key(&session.token));
+    }
+    if let Some(refresh_token) = &session.refresh_token {
+        batch.remove(refresh_token_key(refresh_token.as_bytes()));
+    }
+    batch.remove(make_session_key(user_id, session_id));
+    batch.remove(make_stream_resume_key(user_id, session_id));
+}
+
+/// A session token and refresh token issued by [`AuthTree::refresh_session_logic`].
+#[derive(Debug)]
+pub struct RefreshedSession {
+    pub user_id: u64,
+    pub session_id: u64,
+    pub session_token: SmolStr,
+    pub refresh_token: SmolStr,
+    pub refresh_expires_at: u64,
+}
+
 #[derive(Clone)]
 pub struct AuthTree {
     pub inner: Tree,
@@ -339,6 +511,282 @@ impl AuthTree {
         Ok(val)
     }
 
+    /// Creates a new session for a user that can be authenticated with `token`.
+    pub async fn create_session_logic(
+        &self,
+        user_id: u64,
+        token: &str,
+        client: &ClientInfo,
+    ) -> Result<u64, ServerError> {
+        let mut session_id = gen_rand_u64();
+        while self
+            .contains_key(&make_session_key(user_id, session_id))
+            .await?
+        {
+            session_id = gen_rand_u64();
+        }
+
+        let now = get_time_secs();
+        let session = SessionInfo {
+            token: token.to_string(),
+            device_name: client.device_name.clone(),
+            ip: client.ip.map(|ip| ip.to_string()),
+            created_at: now,
+            last_seen: now,
+            issued_at: now,
+            refresh_token: None,
+            refresh_expires_at: 0,
+        };
+
+        let mut batch = Batch::default();
+        batch.insert(make_session_key(user_id, session_id), rkyv_ser(&session));
+        batch.insert(
+            auth_key(token),
+            [user_id.to_be_bytes(), session_id.to_be_bytes()].concat(),
+        );
+        self.apply_batch(batch).await?;
+
+        Ok(session_id)
+    }
+
+    /// Gets all sessions of a user, along with their IDs.
+    pub async fn get_sessions_logic(
+        &self,
+        user_id: u64,
+    ) -> Result<Vec<(u64, SessionInfo)>, ServerError> {
+        let prefix = make_sessions_prefix(user_id);
+        self.scan_prefix(&prefix)
+            .await
+            .map(|res| {
+                let (key, val) = res?;
+                Ok((deser_id(key.split_at(prefix.len()).1), deser_session(val)))
+            })
+            .collect()
+    }
+
+    /// Removes a session, so its token can't be used anymore. Returns `false`
+    /// if the user had no such session.
+    pub async fn revoke_session_logic(
+        &self,
+        user_id: u64,
+        session_id: u64,
+    ) -> Result<bool, ServerError> {
+        let session_key = make_session_key(user_id, session_id);
+        // the session is only removed if it wasn't refreshed since it was read,
+        // otherwise the new tokens would be left behind
+        loop {
+            let Some(raw) = self.get(&session_key).await? else {
+                return Ok(false);
+            };
+            if self
+                .compare_and_swap(&session_key, Some(raw.as_ref()), None)
+                .await?
+            {
+                let mut batch = Batch::default();
+                batch_remove_session(&mut batch, user_id, session_id, &deser_session(raw));
+                self.apply_batch(batch).await?;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Removes all sessions of a user.
+    pub async fn revoke_all_sessions_logic(&self, user_id: u64) -> Result<(), ServerError> {
+        for (session_id, _) in self.get_sessions_logic(user_id).await? {
+            self.revoke_session_logic(user_id, session_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Saves the sequence of the last logged event that the latest event
+    /// stream of a session went through for each guild.
+    pub async fn set_stream_resume_points_logic(
+        &self,
+        user_id: u64,
+        session_id: u64,
+        points: &ResumePoints,
+    ) -> Result<(), ServerError> {
+        // the session might have been revoked while the stream was open
+        if self
+            .contains_key(make_session_key(user_id, session_id))
+            .await?
+            .not()
+        {
+            return Ok(());
+        }
+
+        let mut value = Vec::with_capacity(points.len() * size_of::<u64>() * 2);
+        for (guild_id, sequence) in points {
+            value.extend_from_slice(&guild_id.to_be_bytes());
+            value.extend_from_slice(&sequence.to_be_bytes());
+        }
+        self.insert(make_stream_resume_key(user_id, session_id), value)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets what [`AuthTree::set_stream_resume_points_logic`] saved for a session.
+    pub async fn get_stream_resume_points_logic(
+        &self,
+        user_id: u64,
+        session_id: u64,
+    ) -> Result<ResumePoints, ServerError> {
+        let raw = self
+            .get(make_stream_resume_key(user_id, session_id))
+            .await?
+            .unwrap_or_default();
+        let points = raw
+            .chunks_exact(size_of::<u64>() * 2)
+            .map(|pair| {
+                let (guild_id, sequence) = pair.split_at(size_of::<u64>());
+                (deser_id(guild_id), deser_id(sequence))
+            })
+            .collect();
+        Ok(points)
+    }
+
+    async fn gen_session_token(&self) -> Result<SmolStr, ServerError> {
+        let mut token = gen_rand_inline_str(); // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
+        while self.contains_key(auth_key(&token)).await? {
+            token = gen_rand_inline_str();
+        }
+        Ok(token)
+    }
+
+    /// Issues a new refresh token for a session, replacing the previous one.
+    /// Returns the token and when it expires.
+    pub async fn issue_refresh_token_logic(
+        &self,
+        user_id: u64,
+        session_id: u64,
+        lifetime: u64,
+    ) -> Result<(SmolStr, u64), ServerError> {
+        let session_key = make_session_key(user_id, session_id);
+        let refresh_token = gen_rand_inline_str();
+        let refresh_token_hashed = hash_token(refresh_token.as_bytes());
+        let new_refresh_key = refresh_token_key(refresh_token_hashed.as_bytes());
+
+        // the new refresh token is written before the session points to it, so
+        // whoever removes the session afterwards also removes the token
+        self.insert(
+            &new_refresh_key,
+            [user_id.to_be_bytes(), session_id.to_be_bytes()].concat(),
+        )
+        .await?;
+
+        loop {
+            let Some(raw) = self.get(&session_key).await? else {
+                self.remove(&new_refresh_key).await?;
+                return Err(ServerError::NoSuchSession(session_id));
+            };
+            let mut session = deser_session(&raw);
+            let old_refresh_token = session.refresh_token.replace(refresh_token_hashed.clone());
+            session.refresh_expires_at = get_time_secs() + lifetime;
+
+            let new = rkyv_ser(&session);
+            if self
+                .compare_and_swap(&session_key, Some(raw.as_ref()), Some(new.as_ref()))
+                .await?
+            {
+                if let Some(old_refresh_token) = old_refresh_token {
+                    self.remove(&refresh_token_key(old_refresh_token.as_bytes()))
+                        .await?;
+                }
+                return Ok((refresh_token, session.refresh_expires_at));
+            }
+        }
+    }
+
+    /// Exchanges a refresh token for a new session token of the same session.
+    /// The refresh token is used up and a new one is issued along with the
+    /// session token.
+    pub async fn refresh_session_logic(
+        &self,
+        refresh_token: &str,
+        lifetime: u64,
+    ) -> Result<RefreshedSession, ServerError> {
+        let refresh_token_hashed = hash_token(refresh_token.as_bytes());
+        let refresh_key = refresh_token_key(refresh_token_hashed.as_bytes());
+        let raw_ids = self
+            .get(&refresh_key)
+            .await?
+            .ok_or(ServerError::InvalidRefreshToken)?;
+        let (user_id, session_id) = {
+            let (user_id, session_id) = raw_ids.split_at(size_of::<u64>());
+            (deser_id(user_id), deser_id(session_id))
+        };
+
+        // claim the refresh token, so it can only be used once
+        if self
+            .compare_and_swap(&refresh_key, Some(raw_ids.as_ref()), None)
+            .await?
+            .not()
+        {
+            return Err(ServerError::InvalidRefreshToken);
+        }
+
+        let session_token = self.gen_session_token().await?;
+        let new_refresh_token = gen_rand_inline_str();
+        let new_refresh_token_hashed = hash_token(new_refresh_token.as_bytes());
+        let new_auth_key = auth_key(&session_token);
+        let new_refresh_key = refresh_token_key(new_refresh_token_hashed.as_bytes());
+
+        // the new tokens are written before the session points to them, so
+        // whoever removes the session afterwards also removes them
+        let mut batch = Batch::default();
+        batch.insert(
+            new_auth_key.as_slice(),
+            [user_id.to_be_bytes(), session_id.to_be_bytes()].concat(),
+        );
+        batch.insert(
+            new_refresh_key.as_slice(),
+            [user_id.to_be_bytes(), session_id.to_be_bytes()].concat(),
+        );
+        self.apply_batch(batch).await?;
+
+        let session_key = make_session_key(user_id, session_id);
+        loop {
+            let now = get_time_secs();
+            let current = self.get(&session_key).await?.and_then(|raw| {
+                let session = deser_session(&raw);
+                let is_valid = session.refresh_expires_at > now
+                    && session.refresh_token.as_ref() == Some(&refresh_token_hashed);
+                is_valid.then(|| (raw, session))
+            });
+            let Some((raw, mut session)) = current else {
+                let mut batch = Batch::default();
+                batch.remove(new_auth_key);
+                batch.remove(new_refresh_key);
+                self.apply_batch(batch).await?;
+                return Err(ServerError::InvalidRefreshToken);
+            };
+
+            let old_token = std::mem::replace(&mut session.token, session_token.to_string());
+            session.issued_at = now;
+            session.last_seen = now;
+            session.refresh_token = Some(new_refresh_token_hashed.clone());
+            session.refresh_expires_at = now + lifetime;
+
+            let new = rkyv_ser(&session);
+            if self
+                .compare_and_swap(&session_key, Some(raw.as_ref()), Some(new.as_ref()))
+                .await?
+            {
+                if old_token.is_empty().not() {
+                    self.remove(&auth_key(&old_token)).await?;
+                }
+                return Ok(RefreshedSession {
+                    user_id,
+                    session_id,
+                    session_token,
+                    refresh_token: new_refresh_token,
+                    refresh_expires_at: session.refresh_expires_at,
+                });
+            }
+        }
+    }
+
     pub async fn get_user_id(&self, email: &str) -> ServerResult<u64> {
         let maybe_user_id = self.get(email.as_bytes()).await?.map(deser_id);
 
@@ -350,16 +798,22 @@ impl AuthTree {
     }
 }
 
-pub fn initial_auth_step() -> AuthStep {
+pub fn initial_auth_step(config: &Config) -> AuthStep {
+    let mut options = vec!["login", "register"];
+    if config.oidc.is_some() {
+        options.push("oidc");
+    }
+    if config.policy.guests.enabled {
+        options.push("guest");
+    }
+    options.push("other-options");
+
     AuthStep {
         can_go_back: false,
         fallback_url: String::default(),
         step: Some(auth_step::Step::Choice(auth_step::Choice {
             title: "initial".to_string(),
-            options: ["login", "register", "other-options"]
-                .iter()
-                .map(ToString::to_string)
-                .collect(),
+            options: options.into_iter().map(ToString::to_string).collect(),
         })),
     }
 }
@@ -436,11 +890,21 @@ const USERNAME_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
     expected: SmolStr::new_inline("text"),
 };
 
+const CODE_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
+    name: SmolStr::new_inline("code"),
+    expected: SmolStr::new_inline("text"),
+};
+
 const TOKEN_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
     name: SmolStr::new_inline("token"),
     expected: SmolStr::new_inline("bytes"),
 };
 
+const GUEST_TOKEN_FIELD_ERR: ServerError = ServerError::WrongTypeForField {
+    name: SmolStr::new_inline("guest_token"),
+    expected: SmolStr::new_inline("bytes"),
+};
+
 #[inline(always)]
 fn try_get_string(values: &mut Vec<Field>, err: ServerError) -> ServerResult<String> {
     if let Some(Field::String(value)) = values.pop() {
@@ -478,3 +942,58 @@ fn try_get_password(values: &mut Vec<Field>) -> ServerResult<Vec<u8>> {
 fn try_get_token(values: &mut Vec<Field>) -> ServerResult<Vec<u8>> {
     try_get_bytes(values, TOKEN_FIELD_ERR)
 }
+
+#[inline(always)]
+fn try_get_guest_token(values: &mut Vec<Field>) -> ServerResult<Vec<u8>> {
+    try_get_bytes(values, GUEST_TOKEN_FIELD_ERR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn refresh_tokens_are_used_once() {
+        const USER_ID: u64 = 1;
+        const LIFETIME: u64 = 60;
+
+        let db = db::open_temp();
+        let auth_tree = AuthTree::new(&db).await.unwrap();
+
+        let session_id = auth_tree
+            .create_session_logic(USER_ID, "token", &ClientInfo::default())
+            .await
+            .unwrap();
+        let (refresh_token, _) = auth_tree
+            .issue_refresh_token_logic(USER_ID, session_id, LIFETIME)
+            .await
+            .unwrap();
+
+        let (first, second) = tokio::join!(
+            auth_tree.refresh_session_logic(&refresh_token, LIFETIME),
+            auth_tree.refresh_session_logic(&refresh_token, LIFETIME),
+        );
+        let refreshed = match (first, second) {
+            (Ok(refreshed), Err(ServerError::InvalidRefreshToken))
+            | (Err(ServerError::InvalidRefreshToken), Ok(refreshed)) => refreshed,
+            res => panic!("expected exactly one refresh to succeed, got {:?}", res),
+        };
+
+        let session = auth_tree
+            .get(make_session_key(USER_ID, session_id))
+            .await
+            .unwrap()
+            .map(deser_session)
+            .unwrap();
+        assert_eq!(session.token, refreshed.session_token.as_str());
+        assert!(!auth_tree.contains_key(auth_key("token")).await.unwrap());
+
+        assert!(auth_tree
+            .revoke_session_logic(USER_ID, session_id)
+            .await
+            .unwrap());
+        assert!(auth_tree.scan_prefix(AUTH_PREFIX).await.next().is_none());
+        assert!(auth_tree.scan_prefix(REFRESH_PREFIX).await.next().is_none());
+    }
+}
@@ -5,6 +5,7 @@ use super::*;
 pub mod delete_user;
 pub mod email;
 pub mod login;
+pub mod oidc;
 pub mod registration;
 pub mod reset_password;
 
@@ -15,6 +16,15 @@ pub async fn handler(
     svc: &AuthServer,
     req: Request<NextStepRequest>,
 ) -> ServerResult<Response<NextStepResponse>> {
+    let client = ClientInfo::from_request(
+        &req,
+        svc.deps
+            .config
+            .policy
+            .ratelimit
+            .client_ip_header_name
+            .as_deref(),
+    );
     let NextStepRequest {
         auth_id,
         step: maybe_step,
@@ -23,29 +33,22 @@ pub async fn handler(
     let auth_id: SmolStr = auth_id.into();
 
     let fut = async {
-        // get step stack for this auth id (the stack is initialized in begin_auth)
-        let Some(mut step_stack) = svc.step_map.get_mut(auth_id.as_str()) else {
-            bail!(ServerError::InvalidAuthId);
-        };
+        let auth_tree = &svc.deps.auth_tree;
+        let now = get_time_secs();
+
+        // get the flow for this auth id (the flow is created in begin_auth)
+        let mut flow = auth_tree.get_auth_flow_logic(&auth_id, now).await?;
 
         // get the next step if possible
         let next_step;
         match maybe_step {
             None => {
-                // Safety: step stack can never be empty [ref:step_stack_non_empty]
-                next_step = unsafe { step_stack.last().unwrap_unchecked().clone() };
+                next_step = flow.current_step().clone();
             }
             Some(step) => {
-                // Safety: step stack can never be empty, and our steps always have an inner step contained in them [ref:step_stack_non_empty]
-                let current_step = unsafe {
-                    step_stack
-                        .last()
-                        .unwrap_unchecked()
-                        .step
-                        .as_ref()
-                        .unwrap_unchecked()
-                        .clone()
-                };
+                // Safety: our steps always have an inner step contained in them
+                let current_step =
+                    unsafe { flow.current_step().step.as_ref().unwrap_unchecked().clone() };
                 tracing::debug!("client replied with step");
                 match step {
                     next_step_request::Step::Choice(next_step_request::Choice { choice }) => {
@@ -60,8 +63,12 @@ pub async fn handler(
                         tracing::debug!("user chose {}", choice);
 
                         if options.contains(&choice) {
-                            next_step = handle_choice(svc, choice.as_str())?;
-                            step_stack.push(next_step.clone());
+                            next_step = match choice.as_str() {
+                                "oidc" => oidc::handle_choice(svc, &auth_id).await?,
+                                "guest" => guest::create_guest(svc, &client).await?,
+                                choice => handle_choice(svc, choice)?,
+                            };
+                            flow.steps.push(next_step.clone());
                         } else {
                             bail!(ServerError::NoSuchChoice {
                                 choice: choice.into(),
@@ -88,10 +95,20 @@ pub async fn handler(
 
                         // handle new forms here
                         next_step = match title.as_str() {
-                            "login" => login::handle(svc, &mut values).await?,
-                            "register" => registration::handle(svc, &mut values).await?,
+                            "login" => login::handle(svc, &mut flow, &mut values, &client).await?,
+                            "login-totp" => {
+                                login::handle_totp(svc, &mut flow, &mut values, &client).await?
+                            }
+                            "oidc" => {
+                                oidc::handle_form(svc, &auth_id, &mut values, &client).await?
+                            }
+                            "register" => registration::handle(svc, &mut values, &client).await?,
+                            "upgrade-guest" => {
+                                registration::handle_upgrade_guest(svc, &mut values, &client)
+                                    .await?
+                            }
                             "register-input-token" => {
-                                registration::handle_input_token(svc, &mut values).await?
+                                registration::handle_input_token(svc, &mut values, &client).await?
                             }
                             "delete-user-input-token" => {
                                 delete_user::handle_input_token(svc, &mut values).await?
@@ -111,13 +128,23 @@ pub async fn handler(
                             )),
                         };
 
-                        step_stack.push(next_step.clone());
+                        flow.steps.push(next_step.clone());
                     }
                 }
             }
         }
 
-        drop(step_stack);
+        let is_complete = matches!(next_step.step, Some(auth_step::Step::Session(_)));
+        if is_complete.not() {
+            auth_tree
+                .update_auth_flow_logic(
+                    &auth_id,
+                    &mut flow,
+                    svc.deps.config.policy.sessions.auth_flow_ttl,
+                    now,
+                )
+                .await?;
+        }
 
         if let Some(chan) = svc.send_step.get(auth_id.as_str()) {
             tracing::debug!("sending next step: {:?}", next_step);
@@ -132,9 +159,9 @@ pub async fn handler(
                 .or_insert_with(|| vec![next_step.clone()]);
         }
 
-        if matches!(next_step.step, Some(auth_step::Step::Session(_))) {
+        if is_complete {
             tracing::debug!("auth session complete");
-            svc.step_map.remove(auth_id.as_str());
+            auth_tree.remove_auth_flow_logic(&auth_id).await?;
             svc.queued_steps.remove(auth_id.as_str());
         }
 
@@ -165,7 +192,7 @@ fn form<'a>(
 
 pub fn handle_choice(svc: &AuthServer, choice: &str) -> ServerResult<AuthStep> {
     let step = match choice {
-        "back-to-initial" => initial_auth_step(),
+        "back-to-initial" => initial_auth_step(&svc.deps.config),
         "other-options" => {
             let mut options = Vec::new();
 
@@ -176,6 +203,9 @@ pub fn handle_choice(svc: &AuthServer, choice: &str) -> ServerResult<AuthStep> {
                         .map(ToString::to_string),
                 );
             }
+            if svc.deps.config.policy.guests.enabled {
+                options.push("upgrade-guest".to_string());
+            }
 
             AuthStep {
                 can_go_back: true,
@@ -217,6 +247,23 @@ pub fn handle_choice(svc: &AuthServer, choice: &str) -> ServerResult<AuthStep> {
                 step: form("register", fields),
             }
         }
+        "upgrade-guest" => {
+            let config = &svc.deps.config;
+            let mut fields = vec![
+                ("guest_token", "password"),
+                ("email", "email"),
+                ("username", "text"),
+                ("password", "password"),
+            ];
+            if config.policy.disable_registration {
+                fields.push(("token", "password"));
+            }
+            AuthStep {
+                can_go_back: true,
+                fallback_url: String::default(),
+                step: form("upgrade-guest", fields),
+            }
+        }
         choice => bail!((
             "h.invalid-choice",
             format!("got invalid choice: {}", choice),
@@ -1,12 +1,82 @@
+use crate::{
+    config::LdapConfig,
+    impls::auth::ldap::{self, LdapUser},
+};
+
 use super::*;
 
-pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<AuthStep> {
+pub async fn handle(
+    svc: &AuthServer,
+    flow: &mut AuthFlow,
+    values: &mut Vec<Field>,
+    client: &ClientInfo,
+) -> ServerResult<AuthStep> {
     let auth_tree = &svc.deps.auth_tree;
+    let policy = &svc.deps.config.policy.login_lockout;
+    let now = get_time_secs();
+
+    if let Some(ip) = client.ip {
+        auth_tree.check_ip_login_logic(ip, policy, now).await?;
+    }
 
     let password_raw = try_get_password(values)?;
     let email = try_get_email(values)?;
 
-    let user_id = auth_tree.get_user_id(&email).await?;
+    let ldap_user = match &svc.deps.config.ldap {
+        Some(config) => find_ldap_user(svc, config, &email)
+            .await?
+            .map(|user| (config, user)),
+        None => None,
+    };
+
+    let user_id = match ldap_user {
+        Some((config, ldap_user)) => {
+            handle_ldap(svc, config, ldap_user, &email, password_raw, client, now).await?
+        }
+        None => handle_local(svc, &email, password_raw, client, now).await?,
+    };
+
+    if auth_tree.is_totp_enabled_logic(user_id).await? {
+        tracing::debug!("user {} needs to input a totp code", user_id);
+        flow.totp_user_id = Some(user_id);
+        return Ok(AuthStep {
+            can_go_back: false,
+            fallback_url: String::default(),
+            step: form("login-totp", [("code", "text")]),
+        });
+    }
+
+    tracing::debug!("user {} logged in with email {}", user_id, email);
+
+    create_session(svc, user_id, client).await
+}
+
+/// Checks the password of a local user, returning their ID.
+async fn handle_local(
+    svc: &AuthServer,
+    email: &str,
+    password_raw: Vec<u8>,
+    client: &ClientInfo,
+    now: u64,
+) -> ServerResult<u64> {
+    let auth_tree = &svc.deps.auth_tree;
+    let policy = &svc.deps.config.policy.login_lockout;
+
+    let user_id = match auth_tree.get_user_id(email).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            if matches!(err, ServerError::WrongEmailOrPassword { .. }) {
+                auth_tree
+                    .record_failed_login_logic(None, client.ip, policy, now)
+                    .await?;
+            }
+            return Err(err.into());
+        }
+    };
+
+    auth_tree
+        .check_account_login_logic(user_id, policy, now)
+        .await?;
 
     // check password
     let is_password_correct =
@@ -18,29 +88,165 @@ pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<A
                 verify_password(password_raw, pass_hash)
             });
     if !is_password_correct {
+        record_failed_login(svc, user_id, Some(email), client, now).await?;
         bail!(ServerError::WrongEmailOrPassword {
             email: email.into(),
         });
     }
 
-    let session_token = svc.gen_auth_token().await?; // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
-    let mut batch = Batch::default();
-    // [ref:token_u64_key]
-    batch.insert(token_key(user_id), session_token.as_str().as_bytes());
-    batch.insert(
-        // [ref:atime_u64_key]
-        atime_key(user_id),
-        // [ref:atime_u64_value]
-        get_time_secs().to_be_bytes(),
-    );
-    auth_tree.apply_batch(batch).await?;
+    Ok(user_id)
+}
 
-    tracing::debug!("user {} logged in with email {}", user_id, email);
+/// Finds the directory user to log in as, along with the ID of the local user
+/// that was created for them if there is one. Returns `None` if the password
+/// should be checked locally instead, which is the case for local users whose
+/// email is also in the directory, or if the directory can't be reached.
+async fn find_ldap_user(
+    svc: &AuthServer,
+    config: &LdapConfig,
+    email: &str,
+) -> ServerResult<Option<(LdapUser, Option<u64>)>> {
+    let ldap_user = match ldap::find_user(config, email).await {
+        Ok(Some(ldap_user)) => ldap_user,
+        Ok(None) => return Ok(None),
+        Err(err) => {
+            tracing::error!("couldnt look up {} in the directory: {}", email, err);
+            return Ok(None);
+        }
+    };
+
+    let auth_tree = &svc.deps.auth_tree;
+    match auth_tree.get(email.as_bytes()).await?.map(deser_id) {
+        // only users that were created for directory users are managed by it
+        Some(user_id) if auth_tree.contains_key(make_ldap_user_key(user_id)).await? => {
+            Ok(Some((ldap_user, Some(user_id))))
+        }
+        Some(_) => Ok(None),
+        None => Ok(Some((ldap_user, None))),
+    }
+}
+
+/// Checks the password of a directory user by binding as them, returning the
+/// ID of their local user.
+async fn handle_ldap(
+    svc: &AuthServer,
+    config: &LdapConfig,
+    (ldap_user, user_id): (LdapUser, Option<u64>),
+    email: &str,
+    password_raw: Vec<u8>,
+    client: &ClientInfo,
+    now: u64,
+) -> ServerResult<u64> {
+    let auth_tree = &svc.deps.auth_tree;
+    let policy = &svc.deps.config.policy.login_lockout;
+
+    if let Some(user_id) = user_id {
+        auth_tree
+            .check_account_login_logic(user_id, policy, now)
+            .await?;
+    }
+
+    let password = String::from_utf8(password_raw).unwrap_or_default();
+    if ldap::verify_password(config, &ldap_user.dn, &password)
+        .await?
+        .not()
+    {
+        match user_id {
+            Some(user_id) => record_failed_login(svc, user_id, Some(email), client, now).await?,
+            None => {
+                auth_tree
+                    .record_failed_login_logic(None, client.ip, policy, now)
+                    .await?;
+            }
+        }
+        bail!(ServerError::WrongEmailOrPassword {
+            email: email.into(),
+        });
+    }
+
+    ldap::sync_user(svc, config, email, &ldap_user, user_id).await
+}
+
+/// Handles the TOTP code (or a recovery code) of a user that passed the
+/// password check in [`handle`].
+pub async fn handle_totp(
+    svc: &AuthServer,
+    flow: &mut AuthFlow,
+    values: &mut Vec<Field>,
+    client: &ClientInfo,
+) -> ServerResult<AuthStep> {
+    let code = try_get_string(values, CODE_FIELD_ERR)?;
+
+    let Some(user_id) = flow.totp_user_id else {
+        bail!(ServerError::InvalidAuthId);
+    };
+
+    let auth_tree = &svc.deps.auth_tree;
+    let now = get_time_secs();
 
     auth_tree
-        .insert(auth_key(session_token.as_str()), user_id.to_be_bytes())
+        .check_account_login_logic(user_id, &svc.deps.config.policy.login_lockout, now)
         .await?;
 
+    let res = auth_tree.verify_totp_logic(user_id, code.trim(), now).await;
+    if let Err(ServerError::WrongTotpCode) = res {
+        record_failed_login(svc, user_id, None, client, now).await?;
+    }
+    res?;
+    flow.totp_user_id = None;
+
+    tracing::debug!("user {} logged in with totp", user_id);
+
+    create_session(svc, user_id, client).await
+}
+
+/// Records a failed login. Errors with [`ServerError::AccountLocked`] if the
+/// account got locked because of it, emailing the user about it if possible.
+async fn record_failed_login(
+    svc: &AuthServer,
+    user_id: u64,
+    email: Option<&str>,
+    client: &ClientInfo,
+    now: u64,
+) -> ServerResult<()> {
+    let policy = &svc.deps.config.policy.login_lockout;
+    let locked = svc
+        .deps
+        .auth_tree
+        .record_failed_login_logic(Some(user_id), client.ip, policy, now)
+        .await?;
+
+    if locked {
+        tracing::warn!("locked user {} because of failed logins", user_id);
+        if let Some(email) = email {
+            lockout::notify_account_locked(svc.deps.as_ref(), email).await;
+        }
+        bail!(ServerError::AccountLocked {
+            until: now + policy.duration,
+        });
+    }
+
+    Ok(())
+}
+
+/// Creates a session for a user that finished logging in, returning the
+/// session step.
+pub async fn create_session(
+    svc: &AuthServer,
+    user_id: u64,
+    client: &ClientInfo,
+) -> ServerResult<AuthStep> {
+    svc.deps.auth_tree.unlock_account_logic(user_id).await?;
+
+    let session_token = svc.gen_auth_token().await?; // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
+    let session_id = svc
+        .deps
+        .auth_tree
+        .create_session_logic(user_id, session_token.as_str(), client)
+        .await?;
+
+    tracing::debug!("created session {} for user {}", session_id, user_id);
+
     Ok(AuthStep {
         can_go_back: false,
         fallback_url: String::default(),
@@ -0,0 +1,58 @@
+use crate::impls::auth::oidc::{
+    authorization_url, discover, exchange_code, get_or_create_oidc_user,
+};
+
+use super::*;
+
+/// Handles the `oidc` choice, returning the form with the authorization URL.
+pub async fn handle_choice(svc: &AuthServer, auth_id: &str) -> ServerResult<AuthStep> {
+    let deps = &svc.deps;
+    let Some(config) = deps.config.oidc.as_ref() else {
+        bail!(("h.invalid-choice", "oidc is not configured"));
+    };
+
+    let metadata = discover(&deps.http, config).await?;
+    let expires_at = get_time_secs() + deps.config.policy.sessions.auth_flow_ttl;
+    let (state, code_verifier) = deps
+        .auth_tree
+        .create_oidc_state_logic(auth_id, expires_at)
+        .await?;
+
+    Ok(AuthStep {
+        can_go_back: true,
+        fallback_url: authorization_url(&metadata, config, &state, &code_verifier),
+        step: form("oidc", [("code", "text")]),
+    })
+}
+
+/// Handles the `oidc` form, exchanging the code the user got from the callback
+/// and creating a session for the user it belongs to.
+pub async fn handle_form(
+    svc: &AuthServer,
+    auth_id: &str,
+    values: &mut Vec<Field>,
+    client: &ClientInfo,
+) -> ServerResult<AuthStep> {
+    let deps = &svc.deps;
+    let Some(config) = deps.config.oidc.as_ref() else {
+        bail!(("h.invalid-form", "oidc is not configured"));
+    };
+
+    let code = try_get_string(values, CODE_FIELD_ERR)?;
+
+    let Some(code_verifier) = deps
+        .auth_tree
+        .take_oidc_verifier_logic(auth_id, get_time_secs())
+        .await?
+    else {
+        bail!(ServerError::OidcLoginNotFinished);
+    };
+
+    let metadata = discover(&deps.http, config).await?;
+    let info = exchange_code(&deps.http, config, &metadata, code.trim(), &code_verifier).await?;
+    let user_id = get_or_create_oidc_user(&deps.profile_tree, &config.issuer, &info).await?;
+
+    tracing::debug!("user {} logged in with oidc", user_id);
+
+    login::create_session(svc, user_id, client).await
+}
@@ -1,5 +1,7 @@
 use rkyv::{de::deserializers::SharedDeserializeMap, Archive, Deserialize, Serialize};
 
+use crate::impls::chat::ChatServer;
+
 use super::*;
 
 #[derive(Debug, Archive, Serialize, Deserialize)]
@@ -8,33 +10,114 @@ struct RegInfo {
     email: String,
     username: String,
     password_raw: Vec<u8>,
+    guild_ids: Vec<u64>,
+    /// Set if a guest is upgrading to a full account
+    guest_id: Option<u64>,
 }
 
-pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<AuthStep> {
-    let auth_tree = &svc.deps.auth_tree;
-    let config = &svc.deps.config;
-
-    if config.policy.disable_registration {
+pub async fn handle(
+    svc: &AuthServer,
+    values: &mut Vec<Field>,
+    client: &ClientInfo,
+) -> ServerResult<AuthStep> {
+    let mut guild_ids = Vec::new();
+    if svc.deps.config.policy.disable_registration {
         let token_raw = try_get_token(values)?;
-        auth_tree.validate_single_use_token(token_raw).await?;
+        guild_ids = use_token(svc, token_raw).await?;
     }
 
     let password_raw = try_get_password(values)?;
     let username = try_get_username(values)?;
     let email = try_get_email(values)?;
 
+    let reg_info = RegInfo {
+        email,
+        username,
+        password_raw,
+        guild_ids,
+        guest_id: None,
+    };
+    validate_or_register(svc, reg_info, client).await
+}
+
+/// Registers a guest, keeping their user ID.
+pub async fn handle_upgrade_guest(
+    svc: &AuthServer,
+    values: &mut Vec<Field>,
+    client: &ClientInfo,
+) -> ServerResult<AuthStep> {
+    let token_raw = if svc.deps.config.policy.disable_registration {
+        Some(try_get_token(values)?)
+    } else {
+        None
+    };
+    let password_raw = try_get_password(values)?;
+    let username = try_get_username(values)?;
+    let email = try_get_email(values)?;
+    let guest_token = try_get_guest_token(values)?;
+
+    let Some(guest_id) = svc
+        .deps
+        .auth_tree
+        .get_guest_by_token_logic(&guest_token, get_time_secs())
+        .await?
+    else {
+        bail!((
+            "h.invalid-guest-token",
+            "guest token is invalid or has expired"
+        ));
+    };
+
+    let guild_ids = match token_raw {
+        Some(token_raw) => use_token(svc, token_raw).await?,
+        None => Vec::new(),
+    };
+
+    let reg_info = RegInfo {
+        email,
+        username,
+        password_raw,
+        guild_ids,
+        guest_id: Some(guest_id),
+    };
+    validate_or_register(svc, reg_info, client).await
+}
+
+/// Uses a token for registering while registration is disabled, returning the
+/// guilds the user should join.
+async fn use_token(svc: &AuthServer, token_raw: Vec<u8>) -> ServerResult<Vec<u64>> {
+    let auth_tree = &svc.deps.auth_tree;
+    match auth_tree
+        .use_registration_token_logic(&token_raw, get_time_secs())
+        .await?
+    {
+        Some(guild_ids) => Ok(guild_ids),
+        // single use tokens were used for registration before registration tokens
+        None => {
+            auth_tree.validate_single_use_token(token_raw).await?;
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Sends a token to the email of the user if emails need to be validated,
+/// otherwise registers them.
+async fn validate_or_register(
+    svc: &AuthServer,
+    reg_info: RegInfo,
+    client: &ClientInfo,
+) -> ServerResult<AuthStep> {
+    let config = &svc.deps.config;
+
     if svc.deps.email.is_some()
         && config.email.is_some()
         && !config.policy.disable_registration_email_validation
     {
-        let reg_info = RegInfo {
-            email,
-            username,
-            password_raw,
-        };
         let reg_info_serialized = rkyv_ser(&reg_info);
 
-        let token = auth_tree
+        let token = svc
+            .deps
+            .auth_tree
             .generate_single_use_token(reg_info_serialized)
             .await?;
 
@@ -53,12 +136,13 @@ pub async fn handle(svc: &AuthServer, values: &mut Vec<Field>) -> ServerResult<A
         });
     }
 
-    logic(svc, password_raw, username, email).await
+    logic(svc, reg_info, client).await
 }
 
 pub async fn handle_input_token(
     svc: &AuthServer,
     values: &mut Vec<Field>,
+    client: &ClientInfo,
 ) -> ServerResult<AuthStep> {
     let token = try_get_token(values)?;
 
@@ -67,22 +151,22 @@ pub async fn handle_input_token(
         .deserialize(&mut SharedDeserializeMap::default())
         .expect("must be correct");
 
-    logic(
-        svc,
-        reg_info.password_raw,
-        reg_info.username,
-        reg_info.email,
-    )
-    .await
+    logic(svc, reg_info, client).await
 }
 
-pub async fn logic(
+async fn logic(
     svc: &AuthServer,
-    password_raw: Vec<u8>,
-    username: String,
-    email: String,
+    reg_info: RegInfo,
+    client: &ClientInfo,
 ) -> ServerResult<AuthStep> {
     let auth_tree = &svc.deps.auth_tree;
+    let RegInfo {
+        email,
+        username,
+        password_raw,
+        guild_ids,
+        guest_id,
+    } = reg_info;
 
     if password_raw.is_empty() {
         bail!(("h.invalid-password", "password can't be empty"));
@@ -105,35 +189,55 @@ pub async fn logic(
         bail!(ServerError::UserAlreadyExists);
     }
 
-    let user_id = svc.gen_user_id().await?;
+    let user_id = match guest_id {
+        Some(user_id) => {
+            // the guest could have expired while their email was being validated
+            if auth_tree.contains_key(make_guest_key(user_id)).await?.not() {
+                bail!((
+                    "h.invalid-guest-token",
+                    "guest token is invalid or has expired"
+                ));
+            }
+            user_id
+        }
+        None => svc.gen_user_id().await?,
+    };
     let session_token = svc.gen_auth_token().await?; // [ref:alphanumeric_auth_token_gen] [ref:auth_token_length]
 
     let mut batch = Batch::default();
     batch.insert(email.into_bytes(), user_id.to_be_bytes());
     batch.insert(user_id.to_be_bytes(), password_hashed.into_bytes());
-    // [ref:token_u64_key]
-    batch.insert(token_key(user_id), session_token.as_str().as_bytes());
-    batch.insert(
-        // [ref:atime_u64_key]
-        atime_key(user_id),
-        // [ref:atime_u64_value]
-        get_time_secs().to_be_bytes(),
-    );
     auth_tree.apply_batch(batch).await?;
 
-    let buf = rkyv_ser(&Profile {
-        user_name: username,
-        ..Default::default()
-    });
-    svc.deps
-        .profile_tree
-        .insert(make_user_profile_key(user_id), buf)
-        .await?;
+    if guest_id.is_some() {
+        guest::upgrade_guest(&svc.deps, user_id, username).await?;
+    } else {
+        let mut batch = Batch::default();
+        batch.insert(make_user_name_key(&username, ""), user_id.to_be_bytes());
+        let buf = rkyv_ser(&Profile {
+            user_name: username,
+            ..Default::default()
+        });
+        batch.insert(make_user_profile_key(user_id), buf);
+        svc.deps.profile_tree.apply_batch(batch).await?;
+
+        tracing::debug!("new user {} registered", user_id);
+    }
 
-    tracing::debug!("new user {} registered", user_id);
+    let chat = ChatServer::new(svc.deps.clone());
+    for guild_id in guild_ids {
+        if let Err(err) = chat.add_member(guild_id, user_id).await {
+            tracing::error!(
+                "couldnt add user {} to guild {}: {}",
+                user_id,
+                guild_id,
+                err
+            );
+        }
+    }
 
     auth_tree
-        .insert(auth_key(session_token.as_str()), user_id.to_be_bytes())
+        .create_session_logic(user_id, session_token.as_str(), client)
         .await?;
 
     Ok(AuthStep {
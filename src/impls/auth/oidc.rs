@@ -0,0 +1,443 @@
+//! Logging in through an OpenID Connect provider, using the authorization code flow.
+//!
+//! Choosing `oidc` gives the client an `oidc` form with a `code` field, with
+//! the authorization URL of the provider as its fallback URL. After the user
+//! logs in, the provider redirects them to [`OIDC_CALLBACK_PATH`], which shows
+//! them the authorization code. Submitting it in the form exchanges it with the
+//! PKCE code verifier of the auth flow, and maps the `sub` claim of the user to
+//! a local user (which is created the first time).
+//!
+//! Only the user that logged in with the provider sees the code, so someone
+//! else can't finish the login by getting them to open the authorization URL
+//! of an auth flow they started.
+
+use crate::{api::profile::AccountKind, config::OidcConfig, impls::profile::ProfileTree};
+
+use db::profile::{make_local_to_oidc_user_key, make_oidc_to_local_user_key};
+use reqwest::{Client as HttpClient, RequestBuilder};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256};
+
+use super::*;
+
+pub const OIDC_CALLBACK_PATH: &str = "/_harmony/oidc/callback";
+
+/// The parts of the provider metadata we need.
+#[derive(Debug, Deserialize)]
+pub struct ProviderMetadata {
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// The claims we use from the userinfo endpoint.
+#[derive(Debug, Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl UserInfo {
+    fn username(&self) -> &str {
+        self.preferred_username
+            .as_deref()
+            .or(self.name.as_deref())
+            .unwrap_or(&self.sub)
+    }
+}
+
+async fn fetch_json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ServerError> {
+    let resp = request
+        .send()
+        .await
+        .map_err(|err| ServerError::OidcProviderError(err.to_string()))?;
+    if resp.status().is_success().not() {
+        return Err(ServerError::OidcProviderError(format!(
+            "provider responded with {}",
+            resp.status()
+        )));
+    }
+    let body = resp
+        .bytes()
+        .await
+        .map_err(|err| ServerError::OidcProviderError(err.to_string()))?;
+    serde_json::from_slice(&body)
+        .map_err(|err| ServerError::OidcProviderError(format!("invalid response: {}", err)))
+}
+
+pub async fn discover(
+    http: &HttpClient,
+    config: &OidcConfig,
+) -> Result<ProviderMetadata, ServerError> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        config.issuer.trim_end_matches('/')
+    );
+    fetch_json(http.get(url)).await
+}
+
+/// Makes the PKCE code challenge of a code verifier, with the `S256` method.
+fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    config: &OidcConfig,
+    state: &str,
+    code_verifier: &str,
+) -> String {
+    format!(
+        "{}?response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&code_challenge={}&code_challenge_method=S256",
+        metadata.authorization_endpoint,
+        urlencoding::encode(&config.client_id),
+        urlencoding::encode(&config.redirect_url),
+        urlencoding::encode(&config.scopes.join(" ")),
+        urlencoding::encode(state),
+        code_challenge(code_verifier),
+    )
+}
+
+/// Exchanges an authorization code, and gets the info of the user it's for.
+pub async fn exchange_code(
+    http: &HttpClient,
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+) -> Result<UserInfo, ServerError> {
+    let token: TokenResponse = fetch_json(http.post(&metadata.token_endpoint).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("code_verifier", code_verifier),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("client_secret", config.client_secret.as_str()),
+    ]))
+    .await?;
+
+    fetch_json(
+        http.get(&metadata.userinfo_endpoint)
+            .bearer_auth(token.access_token),
+    )
+    .await
+}
+
+/// Gets the local user of an OIDC user, creating one with a profile if they
+/// didn't log in before. A random suffix is added to their name if it's taken.
+pub async fn get_or_create_oidc_user(
+    profile_tree: &ProfileTree,
+    issuer: &str,
+    info: &UserInfo,
+) -> Result<u64, ServerError> {
+    let key = make_oidc_to_local_user_key(issuer, &info.sub);
+    if let Some(raw) = profile_tree.get(&key).await? {
+        return Ok(deser_id(raw));
+    }
+
+    let mut local_id = gen_rand_u64();
+    while profile_tree
+        .contains_key(make_user_profile_key(local_id))
+        .await?
+    {
+        local_id = gen_rand_u64();
+    }
+
+    // the name is claimed in the name index first, so the entry of an existing
+    // user with the same name is never replaced
+    let mut username = info.username().to_string();
+    while profile_tree.does_username_exist(&username).await?
+        || profile_tree
+            .compare_and_swap(
+                make_user_name_key(&username, ""),
+                None,
+                Some(&local_id.to_be_bytes()[..]),
+            )
+            .await?
+            .not()
+    {
+        username = format!("{}_{}", info.username(), gen_rand_str::<4>());
+    }
+
+    let mut batch = Batch::default();
+    batch.insert(make_local_to_oidc_user_key(local_id).to_vec(), key.clone());
+    batch.insert(key, local_id.to_be_bytes().to_vec());
+    let profile = Profile {
+        is_bot: false,
+        user_status: UserStatus::OfflineUnspecified.into(),
+        user_avatar: None,
+        user_name: username,
+        account_kind: AccountKind::FullUnspecified.into(),
+    };
+    batch.insert(make_user_profile_key(local_id).to_vec(), rkyv_ser(&profile));
+    profile_tree.apply_batch(batch).await?;
+
+    tracing::debug!("created user {} for oidc user {}", local_id, info.sub);
+
+    Ok(local_id)
+}
+
+fn split_expiry(raw: &[u8]) -> Option<(u64, &[u8])> {
+    (raw.len() >= size_of::<u64>()).then(|| {
+        let (expires_at, rest) = raw.split_at(size_of::<u64>());
+        (deser_id(expires_at), rest)
+    })
+}
+
+fn unexpired_str(raw: Option<&[u8]>, now: u64) -> Option<SmolStr> {
+    raw.and_then(split_expiry).and_then(|(expires_at, value)| {
+        (expires_at > now).then(|| SmolStr::new(String::from_utf8_lossy(value)))
+    })
+}
+
+impl AuthTree {
+    /// Creates the `state` parameter and the PKCE code verifier for an auth flow.
+    pub async fn create_oidc_state_logic(
+        &self,
+        auth_id: &str,
+        expires_at: u64,
+    ) -> Result<(SmolStr, SmolStr), ServerError> {
+        let state = gen_rand_str::<32>();
+        let code_verifier = gen_rand_str::<64>();
+
+        let mut batch = Batch::default();
+        batch.insert(
+            oidc_state_key(&state),
+            [&expires_at.to_be_bytes()[..], auth_id.as_bytes()].concat(),
+        );
+        batch.insert(
+            oidc_verifier_key(auth_id),
+            [&expires_at.to_be_bytes()[..], code_verifier.as_bytes()].concat(),
+        );
+        self.apply_batch(batch).await?;
+
+        Ok((state, code_verifier))
+    }
+
+    /// Gets the auth ID a `state` parameter was created for. States can only be used once.
+    pub async fn take_oidc_state_logic(
+        &self,
+        state: &str,
+        now: u64,
+    ) -> Result<Option<SmolStr>, ServerError> {
+        let raw = self.remove(oidc_state_key(state)).await?;
+        Ok(unexpired_str(raw.as_deref(), now))
+    }
+
+    /// Gets the PKCE code verifier of an auth flow. It can only be used once.
+    pub async fn take_oidc_verifier_logic(
+        &self,
+        auth_id: &str,
+        now: u64,
+    ) -> Result<Option<SmolStr>, ServerError> {
+        let raw = self.remove(oidc_verifier_key(auth_id)).await?;
+        Ok(unexpired_str(raw.as_deref(), now))
+    }
+
+    /// Removes states and code verifiers of OIDC logins that were never finished.
+    pub async fn expire_oidc_logic(&self, now: u64) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        for prefix in [OIDC_STATE_PREFIX, OIDC_VERIFIER_PREFIX] {
+            for res in self.scan_prefix(prefix).await {
+                let (key, value) = res?;
+                if split_expiry(&value).map_or(true, |(expires_at, _)| expires_at <= now) {
+                    batch.remove(key);
+                }
+            }
+        }
+        self.apply_batch(batch).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const CODE: &str = "test-code";
+    // requests are lowercased before they are matched
+    const CODE_VERIFIER: &str = "test-verifier";
+    const ACCESS_TOKEN: &str = "test-access-token";
+
+    /// Starts a minimal OIDC provider, returning its issuer URL.
+    async fn start_mock_provider() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let base = issuer.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0; 4096];
+                loop {
+                    let read = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..read]);
+                    let request = String::from_utf8_lossy(&buf);
+                    if let Some((head, body)) = request.split_once("\r\n\r\n") {
+                        let content_length = head
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())?
+                            })
+                            .unwrap_or(0);
+                        if body.len() >= content_length || read == 0 {
+                            break;
+                        }
+                    }
+                }
+
+                let request = String::from_utf8_lossy(&buf).to_lowercase();
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let (status, body) = match path {
+                    "/.well-known/openid-configuration" => (
+                        "200 OK",
+                        serde_json::json!({
+                            "issuer": base,
+                            "authorization_endpoint": format!("{}/authorize", base),
+                            "token_endpoint": format!("{}/token", base),
+                            "userinfo_endpoint": format!("{}/userinfo", base),
+                        })
+                        .to_string(),
+                    ),
+                    "/token"
+                        if request.contains(&format!("code={}", CODE))
+                            && request.contains(&format!("code_verifier={}", CODE_VERIFIER)) =>
+                    {
+                        (
+                            "200 OK",
+                            serde_json::json!({
+                                "access_token": ACCESS_TOKEN,
+                                "token_type": "Bearer",
+                            })
+                            .to_string(),
+                        )
+                    }
+                    "/userinfo"
+                        if request.contains(&format!("authorization: bearer {}", ACCESS_TOKEN)) =>
+                    {
+                        (
+                            "200 OK",
+                            serde_json::json!({
+                                "sub": "user-1",
+                                "preferred_username": "alice",
+                            })
+                            .to_string(),
+                        )
+                    }
+                    _ => ("400 Bad Request", "{}".to_string()),
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        issuer
+    }
+
+    #[tokio::test]
+    async fn authorization_code_flow() {
+        let config = OidcConfig {
+            issuer: start_mock_provider().await,
+            client_id: "scherzo".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: format!("http://localhost:2289{}", OIDC_CALLBACK_PATH),
+            scopes: vec!["openid".to_string(), "profile".to_string()],
+        };
+        let http = HttpClient::new();
+
+        let metadata = discover(&http, &config).await.unwrap();
+        let url = authorization_url(&metadata, &config, "some-state", CODE_VERIFIER);
+        assert!(url.starts_with(&format!("{}/authorize?", config.issuer)));
+        assert!(url.contains("state=some-state"));
+        assert!(url.contains("scope=openid%20profile"));
+        assert!(url.contains(&format!(
+            "code_challenge={}&code_challenge_method=S256",
+            code_challenge(CODE_VERIFIER)
+        )));
+
+        let info = exchange_code(&http, &config, &metadata, CODE, CODE_VERIFIER)
+            .await
+            .unwrap();
+        assert_eq!(info.sub, "user-1");
+        assert_eq!(info.username(), "alice");
+
+        assert!(
+            exchange_code(&http, &config, &metadata, "wrong-code", CODE_VERIFIER)
+                .await
+                .is_err()
+        );
+        assert!(
+            exchange_code(&http, &config, &metadata, CODE, "wrong-verifier")
+                .await
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn oidc_users_are_created_once() {
+        let db = db::open_temp();
+        let profile_tree = ProfileTree::new(&db).await.unwrap();
+        let info = UserInfo {
+            sub: "user-1".to_string(),
+            preferred_username: None,
+            name: Some("Alice".to_string()),
+        };
+
+        let user_id = get_or_create_oidc_user(&profile_tree, "issuer", &info)
+            .await
+            .unwrap();
+        let again = get_or_create_oidc_user(&profile_tree, "issuer", &info)
+            .await
+            .unwrap();
+        assert_eq!(user_id, again);
+        assert_eq!(
+            profile_tree
+                .oidc_to_local_id("issuer", "user-1")
+                .await
+                .unwrap(),
+            Some(user_id)
+        );
+
+        // another user with the same name doesn't take over the name
+        let other = UserInfo {
+            sub: "user-2".to_string(),
+            ..info
+        };
+        let other_id = get_or_create_oidc_user(&profile_tree, "issuer", &other)
+            .await
+            .unwrap();
+        assert_ne!(user_id, other_id);
+        assert_eq!(
+            profile_tree
+                .get_user_id_by_name("Alice", None)
+                .await
+                .unwrap(),
+            Some(user_id)
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Registration tokens that admins create for letting users register while
+//! registration is disabled. Unlike single use tokens, they can be used a
+//! number of times, can expire, and can make users join guilds.
+
+use super::*;
+
+/// Describes a registration token in a line, for listing them to admins.
+pub fn describe_registration_token(token_id: u64, info: &RegistrationToken) -> String {
+    let max_uses = info
+        .max_uses
+        .map_or_else(|| "unlimited".to_string(), |max_uses| max_uses.to_string());
+    let expires_at = info
+        .expires_at
+        .map_or_else(|| "never".to_string(), |expires_at| expires_at.to_string());
+    let creator = info
+        .creator_id
+        .map_or_else(|| "scherzo_cmd".to_string(), |user_id| user_id.to_string());
+    format!(
+        "{}: used {}/{} times, expires at {}, created by {} at {}, joins guilds {:?}",
+        token_id, info.uses, max_uses, expires_at, creator, info.created_at, info.guild_ids
+    )
+}
+
+impl AuthTree {
+    /// Creates a registration token, returning its ID and the token.
+    pub async fn create_registration_token_logic(
+        &self,
+        creator_id: Option<u64>,
+        max_uses: Option<u32>,
+        expires_at: Option<u64>,
+        guild_ids: Vec<u64>,
+        now: u64,
+    ) -> Result<(u64, SmolStr), ServerError> {
+        let mut token_id = gen_rand_u64();
+        while self
+            .contains_key(make_registration_token_key(token_id))
+            .await?
+        {
+            token_id = gen_rand_u64();
+        }
+
+        let mut token = gen_rand_inline_str();
+        let mut token_hashed = hash_token(token.as_bytes());
+        while self
+            .contains_key(registration_token_hash_key(token_hashed.as_bytes()))
+            .await?
+        {
+            token = gen_rand_inline_str();
+            token_hashed = hash_token(token.as_bytes());
+        }
+
+        let info = RegistrationToken {
+            token_hashed: token_hashed.clone(),
+            creator_id,
+            created_at: now,
+            expires_at,
+            max_uses,
+            uses: 0,
+            guild_ids,
+        };
+
+        let mut batch = Batch::default();
+        batch.insert(
+            registration_token_hash_key(token_hashed.as_bytes()),
+            token_id.to_be_bytes(),
+        );
+        batch.insert(make_registration_token_key(token_id), rkyv_ser(&info));
+        self.apply_batch(batch).await?;
+
+        Ok((token_id, token))
+    }
+
+    async fn get_registration_token_id(&self, token: &[u8]) -> Result<Option<u64>, ServerError> {
+        let token_hashed = hash_token(token);
+        Ok(self
+            .get(registration_token_hash_key(token_hashed.as_bytes()))
+            .await?
+            .map(deser_id))
+    }
+
+    /// Gets a registration token if it can still be used.
+    pub async fn check_registration_token_logic(
+        &self,
+        token: &[u8],
+        now: u64,
+    ) -> Result<Option<(u64, RegistrationToken)>, ServerError> {
+        let Some(token_id) = self.get_registration_token_id(token).await? else {
+            return Ok(None);
+        };
+        Ok(self
+            .get(make_registration_token_key(token_id))
+            .await?
+            .map(deser_registration_token)
+            .filter(|info| info.is_usable(now))
+            .map(|info| (token_id, info)))
+    }
+
+    /// Uses a registration token, returning the guilds the user should join.
+    /// Returns `None` if there is no such token, and errors with
+    /// [`ServerError::InvalidRegistrationToken`] if it can't be used anymore.
+    pub async fn use_registration_token_logic(
+        &self,
+        token: &[u8],
+        now: u64,
+    ) -> Result<Option<Vec<u64>>, ServerError> {
+        let Some(token_id) = self.get_registration_token_id(token).await? else {
+            return Ok(None);
+        };
+        let key = make_registration_token_key(token_id);
+
+        // the uses are only counted if the token wasn't changed in between, so
+        // concurrent registrations can't use it more than `max_uses` times
+        loop {
+            let Some(raw) = self.get(key).await? else {
+                return Ok(None);
+            };
+            let mut info = deser_registration_token(&raw);
+
+            if info.is_usable(now).not() {
+                self.revoke_registration_token_logic(token_id).await?;
+                return Err(ServerError::InvalidRegistrationToken);
+            }
+
+            info.uses += 1;
+            if info.is_usable(now) {
+                let new = rkyv_ser(&info);
+                if self
+                    .compare_and_swap(key, Some(raw.as_ref()), Some(new.as_ref()))
+                    .await?
+                {
+                    return Ok(Some(info.guild_ids));
+                }
+            } else if self.compare_and_swap(key, Some(raw.as_ref()), None).await? {
+                self.remove(registration_token_hash_key(info.token_hashed.as_bytes()))
+                    .await?;
+                return Ok(Some(info.guild_ids));
+            }
+        }
+    }
+
+    /// Lists registration tokens that can still be used, sorted by creation time.
+    pub async fn list_registration_tokens_logic(
+        &self,
+        now: u64,
+    ) -> Result<Vec<(u64, RegistrationToken)>, ServerError> {
+        let mut tokens = Vec::new();
+        for res in self.scan_prefix(REG_TOKEN_PREFIX).await {
+            let (key, value) = res?;
+            let info = deser_registration_token(value);
+            if info.is_usable(now) {
+                tokens.push((deser_id(&key[REG_TOKEN_PREFIX.len()..]), info));
+            }
+        }
+        tokens.sort_unstable_by_key(|(_, info)| info.created_at);
+        Ok(tokens)
+    }
+
+    /// Deletes a registration token. Returns `false` if it didn't exist.
+    pub async fn revoke_registration_token_logic(
+        &self,
+        token_id: u64,
+    ) -> Result<bool, ServerError> {
+        let key = make_registration_token_key(token_id);
+        let Some(raw) = self.get(key).await? else {
+            return Ok(false);
+        };
+        let info = deser_registration_token(raw);
+
+        let mut batch = Batch::default();
+        batch.remove(key);
+        batch.remove(registration_token_hash_key(info.token_hashed.as_bytes()));
+        self.apply_batch(batch).await?;
+
+        Ok(true)
+    }
+
+    /// Deletes registration tokens that expired.
+    pub async fn expire_registration_tokens_logic(&self, now: u64) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        for res in self.scan_prefix(REG_TOKEN_PREFIX).await {
+            let (key, value) = res?;
+            let info = deser_registration_token(value);
+            if info.is_usable(now).not() {
+                batch.remove(registration_token_hash_key(info.token_hashed.as_bytes()));
+                batch.remove(key);
+            }
+        }
+        self.apply_batch(batch).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn tokens_run_out_of_uses_and_expire() {
+        const NOW: u64 = 1_700_000_000;
+        const GUILD_ID: u64 = 1;
+
+        let db = db::open_temp();
+        let auth_tree = AuthTree::new(&db).await.unwrap();
+
+        let (_, token) = auth_tree
+            .create_registration_token_logic(Some(1), Some(2), None, vec![GUILD_ID], NOW)
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            let guild_ids = auth_tree
+                .use_registration_token_logic(token.as_bytes(), NOW)
+                .await
+                .unwrap();
+            assert_eq!(guild_ids, Some(vec![GUILD_ID]));
+        }
+        assert_eq!(
+            auth_tree
+                .use_registration_token_logic(token.as_bytes(), NOW)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let (token_id, token) = auth_tree
+            .create_registration_token_logic(None, None, Some(NOW + 60), Vec::new(), NOW)
+            .await
+            .unwrap();
+        let tokens = auth_tree.list_registration_tokens_logic(NOW).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].0, token_id);
+        assert!(matches!(
+            auth_tree
+                .use_registration_token_logic(token.as_bytes(), NOW + 60)
+                .await,
+            Err(ServerError::InvalidRegistrationToken)
+        ));
+        assert!(!auth_tree
+            .revoke_registration_token_logic(token_id)
+            .await
+            .unwrap());
+    }
+}
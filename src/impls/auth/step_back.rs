@@ -7,14 +7,12 @@ pub async fn handler(
     let req = req.into_message().await?;
     let auth_id = req.auth_id;
 
-    let prev_step;
-    let Some(mut step_stack) = svc.step_map.get_mut(auth_id.as_str()) else {
-        bail!(ServerError::InvalidAuthId);
-    };
+    let auth_tree = &svc.deps.auth_tree;
+    let now = get_time_secs();
+    let mut flow = auth_tree.get_auth_flow_logic(&auth_id, now).await?;
 
-    // Safety: step stack can never be empty [ref:step_stack_non_empty]
-    if unsafe { step_stack.last().unwrap_unchecked().can_go_back } {
-        step_stack.pop();
+    if flow.current_step().can_go_back && flow.steps.len() > 1 {
+        flow.steps.pop();
         tracing::debug!("auth session {} went to previous step", auth_id);
     } else {
         tracing::debug!(
@@ -23,10 +21,16 @@ pub async fn handler(
         );
     }
 
-    // Safety: step stack can never be empty [ref:step_stack_non_empty]
-    prev_step = unsafe { step_stack.last().unwrap_unchecked().clone() };
+    let prev_step = flow.current_step().clone();
 
-    drop(step_stack);
+    auth_tree
+        .update_auth_flow_logic(
+            &auth_id,
+            &mut flow,
+            svc.deps.config.policy.sessions.auth_flow_ttl,
+            now,
+        )
+        .await?;
 
     if let Some(chan) = svc.send_step.get(auth_id.as_str()) {
         tracing::debug!("sending prev step to {} stream", auth_id);
@@ -9,12 +9,16 @@ pub async fn handler(
 
     let auth_id: SmolStr = msg.auth_id.into();
 
-    if svc.step_map.contains_key(auth_id.as_str()) {
-        tracing::debug!("auth id {} validated", auth_id);
-    } else {
-        tracing::error!("auth id {} is not valid", auth_id);
-        return Err(ServerError::InvalidAuthId.into());
+    if let Err(err) = svc
+        .deps
+        .auth_tree
+        .get_auth_flow_logic(&auth_id, get_time_secs())
+        .await
+    {
+        tracing::error!("auth id {} is not valid: {}", auth_id, err);
+        return Err(err.into());
     }
+    tracing::debug!("auth id {} validated", auth_id);
 
     tracing::debug!("creating stream for id {}", auth_id);
 
@@ -0,0 +1,294 @@
+//! Time-based one-time passwords ([RFC 6238]) used as a second factor when
+//! logging in.
+//!
+//! [RFC 6238]: https://datatracker.ietf.org/doc/html/rfc6238
+
+use rand::RngCore;
+use sha1::{Digest, Sha1};
+
+use super::*;
+
+pub const TOTP_STEP_SECS: u64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+const TOTP_SECRET_LEN: usize = 20;
+/// How many steps before and after the current one are accepted, to allow
+/// for some clock drift between the server and the authenticator app.
+const TOTP_ALLOWED_SKEW: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+fn hmac_sha1(key: &[u8], msg: &[u8]) -> [u8; 20] {
+    const BLOCK_LEN: usize = 64;
+
+    let mut block = [0_u8; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = Sha1::new()
+        .chain_update(block.map(|b| b ^ 0x36))
+        .chain_update(msg)
+        .finalize();
+    Sha1::new()
+        .chain_update(block.map(|b| b ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Generates the code for a time step.
+pub fn totp_code_at(secret: &[u8], step: u64) -> u32 {
+    let hash = hmac_sha1(secret, &step.to_be_bytes());
+    let offset = (hash[19] & 0xf) as usize;
+    let truncated = u32::from_be_bytes(hash[offset..offset + 4].try_into().expect("len is 4"));
+    (truncated & 0x7fff_ffff) % 10_u32.pow(TOTP_DIGITS)
+}
+
+/// Checks a code at `time_secs`, returning the time step it was valid for.
+pub fn verify_totp_code(secret: &[u8], code: &str, time_secs: u64) -> Option<u64> {
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+
+    let current = time_secs / TOTP_STEP_SECS;
+    (current.saturating_sub(TOTP_ALLOWED_SKEW)..=current + TOTP_ALLOWED_SKEW)
+        .find(|step| totp_code_at(secret, *step) == code)
+}
+
+/// Encodes data with the [RFC 4648] base32 alphabet, without padding.
+///
+/// [RFC 4648]: https://datatracker.ietf.org/doc/html/rfc4648#section-6
+pub fn base32_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer = 0_u16;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// Creates an `otpauth://` URI that authenticator apps can import the secret from.
+pub fn totp_provisioning_uri(secret: &[u8], issuer: &str, account: &str) -> String {
+    let issuer = urlencoding::encode(issuer);
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = issuer,
+        account = urlencoding::encode(account),
+        secret = base32_encode(secret),
+        digits = TOTP_DIGITS,
+        period = TOTP_STEP_SECS,
+    )
+}
+
+impl AuthTree {
+    pub async fn get_totp_logic(&self, user_id: u64) -> Result<Option<TotpInfo>, ServerError> {
+        Ok(self.get(make_totp_key(user_id)).await?.map(deser_totp))
+    }
+
+    pub async fn is_totp_enabled_logic(&self, user_id: u64) -> Result<bool, ServerError> {
+        Ok(self
+            .get_totp_logic(user_id)
+            .await?
+            .map_or(false, |totp| totp.confirmed))
+    }
+
+    /// Generates a new secret for a user. It has to be confirmed with
+    /// [`AuthTree::confirm_totp_logic`] before it's used when logging in.
+    pub async fn begin_totp_enrollment_logic(&self, user_id: u64) -> Result<Vec<u8>, ServerError> {
+        if self.is_totp_enabled_logic(user_id).await? {
+            return Err(ServerError::TotpAlreadyEnabled);
+        }
+
+        let mut secret = vec![0; TOTP_SECRET_LEN];
+        rand::thread_rng().fill_bytes(&mut secret);
+
+        let totp = TotpInfo {
+            secret: secret.clone(),
+            confirmed: false,
+            last_used_step: 0,
+            recovery_codes: Vec::new(),
+        };
+        self.insert(make_totp_key(user_id), rkyv_ser(&totp)).await?;
+
+        Ok(secret)
+    }
+
+    /// Enables TOTP for a user if the code is valid for the secret generated
+    /// while enrolling. Returns the recovery codes of the user.
+    pub async fn confirm_totp_logic(
+        &self,
+        user_id: u64,
+        code: &str,
+        time_secs: u64,
+    ) -> Result<Vec<SmolStr>, ServerError> {
+        let mut totp = self
+            .get_totp_logic(user_id)
+            .await?
+            .ok_or(ServerError::TotpNotEnrolled)?;
+        if totp.confirmed {
+            return Err(ServerError::TotpAlreadyEnabled);
+        }
+
+        let step =
+            verify_totp_code(&totp.secret, code, time_secs).ok_or(ServerError::WrongTotpCode)?;
+
+        let recovery_codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| gen_rand_str::<10>())
+            .collect::<Vec<_>>();
+        totp.confirmed = true;
+        totp.last_used_step = step;
+        totp.recovery_codes = recovery_codes
+            .iter()
+            .map(|code| hash_token(code.as_bytes()))
+            .collect();
+        self.insert(make_totp_key(user_id), rkyv_ser(&totp)).await?;
+
+        Ok(recovery_codes)
+    }
+
+    /// Checks a TOTP code or a recovery code of a user. Used recovery codes
+    /// are removed, and TOTP codes can't be used twice.
+    pub async fn verify_totp_logic(
+        &self,
+        user_id: u64,
+        code: &str,
+        time_secs: u64,
+    ) -> Result<(), ServerError> {
+        let mut totp = match self.get_totp_logic(user_id).await? {
+            Some(totp) if totp.confirmed => totp,
+            _ => return Err(ServerError::TotpNotEnrolled),
+        };
+
+        match verify_totp_code(&totp.secret, code, time_secs) {
+            Some(step) if step > totp.last_used_step => totp.last_used_step = step,
+            _ => {
+                let hashed = hash_token(code);
+                let index = totp
+                    .recovery_codes
+                    .iter()
+                    .position(|recovery_code| *recovery_code == hashed)
+                    .ok_or(ServerError::WrongTotpCode)?;
+                totp.recovery_codes.remove(index);
+            }
+        }
+
+        self.insert(make_totp_key(user_id), rkyv_ser(&totp)).await?;
+
+        Ok(())
+    }
+
+    /// Disables TOTP for a user. Returns `false` if it wasn't set up.
+    pub async fn reset_totp_logic(&self, user_id: u64) -> Result<bool, ServerError> {
+        Ok(self.remove(make_totp_key(user_id)).await?.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors from RFC 6238 appendix B, truncated to 6 digits
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+    const RFC_VECTORS: [(u64, &str); 5] = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+    ];
+
+    #[test]
+    fn codes_match_rfc_vectors() {
+        for (time, code) in RFC_VECTORS {
+            assert_eq!(
+                format!("{:06}", totp_code_at(RFC_SECRET, time / TOTP_STEP_SECS)),
+                code
+            );
+            assert_eq!(
+                verify_totp_code(RFC_SECRET, code, time),
+                Some(time / TOTP_STEP_SECS)
+            );
+        }
+    }
+
+    #[test]
+    fn codes_are_valid_for_one_step_of_skew() {
+        let (time, code) = RFC_VECTORS[3];
+        assert!(verify_totp_code(RFC_SECRET, code, time + TOTP_STEP_SECS).is_some());
+        assert!(verify_totp_code(RFC_SECRET, code, time - TOTP_STEP_SECS).is_some());
+        assert!(verify_totp_code(RFC_SECRET, code, time + 3 * TOTP_STEP_SECS).is_none());
+        assert!(verify_totp_code(RFC_SECRET, "+05924", time).is_none());
+    }
+
+    #[test]
+    fn base32_matches_rfc_vectors() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"fo"), "MZXQ");
+        assert_eq!(base32_encode(b"foo"), "MZXW6");
+        assert_eq!(base32_encode(b"foob"), "MZXW6YQ");
+        assert_eq!(base32_encode(b"fooba"), "MZXW6YTB");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn enroll_and_verify() {
+        const USER_ID: u64 = 1;
+        const TIME: u64 = 1_700_000_000;
+
+        let db = db::open_temp();
+        let auth_tree = AuthTree::new(&db).await.unwrap();
+
+        let secret = auth_tree
+            .begin_totp_enrollment_logic(USER_ID)
+            .await
+            .unwrap();
+        assert!(!auth_tree.is_totp_enabled_logic(USER_ID).await.unwrap());
+
+        let code = |time: u64| format!("{:06}", totp_code_at(&secret, time / TOTP_STEP_SECS));
+        let recovery_codes = auth_tree
+            .confirm_totp_logic(USER_ID, &code(TIME), TIME)
+            .await
+            .unwrap();
+        assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+        assert!(auth_tree.is_totp_enabled_logic(USER_ID).await.unwrap());
+
+        // the code used for confirming can't be used again
+        let later = TIME + TOTP_STEP_SECS;
+        assert!(auth_tree
+            .verify_totp_logic(USER_ID, &code(TIME), TIME)
+            .await
+            .is_err());
+        auth_tree
+            .verify_totp_logic(USER_ID, &code(later), later)
+            .await
+            .unwrap();
+
+        // recovery codes work only once
+        auth_tree
+            .verify_totp_logic(USER_ID, &recovery_codes[0], later)
+            .await
+            .unwrap();
+        assert!(auth_tree
+            .verify_totp_logic(USER_ID, &recovery_codes[0], later)
+            .await
+            .is_err());
+
+        assert!(auth_tree.reset_totp_logic(USER_ID).await.unwrap());
+        assert!(!auth_tree.is_totp_enabled_logic(USER_ID).await.unwrap());
+    }
+}
@@ -0,0 +1,309 @@
+//! Audit logs of guilds, which record who did which moderation action. New
+//! entries are sent to users who can view the audit log through their event
+//! streams, as [`AUDIT_LOG_ACTION_ID`] actions since the protocol doesn't have
+//! an event for them. They are also posted to the audit log channel of a guild
+//! if it has one.
+
+use super::*;
+
+/// Header clients can set on moderation requests to give a reason for the
+/// action, which is saved in the audit log entry.
+pub const AUDIT_LOG_REASON_HEADER: &str = "scherzo-audit-log-reason";
+/// Permission node needed for viewing the audit log of a guild.
+pub const AUDIT_LOG_VIEW: &str = "audit-log.view";
+/// Maximum number of audit log entries that can be fetched at once.
+pub const MAX_AUDIT_LOG_ENTRIES: u64 = 100;
+/// Action ID of the `ActionPerformed` events new audit log entries are sent
+/// as. The input of their payload is the entry in JSON, in the same format as
+/// the audit log REST endpoint returns it.
+pub const AUDIT_LOG_ACTION_ID: &str = "scherzo.audit-log-entry";
+
+/// Gets the reason set in the [`AUDIT_LOG_REASON_HEADER`] header of a request.
+pub fn get_audit_log_reason<T>(request: &Request<T>) -> Option<String> {
+    request
+        .header_map()
+        .and_then(|headers| headers.get(AUDIT_LOG_REASON_HEADER))
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|reason| reason.is_empty().not())
+        .map(ToString::to_string)
+}
+
+/// Describes an audit log entry in a line, for posting it to the audit log
+/// channel.
+pub fn describe_audit_log_entry(entry_id: u64, entry: &AuditLogEntry) -> String {
+    let mut text = format!(
+        "#{}: {} by {} on {}",
+        entry_id,
+        entry.action.as_str(),
+        entry.actor_id,
+        entry.target_id
+    );
+    if let Some(channel_id) = entry.channel_id {
+        text.push_str(&format!(" in channel {}", channel_id));
+    }
+    if let Some(reason) = &entry.reason {
+        text.push_str(&format!(", reason: {}", reason));
+    }
+    text
+}
+
+/// Converts an audit log entry to JSON, for the REST endpoint and events.
+pub fn audit_log_entry_json(entry_id: u64, entry: &AuditLogEntry) -> serde_json::Value {
+    serde_json::json!({
+        "entry_id": entry_id,
+        "actor_id": entry.actor_id,
+        "action": entry.action.as_str(),
+        "target_id": entry.target_id,
+        "channel_id": entry.channel_id,
+        "reason": entry.reason,
+        "created_at": entry.created_at,
+    })
+}
+
+impl ChatTree {
+    /// Adds an entry to the audit log of a guild, returning its ID.
+    pub async fn append_audit_log_logic(
+        &self,
+        guild_id: u64,
+        entry: &AuditLogEntry,
+    ) -> Result<u64, ServerError> {
+        // the ID is claimed before the entry is saved, so that concurrent
+        // actions can't get the same one
+        let entry_id = self
+            .update_and_fetch(make_audit_log_seq_key(guild_id), |raw| {
+                let entry_id = raw.map_or(0, deser_id) + 1;
+                Some(entry_id.to_be_bytes().to_vec())
+            })
+            .await?
+            .map_or(0, deser_id);
+
+        self.insert(make_audit_log_key(guild_id, entry_id), rkyv_ser(entry))
+            .await?;
+
+        Ok(entry_id)
+    }
+
+    /// Gets at most `limit` audit log entries of a guild that are older than
+    /// the entry `before`, newest first. Starts from the newest entry if
+    /// `before` is `None`.
+    pub async fn get_audit_log_logic(
+        &self,
+        guild_id: u64,
+        before: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<(u64, AuditLogEntry)>, ServerError> {
+        let to = before.map_or(u64::MAX, |before| before.saturating_sub(1));
+        if to == 0 {
+            return Ok(Vec::new());
+        }
+
+        let prefix_len = make_audit_log_prefix(guild_id).len();
+        let from_key = make_audit_log_key(guild_id, 1);
+        let to_key = make_audit_log_key(guild_id, to);
+
+        self.chat_tree
+            .range((&from_key)..=(&to_key))
+            .await
+            .rev()
+            .take(limit as usize)
+            .map(|res| {
+                let (key, value) = res.map_err(ServerError::DbError)?;
+                let entry_id = deser_id(key.split_at(prefix_len).1);
+                Ok((entry_id, deser_audit_log_entry(value)))
+            })
+            .collect()
+    }
+
+    /// Gets the channel audit log entries of a guild are posted to.
+    pub async fn get_audit_log_channel_logic(
+        &self,
+        guild_id: u64,
+    ) -> Result<Option<u64>, ServerError> {
+        Ok(self
+            .get(make_audit_log_channel_key(guild_id))
+            .await?
+            .map(deser_id))
+    }
+
+    /// Sets the channel audit log entries of a guild are posted to. `None`
+    /// stops posting them.
+    pub async fn set_audit_log_channel_logic(
+        &self,
+        guild_id: u64,
+        channel_id: Option<u64>,
+    ) -> Result<(), ServerError> {
+        let key = make_audit_log_channel_key(guild_id);
+        match channel_id {
+            Some(channel_id) => {
+                self.does_channel_exist(guild_id, channel_id).await?;
+                self.insert(key, channel_id.to_be_bytes()).await?;
+            }
+            None => {
+                self.remove(key).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChatServer {
+    /// Records a moderation action in the audit log of a guild, sends it to
+    /// users who can view the audit log, and posts it to the audit log channel
+    /// of the guild if it has one.
+    pub async fn record_audit_log(
+        &self,
+        guild_id: u64,
+        actor_id: u64,
+        action: AuditAction,
+        target_id: u64,
+        channel_id: Option<u64>,
+        reason: Option<String>,
+    ) -> Result<(), ServerError> {
+        let chat_tree = &self.deps.chat_tree;
+
+        let entry = AuditLogEntry {
+            actor_id,
+            action,
+            target_id,
+            channel_id,
+            reason,
+            created_at: get_time_secs(),
+        };
+        let entry_id = chat_tree.append_audit_log_logic(guild_id, &entry).await?;
+
+        self.send_event_through_chan(
+            EventSub::Guild(guild_id),
+            stream_event::Event::ActionPerformed(stream_event::ActionPerformed {
+                guild_id,
+                channel_id: entry.channel_id.unwrap_or_default(),
+                message_id: 0,
+                action_id: AUDIT_LOG_ACTION_ID.to_string(),
+                payload: Some(ActionPayload {
+                    payload: Some(action_payload::Payload::Input(action_payload::Input {
+                        input: audit_log_entry_json(entry_id, &entry).to_string(),
+                    })),
+                }),
+                user_id: actor_id,
+            }),
+            Some(PermCheck::new(guild_id, None, AUDIT_LOG_VIEW, false)),
+            EventContext::empty(),
+        )
+        .await;
+
+        let log_channel_id = match chat_tree.get_audit_log_channel_logic(guild_id).await? {
+            Some(log_channel_id) => log_channel_id,
+            None => return Ok(()),
+        };
+        // the channel might have been deleted since it was set
+        if chat_tree
+            .does_channel_exist(guild_id, log_channel_id)
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+
+        // the action was done already, so failing to post it shouldn't fail the request
+        if let Err(err) = self
+            .post_audit_log_entry(guild_id, log_channel_id, entry_id, &entry)
+            .await
+        {
+            tracing::error!(
+                "couldnt post audit log entry {} of guild {}: {}",
+                entry_id,
+                guild_id,
+                err
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Posts an audit log entry to a channel. Only users who can view the
+    /// audit log receive the message.
+    async fn post_audit_log_entry(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        entry_id: u64,
+        entry: &AuditLogEntry,
+    ) -> ServerResult<()> {
+        let content = content::Content::TextMessage(content::TextContent {
+            content: Some(FormattedText::new(
+                describe_audit_log_entry(entry_id, entry),
+                Vec::new(),
+            )),
+        });
+        let (message_id, message) = self
+            .deps
+            .chat_tree
+            .send_with_system(guild_id, channel_id, content)
+            .await?;
+        self.send_event_through_chan(
+            EventSub::Guild(guild_id),
+            stream_event::Event::SentMessage(stream_event::MessageSent {
+                echo_id: None,
+                guild_id,
+                channel_id,
+                message_id,
+                message: Some(message),
+            }),
+            Some(PermCheck::new(
+                guild_id,
+                Some(channel_id),
+                AUDIT_LOG_VIEW,
+                false,
+            )),
+            EventContext::empty(),
+        )
+        .await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn audit_log_pages_go_back_in_time() {
+        const GUILD_ID: u64 = 1;
+
+        let db = db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+
+        for target_id in 1..=5 {
+            let entry = AuditLogEntry {
+                actor_id: 1,
+                action: AuditAction::Kick,
+                target_id,
+                channel_id: None,
+                reason: None,
+                created_at: 0,
+            };
+            chat_tree
+                .append_audit_log_logic(GUILD_ID, &entry)
+                .await
+                .unwrap();
+        }
+
+        let ids = |entries: Vec<(u64, AuditLogEntry)>| {
+            entries
+                .into_iter()
+                .map(|(entry_id, entry)| {
+                    assert_eq!(entry_id, entry.target_id);
+                    entry_id
+                })
+                .collect::<Vec<_>>()
+        };
+        let page = chat_tree.get_audit_log_logic(GUILD_ID, None, 3).await;
+        assert_eq!(ids(page.unwrap()), vec![5, 4, 3]);
+        let page = chat_tree.get_audit_log_logic(GUILD_ID, Some(3), 3).await;
+        assert_eq!(ids(page.unwrap()), vec![2, 1]);
+        let page = chat_tree.get_audit_log_logic(GUILD_ID, Some(1), 3).await;
+        assert!(page.unwrap().is_empty());
+    }
+}
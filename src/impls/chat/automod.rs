@@ -0,0 +1,523 @@
+//! Automod, which checks messages against the content rules of a guild when
+//! they are sent or edited, and acts on the messages that break them.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+
+use super::*;
+
+/// Permission node needed for viewing and changing the automod rules of a
+/// guild.
+pub const AUTOMOD_MANAGE: &str = "automod.manage";
+/// Permission node that makes automod ignore a user's messages.
+pub const AUTOMOD_BYPASS: &str = "automod.bypass";
+/// Maximum number of automod rules a guild can have.
+pub const MAX_AUTOMOD_RULES: usize = 25;
+/// How long after a message the same message counts as a repeat, in seconds.
+pub const DUPLICATE_MESSAGE_WINDOW: u64 = 60;
+/// How much of a message is quoted when it's posted to a log channel.
+const MAX_LOGGED_TEXT_LEN: usize = 200;
+/// Limits how big compiled regex rules can get, in bytes.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// How many links of a message are looked up as invites at most.
+const MAX_INVITE_LOOKUPS: usize = 10;
+
+lazy_static! {
+    static ref LINK_REGEX: Regex = Regex::new(r"(?i)\bhttps?://([^\s/?#]+)").unwrap();
+    /// Matches links of any scheme, capturing the last segment of their path,
+    /// which is where invite links have the invite ID.
+    static ref INVITE_LINK_REGEX: Regex =
+        Regex::new(r"(?i)\b[a-z][a-z0-9+.-]*://[^\s/?#]+(?:/[^\s/?#]+)*/([^\s/?#]+)").unwrap();
+}
+
+/// Compiled regex rules of a guild, by their pattern.
+pub type RuleRegexes = HashMap<String, Regex, ahash::RandomState>;
+
+fn compile_rule_regex(pattern: &str) -> Result<Regex, ServerError> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|err| ServerError::InvalidAutomodRule(err.to_string()))
+}
+
+/// Checks that a rule can be saved.
+fn validate_automod_rule(rule: &AutomodRule) -> Result<(), ServerError> {
+    let invalid = |reason: &str| Err(ServerError::InvalidAutomodRule(reason.to_string()));
+
+    if rule.name.trim().is_empty() {
+        return invalid("rule name can't be empty");
+    }
+    match &rule.trigger {
+        AutomodTrigger::BannedWords { words } if words.is_empty() => {
+            return invalid("banned words can't be empty");
+        }
+        AutomodTrigger::Regex { pattern } => {
+            compile_rule_regex(pattern)?;
+        }
+        AutomodTrigger::Duplicates { max_repeats: 0 } => {
+            return invalid("max repeats must be at least 1");
+        }
+        _ => {}
+    }
+    if let AutomodAction::Timeout { duration } = rule.action {
+        if duration == 0 || duration > MAX_TIMEOUT_DURATION {
+            return Err(ServerError::InvalidDuration);
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the invite IDs the links in some text could have.
+fn get_invite_link_ids(text: &str) -> impl Iterator<Item = &str> + '_ {
+    INVITE_LINK_REGEX
+        .captures_iter(text)
+        .filter_map(|captures| captures.get(1))
+        .map(|invite_id| invite_id.as_str())
+}
+
+/// Gets the hosts of the links in some text, lowercased.
+fn get_link_hosts(text: &str) -> impl Iterator<Item = String> + '_ {
+    LINK_REGEX.captures_iter(text).map(|captures| {
+        let authority = &captures[1];
+        let host = authority.rsplit('@').next().unwrap_or(authority);
+        let host = host.split(':').next().unwrap_or(host);
+        host.to_lowercase()
+    })
+}
+
+fn is_host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.to_lowercase();
+        host == allowed
+            || host
+                .strip_suffix(allowed.as_str())
+                .map_or(false, |sub| sub.ends_with('.'))
+    })
+}
+
+fn count_mentions(text: &FormattedText) -> usize {
+    text.format
+        .iter()
+        .filter(|format| {
+            matches!(
+                format.format,
+                Some(format::Format::UserMention(_) | format::Format::RoleMention(_))
+            )
+        })
+        .count()
+}
+
+fn hash_text(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ChatTree {
+    /// Gets the compiled regex of a rule of a guild, only compiling it the
+    /// first time it's used.
+    fn get_rule_regex(&self, guild_id: u64, pattern: &str) -> Result<Regex, ServerError> {
+        if let Some(regex) = self
+            .automod_regexes
+            .get(&guild_id)
+            .and_then(|regexes| regexes.get(pattern).cloned())
+        {
+            return Ok(regex);
+        }
+
+        let regex = compile_rule_regex(pattern)?;
+        let mut regexes = self.automod_regexes.entry(guild_id).or_default();
+        // rules changed on other nodes leave their old patterns behind
+        if regexes.len() >= MAX_AUTOMOD_RULES {
+            regexes.clear();
+        }
+        regexes.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+
+    pub async fn get_automod_rules_logic(
+        &self,
+        guild_id: u64,
+    ) -> Result<Vec<AutomodRule>, ServerError> {
+        Ok(self
+            .get(make_automod_rules_key(guild_id))
+            .await?
+            .map_or_else(Vec::new, db::chat::deser_automod_rules))
+    }
+
+    /// Replaces the automod rules of a guild.
+    pub async fn set_automod_rules_logic(
+        &self,
+        guild_id: u64,
+        rules: Vec<AutomodRule>,
+    ) -> Result<(), ServerError> {
+        if rules.len() > MAX_AUTOMOD_RULES {
+            return Err(ServerError::InvalidAutomodRule(format!(
+                "a guild can have at most {} rules",
+                MAX_AUTOMOD_RULES
+            )));
+        }
+        for rule in &rules {
+            validate_automod_rule(rule)?;
+            if let AutomodAction::LogToChannel { channel_id } = rule.action {
+                self.does_channel_exist(guild_id, channel_id).await?;
+            }
+        }
+
+        let key = make_automod_rules_key(guild_id);
+        if rules.is_empty() {
+            self.remove(key).await?;
+        } else {
+            self.insert(key, rkyv_ser(&rules)).await?;
+        }
+        self.automod_regexes.remove(&guild_id);
+
+        Ok(())
+    }
+
+    /// Counts how many times in a row a user sent the same text in a guild,
+    /// including this time. Each repeat must be sent within
+    /// [`DUPLICATE_MESSAGE_WINDOW`] of the last one.
+    pub async fn count_repeats_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        text: &str,
+        now: u64,
+    ) -> Result<u64, ServerError> {
+        let key = make_automod_repeats_key(guild_id, user_id);
+        let hash = hash_text(text);
+
+        let repeats = match self.get(key).await? {
+            Some(raw) => {
+                let mut values = raw.chunks_exact(size_of::<u64>()).map(deser_id);
+                let (last_hash, last_sent, count) = (
+                    values.next().unwrap_or_default(),
+                    values.next().unwrap_or_default(),
+                    values.next().unwrap_or_default(),
+                );
+                if last_hash == hash && now.saturating_sub(last_sent) <= DUPLICATE_MESSAGE_WINDOW {
+                    count + 1
+                } else {
+                    1
+                }
+            }
+            None => 1,
+        };
+
+        let value = [hash.to_be_bytes(), now.to_be_bytes(), repeats.to_be_bytes()].concat();
+        self.insert(key, value).await?;
+
+        Ok(repeats)
+    }
+
+    /// Checks whether a message in a guild matches the trigger of a rule.
+    /// `repeats` is how many times in a row the message was sent.
+    pub async fn matches_automod_trigger(
+        &self,
+        guild_id: u64,
+        trigger: &AutomodTrigger,
+        text: &FormattedText,
+        repeats: u64,
+    ) -> Result<bool, ServerError> {
+        let matches = match trigger {
+            AutomodTrigger::BannedWords { words } => {
+                let terms = search::split_search_terms(&text.text);
+                words
+                    .iter()
+                    .any(|word| terms.contains(word.to_lowercase().as_str()))
+            }
+            AutomodTrigger::Regex { pattern } => {
+                self.get_rule_regex(guild_id, pattern)?.is_match(&text.text)
+            }
+            AutomodTrigger::Links { allowed_hosts } => {
+                get_link_hosts(&text.text).any(|host| is_host_allowed(&host, allowed_hosts).not())
+            }
+            AutomodTrigger::Invites => {
+                // invite IDs are picked by users, so the links have to be
+                // looked up, but only so many of them
+                for invite_id in get_invite_link_ids(&text.text).take(MAX_INVITE_LOOKUPS) {
+                    if let Some(raw) = self.get(make_invite_key(invite_id)).await? {
+                        if db::deser_invite_entry_guild_id(&raw) != guild_id {
+                            return Ok(true);
+                        }
+                    }
+                }
+                false
+            }
+            AutomodTrigger::MentionSpam { max_mentions } => {
+                count_mentions(text) > *max_mentions as usize
+            }
+            AutomodTrigger::Duplicates { max_repeats } => repeats > u64::from(*max_repeats),
+        };
+        Ok(matches)
+    }
+}
+
+impl ChatServer {
+    /// Checks a message against the automod rules of its guild, before it's
+    /// sent or edited. `edited_message_id` is set for edits. Errors with
+    /// [`ServerError::BlockedByAutomod`] if the message must not go through.
+    pub async fn run_automod(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+        edited_message_id: Option<u64>,
+        text: &FormattedText,
+    ) -> ServerResult<()> {
+        let chat_tree = &self.deps.chat_tree;
+
+        let rules = chat_tree.get_automod_rules_logic(guild_id).await?;
+        if rules.is_empty() {
+            return Ok(());
+        }
+        let bypass = chat_tree
+            .check_perms(guild_id, Some(channel_id), user_id, AUTOMOD_BYPASS, false)
+            .await;
+        match bypass {
+            Ok(_) => return Ok(()),
+            Err(ServerError::NotEnoughPermissions { .. }) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let checks_repeats = rules
+            .iter()
+            .any(|rule| matches!(rule.trigger, AutomodTrigger::Duplicates { .. }));
+        // edits aren't new messages, so they can't be repeats
+        let repeats = if checks_repeats && edited_message_id.is_none() {
+            chat_tree
+                .count_repeats_logic(guild_id, user_id, &text.text, get_time_secs())
+                .await?
+        } else {
+            0
+        };
+
+        let mut blocked_by = None;
+        let mut deleted = false;
+        for rule in rules {
+            if chat_tree
+                .matches_automod_trigger(guild_id, &rule.trigger, text, repeats)
+                .await?
+                .not()
+            {
+                continue;
+            }
+
+            // the message is already rejected, so failing to act on it
+            // shouldn't change what the user gets back
+            let res = match rule.action {
+                AutomodAction::Block => Ok(()),
+                AutomodAction::DeleteAndWarn => {
+                    let message_id = edited_message_id.filter(|_| deleted.not());
+                    deleted = true;
+                    self.delete_and_warn(guild_id, channel_id, user_id, message_id, &rule.name)
+                        .await
+                }
+                AutomodAction::Timeout { duration } => self
+                    .timeout_user(
+                        guild_id,
+                        0,
+                        user_id,
+                        duration,
+                        Some(format!("automod rule {}", rule.name)),
+                    )
+                    .await
+                    .map_err(Into::into),
+                AutomodAction::LogToChannel {
+                    channel_id: log_channel_id,
+                } => {
+                    let quoted = text
+                        .text
+                        .chars()
+                        .take(MAX_LOGGED_TEXT_LEN)
+                        .collect::<String>();
+                    let log = format!(
+                        "user {} broke automod rule {} in channel {}: {}",
+                        user_id, rule.name, channel_id, quoted
+                    );
+                    if let Err(err) = self
+                        .post_automod_message(guild_id, log_channel_id, log)
+                        .await
+                    {
+                        tracing::error!(
+                            "couldnt log automod rule {} of guild {}: {}",
+                            rule.name,
+                            guild_id,
+                            err
+                        );
+                    }
+                    // logged messages are let through
+                    continue;
+                }
+            };
+            if let Err(err) = res {
+                tracing::error!(
+                    "couldnt act on automod rule {} of guild {}: {}",
+                    rule.name,
+                    guild_id,
+                    err
+                );
+            }
+
+            blocked_by.get_or_insert(rule.name);
+        }
+
+        match blocked_by {
+            Some(rule) => Err(ServerError::BlockedByAutomod { rule }.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes a message that was being edited, if `message_id` is set, and
+    /// warns its author in the channel.
+    async fn delete_and_warn(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+        message_id: Option<u64>,
+        rule_name: &str,
+    ) -> ServerResult<()> {
+        let chat_tree = &self.deps.chat_tree;
+
+        if let Some(message_id) = message_id {
+            let (message, key) = chat_tree
+                .get_message_logic(guild_id, channel_id, message_id)
+                .await?;
+            let mut batch = Batch::default();
+            batch.remove(key);
+            search::unindex_message(&mut batch, guild_id, channel_id, message_id, &message);
+            chat_tree
+                .chat_tree
+                .apply_batch(batch)
+                .await
+                .map_err(ServerError::DbError)?;
+
+            self.send_event_through_chan(
+                EventSub::Guild(guild_id),
+                stream_event::Event::DeletedMessage(stream_event::MessageDeleted {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                }),
+                Some(PermCheck::new(
+                    guild_id,
+                    Some(channel_id),
+                    "messages.view",
+                    false,
+                )),
+                EventContext::empty(),
+            )
+            .await;
+        }
+
+        let warning = format!(
+            "a message from user {} was removed by automod rule {}",
+            user_id, rule_name
+        );
+        self.post_automod_message(guild_id, channel_id, warning)
+            .await
+    }
+
+    /// Posts a message as the system user. Does nothing if the channel was
+    /// deleted.
+    async fn post_automod_message(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        text: String,
+    ) -> ServerResult<()> {
+        let chat_tree = &self.deps.chat_tree;
+        if chat_tree
+            .does_channel_exist(guild_id, channel_id)
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+
+        let content = content::Content::TextMessage(content::TextContent {
+            content: Some(FormattedText::new(text, Vec::new())),
+        });
+        let (message_id, message) = chat_tree
+            .send_with_system(guild_id, channel_id, content)
+            .await?;
+        self.send_event_through_chan(
+            EventSub::Guild(guild_id),
+            stream_event::Event::SentMessage(stream_event::MessageSent {
+                echo_id: None,
+                guild_id,
+                channel_id,
+                message_id,
+                message: Some(message),
+            }),
+            Some(PermCheck::new(
+                guild_id,
+                Some(channel_id),
+                "messages.view",
+                false,
+            )),
+            EventContext::empty(),
+        )
+        .await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_to_allowed_hosts_are_allowed() {
+        let allowed = ["example.org".to_string()];
+        let hosts = get_link_hosts(
+            "see https://docs.example.org/page and HTTP://user@Evil.com:8080/x or example.org",
+        )
+        .collect::<Vec<_>>();
+        assert_eq!(hosts, vec!["docs.example.org", "evil.com"]);
+        assert!(is_host_allowed(&hosts[0], &allowed));
+        assert!(is_host_allowed(&hosts[1], &allowed).not());
+        assert!(is_host_allowed("notexample.org", &allowed).not());
+    }
+
+    #[test]
+    fn invite_ids_are_taken_from_links() {
+        let ids = get_invite_link_ids(
+            "join https://chat.example.org/invite/abc or hmc://example.org/def but not ghi",
+        )
+        .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["abc", "def"]);
+    }
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn repeats_are_counted_in_a_window() {
+        const NOW: u64 = 1_700_000_000;
+        const GUILD_ID: u64 = 1;
+        const USER_ID: u64 = 2;
+
+        let db = db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+
+        let count = |text, now| chat_tree.count_repeats_logic(GUILD_ID, USER_ID, text, now);
+        assert_eq!(count("spam", NOW).await.unwrap(), 1);
+        assert_eq!(count("spam", NOW + 10).await.unwrap(), 2);
+        assert_eq!(count("spam", NOW + 20).await.unwrap(), 3);
+        assert_eq!(count("hello", NOW + 30).await.unwrap(), 1);
+        assert_eq!(
+            count("hello", NOW + 31 + DUPLICATE_MESSAGE_WINDOW)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
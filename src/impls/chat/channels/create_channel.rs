@@ -21,6 +21,16 @@ pub async fn handler(
         .check_perms(guild_id, None, user_id, "channels.manage.create", false)
         .await?;
 
+    if matches!(
+        chat_tree.get_guild_kind_logic(guild_id).await?,
+        guild_kind::Kind::Room(_) | guild_kind::Kind::DirectMessage(_)
+    ) {
+        bail!((
+            "h.cant-create-channel-in-room",
+            "rooms and direct messages can't have more than one channel"
+        ));
+    }
+
     if channel_name.is_empty() {
         bail!(("h.bad-channel-name", "channel name can't be empty"));
     }
@@ -56,7 +66,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((CreateChannelResponse { channel_id }).into_response())
 }
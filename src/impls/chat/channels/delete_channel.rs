@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<DeleteChannelRequest>,
 ) -> ServerResult<Response<DeleteChannelResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
 
     let DeleteChannelRequest {
         guild_id,
@@ -61,7 +62,18 @@ pub async fn handler(
         }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
+
+    svc.record_audit_log(
+        guild_id,
+        user_id,
+        AuditAction::ChannelDeleted,
+        channel_id,
+        Some(channel_id),
+        reason,
+    )
+    .await?;
 
     Ok((DeleteChannelResponse {}).into_response())
 }
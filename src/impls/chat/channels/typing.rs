@@ -34,7 +34,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((TypingResponse {}).into_response())
 }
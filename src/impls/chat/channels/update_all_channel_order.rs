@@ -52,7 +52,8 @@ pub async fn handler(
         }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((UpdateAllChannelOrderResponse {}).into_response())
 }
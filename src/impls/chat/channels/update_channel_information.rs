@@ -65,7 +65,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((UpdateChannelInformationResponse {}).into_response())
 }
@@ -41,7 +41,8 @@ pub async fn handler(
             }),
             None,
             EventContext::empty(),
-        );
+        )
+        .await;
     }
 
     Ok((UpdateChannelOrderResponse {}).into_response())
@@ -0,0 +1,606 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use dashmap::DashSet;
+use lazy_static::lazy_static;
+use sha3::{Digest, Sha3_256};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+};
+
+use crate::{
+    api::{
+        emote::StreamEvent as EmoteStreamEvent, exports::prost::bytes::Bytes,
+        profile::StreamEvent as ProfileStreamEvent,
+    },
+    config::EventBusConfig,
+};
+
+use super::*;
+
+/// Max amount of events buffered for a subscriber or a peer before they start lagging.
+const EVENT_BUFFER_LEN: usize = 2048;
+/// Frames bigger than this are rejected, no event should ever come close.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+const PEER_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Length of the random challenges nodes authenticate each other with.
+const CHALLENGE_LEN: usize = 32;
+const CHALLENGE_RESPONSE_LEN: usize = 32;
+
+/// Frame kinds sent to peers after authenticating
+const EVENT_FRAME: u8 = 0;
+const CANCEL_FRAME: u8 = 1;
+
+/// Fans out events to every event stream. Implementations can send events to
+/// other scherzo nodes, so that streams on all of them receive the events.
+pub trait EventBus: Send + Sync {
+    /// Sends an event to every subscriber.
+    fn send(&self, broadcast: Arc<EventBroadcast>);
+    /// Subscribes to all events, including the ones sent from other nodes.
+    fn subscribe(&self) -> broadcast::Receiver<Arc<EventBroadcast>>;
+    /// Amount of subscribers on this node.
+    fn receiver_count(&self) -> usize;
+    /// Tells event streams to close, including the ones on other nodes.
+    fn cancel(&self, cancel: StreamCancel);
+    /// Subscribes to stream cancellations, including the ones sent from other nodes.
+    fn subscribe_cancels(&self) -> broadcast::Receiver<StreamCancel>;
+}
+
+/// Creates the event bus the config asks for.
+pub fn make_event_bus(config: Option<&EventBusConfig>) -> EventSender {
+    match config {
+        Some(config) => std::sync::Arc::new(TcpEventBus::new(config)),
+        None => std::sync::Arc::new(LocalEventBus::new()),
+    }
+}
+
+/// Event bus that only delivers events inside this process.
+pub struct LocalEventBus {
+    sender: broadcast::Sender<Arc<EventBroadcast>>,
+    cancels: broadcast::Sender<StreamCancel>,
+}
+
+impl LocalEventBus {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUFFER_LEN).0,
+            cancels: broadcast::channel(EVENT_BUFFER_LEN).0,
+        }
+    }
+}
+
+impl Default for LocalEventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus for LocalEventBus {
+    fn send(&self, broadcast: Arc<EventBroadcast>) {
+        drop(self.sender.send(broadcast));
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<EventBroadcast>> {
+        self.sender.subscribe()
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    fn cancel(&self, cancel: StreamCancel) {
+        drop(self.cancels.send(cancel));
+    }
+
+    fn subscribe_cancels(&self) -> broadcast::Receiver<StreamCancel> {
+        self.cancels.subscribe()
+    }
+}
+
+/// Event bus that also sends every event and stream cancellation to the
+/// configured peers over TCP.
+///
+/// Events received from peers are only delivered locally and never forwarded,
+/// so every node must list all of the other nodes as its peers. Nodes prove
+/// to each other that they know the shared secret without sending it, but
+/// events are not encrypted, so peers should talk over a private network.
+pub struct TcpEventBus {
+    local: LocalEventBus,
+    peers: Vec<mpsc::Sender<Bytes>>,
+}
+
+impl TcpEventBus {
+    pub fn new(config: &EventBusConfig) -> Self {
+        let local = LocalEventBus::new();
+        let secret = Bytes::from(config.secret.clone());
+
+        tokio::spawn(run_listener(
+            config.listen,
+            secret.clone(),
+            local.sender.clone(),
+            local.cancels.clone(),
+        ));
+
+        let peers = config
+            .peers
+            .iter()
+            .map(|addr| {
+                let (tx, rx) = mpsc::channel(EVENT_BUFFER_LEN);
+                tokio::spawn(run_peer_connection(addr.clone(), secret.clone(), rx));
+                tx
+            })
+            .collect();
+
+        Self { local, peers }
+    }
+}
+
+impl TcpEventBus {
+    fn send_to_peers(&self, kind: u8, encode: impl FnOnce() -> Vec<u8>) {
+        if self.peers.is_empty() {
+            return;
+        }
+
+        let frame = Bytes::from([&[kind][..], &encode()].concat());
+        for peer in &self.peers {
+            if let Err(mpsc::error::TrySendError::Full(_)) = peer.try_send(frame.clone()) {
+                tracing::warn!("event bus peer is lagging behind, dropping event");
+            }
+        }
+    }
+}
+
+impl EventBus for TcpEventBus {
+    fn send(&self, broadcast: Arc<EventBroadcast>) {
+        self.send_to_peers(EVENT_FRAME, || encode_broadcast(&broadcast));
+        self.local.send(broadcast);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<EventBroadcast>> {
+        self.local.subscribe()
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.local.receiver_count()
+    }
+
+    fn cancel(&self, cancel: StreamCancel) {
+        self.send_to_peers(CANCEL_FRAME, || encode_cancel(cancel));
+        self.local.cancel(cancel);
+    }
+
+    fn subscribe_cancels(&self) -> broadcast::Receiver<StreamCancel> {
+        self.local.subscribe_cancels()
+    }
+}
+
+async fn run_listener(
+    addr: SocketAddr,
+    secret: Bytes,
+    local: broadcast::Sender<Arc<EventBroadcast>>,
+    cancels: broadcast::Sender<StreamCancel>,
+) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!("couldnt listen for event bus peers on {}: {}", addr, err);
+            return;
+        }
+    };
+    tracing::info!("listening for event bus peers on {}", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer_addr)) => {
+                let secret = secret.clone();
+                let local = local.clone();
+                let cancels = cancels.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = receive_from_peer(stream, &secret, &local, &cancels).await {
+                        tracing::warn!("event bus peer {} disconnected: {}", peer_addr, err);
+                    }
+                });
+            }
+            Err(err) => tracing::error!("couldnt accept event bus peer: {}", err),
+        }
+    }
+}
+
+async fn receive_from_peer(
+    stream: TcpStream,
+    secret: &[u8],
+    local: &broadcast::Sender<Arc<EventBroadcast>>,
+    cancels: &broadcast::Sender<StreamCancel>,
+) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    authenticate(&mut stream, secret, b"listener", b"peer").await?;
+
+    loop {
+        let frame = read_frame(&mut stream, MAX_FRAME_LEN).await?;
+        match frame.split_first() {
+            Some((&EVENT_FRAME, raw)) => match decode_broadcast(raw) {
+                Some(broadcast) => drop(local.send(Arc::new(broadcast))),
+                None => tracing::error!("couldnt decode event received from event bus peer"),
+            },
+            Some((&CANCEL_FRAME, raw)) => match decode_cancel(raw) {
+                Some(cancel) => drop(cancels.send(cancel)),
+                None => tracing::error!("couldnt decode cancel received from event bus peer"),
+            },
+            _ => tracing::error!("event bus peer sent an unknown frame"),
+        }
+    }
+}
+
+async fn run_peer_connection(addr: String, secret: Bytes, mut rx: mpsc::Receiver<Bytes>) {
+    loop {
+        match TcpStream::connect(&addr).await {
+            Ok(mut stream) => {
+                tracing::info!("connected to event bus peer {}", addr);
+                let res = async {
+                    authenticate(&mut stream, &secret, b"peer", b"listener").await?;
+                    while let Some(frame) = rx.recv().await {
+                        write_frame(&mut stream, &frame).await?;
+                    }
+                    io::Result::Ok(())
+                }
+                .await;
+
+                match res {
+                    // the event bus was dropped, so there is nothing left to send
+                    Ok(_) => return,
+                    Err(err) => {
+                        tracing::warn!("lost connection to event bus peer {}: {}", addr, err)
+                    }
+                }
+            }
+            Err(err) => tracing::warn!("couldnt connect to event bus peer {}: {}", addr, err),
+        }
+
+        tokio::time::sleep(PEER_RECONNECT_DELAY).await;
+    }
+}
+
+/// Both sides of a connection send a random challenge, then answer the
+/// challenge of the other side with a MAC of it keyed with the shared secret.
+/// The secret itself is never sent, and since the role of each side is part
+/// of the MAC, a node's answers can't be reflected back to it.
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    secret: &[u8],
+    role: &[u8],
+    peer_role: &[u8],
+) -> io::Result<()> {
+    let challenge: [u8; CHALLENGE_LEN] = rand::random();
+    write_frame(stream, &challenge).await?;
+    let peer_challenge = read_frame(stream, CHALLENGE_LEN).await?;
+
+    write_frame(stream, &challenge_response(secret, role, &peer_challenge)).await?;
+    let response = read_frame(stream, CHALLENGE_RESPONSE_LEN).await?;
+
+    let expected = challenge_response(secret, peer_role, &challenge);
+    if constant_time_eq(&response, &expected).not() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "peer doesn't know the shared secret",
+        ));
+    }
+
+    Ok(())
+}
+
+fn challenge_response(
+    secret: &[u8],
+    role: &[u8],
+    challenge: &[u8],
+) -> [u8; CHALLENGE_RESPONSE_LEN] {
+    // SHA-3 isn't vulnerable to length extension, so a keyed hash works as a MAC
+    Sha3_256::new()
+        .chain_update((secret.len() as u64).to_be_bytes())
+        .chain_update(secret)
+        .chain_update(role)
+        .chain_update(challenge)
+        .finalize()
+        .into()
+}
+
+/// Compares two byte strings in an amount of time that only depends on their length.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S, max_len: usize) -> io::Result<Vec<u8>> {
+    let len = stream.read_u32().await? as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame is too big",
+        ));
+    }
+    let mut frame = vec![0; len];
+    stream.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, frame: &[u8]) -> io::Result<()> {
+    stream.write_u32(frame.len() as u32).await?;
+    stream.write_all(frame).await
+}
+
+lazy_static! {
+    // permission nodes are a small, fixed set, so leaking them once is fine
+    static ref PERM_NODES: DashSet<&'static str, ahash::RandomState> =
+        DashSet::with_hasher(ahash::RandomState::new());
+}
+
+fn intern_perm_node(node: &str) -> &'static str {
+    if let Some(interned) = PERM_NODES.get(node) {
+        return *interned;
+    }
+    let interned: &'static str = Box::leak(node.to_string().into_boxed_str());
+    PERM_NODES.insert(interned);
+    interned
+}
+
+fn encode_broadcast(broadcast: &EventBroadcast) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    match broadcast.sub {
+        EventSub::Guild(guild_id) => {
+            buf.push(0);
+            buf.extend_from_slice(&guild_id.to_be_bytes());
+        }
+        EventSub::Homeserver => buf.push(1),
+        EventSub::Actions => buf.push(2),
+    }
+    // sequences start from 1, so we can use 0 for "not logged"
+    buf.extend_from_slice(&broadcast.sequence.unwrap_or(0).to_be_bytes());
+
+    match broadcast.perm_check {
+        Some(PermCheck {
+            guild_id,
+            channel_id,
+            check_for,
+            must_be_guild_owner,
+        }) => {
+            buf.push(1);
+            buf.extend_from_slice(&guild_id.to_be_bytes());
+            // channel IDs are never 0, so we can use it for "no channel"
+            buf.extend_from_slice(&channel_id.unwrap_or(0).to_be_bytes());
+            buf.push(must_be_guild_owner as u8);
+            buf.push(check_for.len() as u8);
+            buf.extend_from_slice(check_for.as_bytes());
+        }
+        None => buf.push(0),
+    }
+
+    buf.extend_from_slice(&(broadcast.context.user_ids.len() as u32).to_be_bytes());
+    for user_id in &broadcast.context.user_ids {
+        buf.extend_from_slice(&user_id.to_be_bytes());
+    }
+
+    let res = match &broadcast.event {
+        Event::Chat(event) => {
+            buf.push(0);
+            StreamEvent {
+                event: Some(event.clone()),
+            }
+            .encode(&mut buf)
+        }
+        Event::Emote(event) => {
+            buf.push(1);
+            EmoteStreamEvent {
+                event: Some(event.clone()),
+            }
+            .encode(&mut buf)
+        }
+        Event::Profile(event) => {
+            buf.push(2);
+            ProfileStreamEvent {
+                event: Some(event.clone()),
+            }
+            .encode(&mut buf)
+        }
+    };
+    res.expect("vec can grow, so encoding can't fail");
+
+    buf
+}
+
+fn encode_cancel(cancel: StreamCancel) -> Vec<u8> {
+    let mut buf = cancel.user_id.to_be_bytes().to_vec();
+    if let Some(session_id) = cancel.session_id {
+        buf.extend_from_slice(&session_id.to_be_bytes());
+    }
+    buf
+}
+
+fn decode_cancel(raw: &[u8]) -> Option<StreamCancel> {
+    let user_id = deser_id(raw.get(..size_of::<u64>())?);
+    match raw.get(size_of::<u64>()..)? {
+        [] => Some(StreamCancel::user(user_id)),
+        session_id if session_id.len() == size_of::<u64>() => {
+            Some(StreamCancel::session(user_id, deser_id(session_id)))
+        }
+        _ => None,
+    }
+}
+
+fn decode_broadcast(raw: &[u8]) -> Option<EventBroadcast> {
+    fn split_u64(raw: &[u8]) -> Option<(u64, &[u8])> {
+        let (id, rest) = (raw.len() >= size_of::<u64>()).then(|| raw.split_at(size_of::<u64>()))?;
+        Some((deser_id(id), rest))
+    }
+
+    let (sub_kind, raw) = raw.split_first()?;
+    let (sub, raw) = match sub_kind {
+        0 => {
+            let (guild_id, raw) = split_u64(raw)?;
+            (EventSub::Guild(guild_id), raw)
+        }
+        1 => (EventSub::Homeserver, raw),
+        2 => (EventSub::Actions, raw),
+        _ => return None,
+    };
+    let (sequence, raw) = split_u64(raw)?;
+
+    let (has_perm_check, raw) = raw.split_first()?;
+    let (perm_check, raw) = if *has_perm_check == 1 {
+        let (guild_id, raw) = split_u64(raw)?;
+        let (channel_id, raw) = split_u64(raw)?;
+        let (must_be_guild_owner, raw) = raw.split_first()?;
+        let (check_for_len, raw) = raw.split_first()?;
+        let check_for = raw.get(..*check_for_len as usize)?;
+        let raw = &raw[check_for.len()..];
+
+        let perm_check = PermCheck::new(
+            guild_id,
+            (channel_id != 0).then(|| channel_id),
+            intern_perm_node(std::str::from_utf8(check_for).ok()?),
+            *must_be_guild_owner == 1,
+        );
+        (Some(perm_check), raw)
+    } else {
+        (None, raw)
+    };
+
+    let user_count = u32::from_be_bytes(raw.get(..size_of::<u32>())?.try_into().ok()?) as usize;
+    let raw = &raw[size_of::<u32>()..];
+    let user_ids = raw.get(..user_count.checked_mul(size_of::<u64>())?)?;
+    let raw = &raw[user_ids.len()..];
+    let context = EventContext::new(db::make_u64_iter_logic(user_ids).collect());
+
+    let (event_kind, raw) = raw.split_first()?;
+    let event = match event_kind {
+        0 => Event::Chat(StreamEvent::decode(raw).ok()?.event?),
+        1 => Event::Emote(EmoteStreamEvent::decode(raw).ok()?.event?),
+        2 => Event::Profile(ProfileStreamEvent::decode(raw).ok()?.event?),
+        _ => return None,
+    };
+
+    let mut broadcast = EventBroadcast::new(sub, event, perm_check, context);
+    broadcast.sequence = (sequence != 0).then(|| sequence);
+    Some(broadcast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_roundtrip() {
+        let mut broadcast = EventBroadcast::new(
+            EventSub::Guild(1),
+            Event::Chat(stream_event::Event::DeletedMessage(
+                stream_event::MessageDeleted {
+                    guild_id: 1,
+                    channel_id: 2,
+                    message_id: 3,
+                },
+            )),
+            Some(PermCheck::new(1, Some(2), "messages.view", false)),
+            EventContext::new(vec![4, 5]),
+        );
+        broadcast.sequence = Some(6);
+
+        let decoded = decode_broadcast(&encode_broadcast(&broadcast)).expect("must decode");
+
+        assert_eq!(decoded.sub, broadcast.sub);
+        assert_eq!(decoded.sequence, Some(6));
+        assert!(matches!(
+            decoded.event,
+            Event::Chat(stream_event::Event::DeletedMessage(
+                stream_event::MessageDeleted {
+                    guild_id: 1,
+                    channel_id: 2,
+                    message_id: 3,
+                }
+            ))
+        ));
+        assert_eq!(decoded.context.user_ids, broadcast.context.user_ids);
+        let perm_check = decoded.perm_check.expect("must have perm check");
+        assert_eq!(perm_check.guild_id, 1);
+        assert_eq!(perm_check.channel_id, Some(2));
+        assert_eq!(perm_check.check_for, "messages.view");
+        assert!(perm_check.must_be_guild_owner.not());
+    }
+
+    #[tokio::test]
+    async fn peers_must_share_the_secret() {
+        async fn handshake(peer_secret: &[u8]) -> (io::Result<()>, io::Result<()>) {
+            let (mut listener, mut peer) = tokio::io::duplex(1024);
+            tokio::join!(
+                authenticate(&mut listener, b"secret", b"listener", b"peer"),
+                authenticate(&mut peer, peer_secret, b"peer", b"listener"),
+            )
+        }
+
+        let (listener, peer) = handshake(b"secret").await;
+        assert!(listener.is_ok() && peer.is_ok());
+        let (listener, peer) = handshake(b"not the secret").await;
+        assert!(listener.is_err() && peer.is_err());
+    }
+
+    #[tokio::test]
+    async fn events_reach_other_nodes() {
+        fn bind_free() -> std::net::TcpListener {
+            std::net::TcpListener::bind("127.0.0.1:0").expect("must get a free port")
+        }
+        fn make_bus(listen: SocketAddr, peer: SocketAddr) -> TcpEventBus {
+            TcpEventBus::new(&EventBusConfig {
+                listen,
+                peers: vec![peer.to_string()],
+                secret: "secret".to_string(),
+                run_periodic_tasks: true,
+            })
+        }
+
+        // both ports are held until they're known, so they can't be the same
+        let (free_a, free_b) = (bind_free(), bind_free());
+        let addr_a = free_a.local_addr().unwrap();
+        let addr_b = free_b.local_addr().unwrap();
+        drop((free_a, free_b));
+
+        // b is created first, so it's listening by the time a connects to it
+        let node_b = make_bus(addr_b, addr_a);
+        let node_a = make_bus(addr_a, addr_b);
+        let mut events = node_b.subscribe();
+        let mut cancels = node_b.subscribe_cancels();
+
+        node_a.send(Arc::new(EventBroadcast::new(
+            EventSub::Homeserver,
+            Event::Chat(stream_event::Event::DeletedMessage(
+                stream_event::MessageDeleted {
+                    guild_id: 1,
+                    channel_id: 2,
+                    message_id: 3,
+                },
+            )),
+            None,
+            EventContext::empty(),
+        )));
+        node_a.cancel(StreamCancel::session(4, 5));
+
+        // frames are queued until the peer connection is up, which can take
+        // a reconnect if it was attempted too early
+        let timeout = PEER_RECONNECT_DELAY * 2;
+        let event = tokio::time::timeout(timeout, events.recv())
+            .await
+            .expect("event must reach node b")
+            .expect("event bus must stay open");
+        assert_eq!(event.sub, EventSub::Homeserver);
+        assert!(matches!(
+            event.event,
+            Event::Chat(stream_event::Event::DeletedMessage(
+                stream_event::MessageDeleted { message_id: 3, .. }
+            ))
+        ));
+
+        let cancel = tokio::time::timeout(timeout, cancels.recv())
+            .await
+            .expect("cancel must reach node b")
+            .expect("event bus must stay open");
+        assert_eq!(cancel.user_id, 4);
+        assert_eq!(cancel.session_id, Some(5));
+    }
+}
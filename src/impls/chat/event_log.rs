@@ -0,0 +1,349 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use super::*;
+
+/// Header clients can set when opening an event stream to resume it. The value
+/// is a comma separated list of `guild_id:sequence` pairs, where `sequence` is
+/// the last sequence the client has seen for that guild.
+pub const RESUME_EVENTS_HEADER: &str = "scherzo-resume-from";
+
+/// Maps guild IDs to the last sequence a client has seen for them.
+pub type ResumePoints = HashMap<u64, u64, ahash::RandomState>;
+
+/// Parses the value of the [`RESUME_EVENTS_HEADER`] header.
+pub fn parse_resume_points(value: &str) -> Result<ResumePoints, ServerError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| pair.is_empty().not())
+        .map(|pair| {
+            let (guild_id, sequence) = pair
+                .split_once(':')
+                .ok_or(ServerError::InvalidResumePoints)?;
+            let guild_id = guild_id
+                .parse()
+                .map_err(|_| ServerError::InvalidResumePoints)?;
+            let sequence = sequence
+                .parse()
+                .map_err(|_| ServerError::InvalidResumePoints)?;
+            Ok((guild_id, sequence))
+        })
+        .collect()
+}
+
+/// Whether an event is worth replaying to clients that resume later. Typing
+/// events are outdated by the time anyone could resume, and the log of a guild
+/// is deleted along with it.
+fn is_logged_event(event: &stream_event::Event) -> bool {
+    matches!(
+        event,
+        stream_event::Event::Typing(_) | stream_event::Event::DeletedGuild(_)
+    )
+    .not()
+}
+
+/// A guild event read back from the event log.
+#[derive(Debug)]
+pub struct LoggedEvent {
+    pub sequence: u64,
+    /// Channel ID, permission node and whether the user must be guild owner
+    pub perm_check: Option<(Option<u64>, SmolStr, bool)>,
+    pub user_ids: HashSet<u64, ahash::RandomState>,
+    pub event: stream_event::Event,
+}
+
+fn encode_logged_event(broadcast: &EventBroadcast, event: stream_event::Event) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    match broadcast.perm_check {
+        Some(PermCheck {
+            channel_id,
+            check_for,
+            must_be_guild_owner,
+            ..
+        }) => {
+            buf.push(1);
+            // channel IDs are never 0, so we can use it for "no channel"
+            buf.extend_from_slice(&channel_id.unwrap_or(0).to_be_bytes());
+            buf.push(must_be_guild_owner as u8);
+            buf.push(check_for.len() as u8);
+            buf.extend_from_slice(check_for.as_bytes());
+        }
+        None => buf.push(0),
+    }
+
+    buf.extend_from_slice(&(broadcast.context.user_ids.len() as u32).to_be_bytes());
+    for user_id in &broadcast.context.user_ids {
+        buf.extend_from_slice(&user_id.to_be_bytes());
+    }
+
+    StreamEvent { event: Some(event) }
+        .encode(&mut buf)
+        .expect("vec can grow, so encoding can't fail");
+
+    buf
+}
+
+fn decode_logged_event(sequence: u64, raw: &[u8]) -> Option<LoggedEvent> {
+    fn split_at(raw: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
+        (raw.len() >= mid).then(|| raw.split_at(mid))
+    }
+
+    let (has_perm_check, mut raw) = raw.split_first()?;
+
+    let perm_check = if *has_perm_check == 1 {
+        let (channel_id, rest) = split_at(raw, size_of::<u64>())?;
+        let (must_be_guild_owner, rest) = rest.split_first()?;
+        let (check_for_len, rest) = rest.split_first()?;
+        let (check_for, rest) = split_at(rest, *check_for_len as usize)?;
+        raw = rest;
+
+        let channel_id = deser_id(channel_id);
+        Some((
+            (channel_id != 0).then(|| channel_id),
+            SmolStr::new(std::str::from_utf8(check_for).ok()?),
+            *must_be_guild_owner == 1,
+        ))
+    } else {
+        None
+    };
+
+    let (user_count, rest) = split_at(raw, size_of::<u32>())?;
+    let user_count = u32::from_be_bytes(user_count.try_into().ok()?) as usize;
+    let (user_ids, rest) = split_at(rest, user_count.checked_mul(size_of::<u64>())?)?;
+    let user_ids = db::make_u64_iter_logic(user_ids).collect();
+
+    let event = StreamEvent::decode(rest).ok()?.event?;
+
+    Some(LoggedEvent {
+        sequence,
+        perm_check,
+        user_ids,
+        event,
+    })
+}
+
+impl ChatTree {
+    /// Gets the sequence of the last event logged for a guild, 0 if there are none.
+    pub async fn get_event_log_sequence_logic(&self, guild_id: u64) -> Result<u64, ServerError> {
+        Ok(self
+            .get(make_event_log_seq_key(guild_id))
+            .await?
+            .map_or(0, deser_id))
+    }
+
+    /// Gets the latest event sequence of every local guild the user is in.
+    pub async fn get_user_event_log_sequences_logic(
+        &self,
+        user_id: u64,
+    ) -> Result<Vec<(u64, u64)>, ServerError> {
+        let prefix = make_guild_list_key_prefix(user_id);
+        let mut sequences = Vec::new();
+        for res in self.scan_prefix(&prefix).await {
+            let (key, _) = res?;
+            let (raw_guild_id, host) = key.split_at(prefix.len()).1.split_at(size_of::<u64>());
+            if host.is_empty() {
+                let guild_id = deser_id(raw_guild_id);
+                let sequence = self.get_event_log_sequence_logic(guild_id).await?;
+                sequences.push((guild_id, sequence));
+            }
+        }
+        Ok(sequences)
+    }
+
+    /// Logs a guild event and puts its sequence on it, so it can be sent to
+    /// streams along with the event. Events that aren't guild events, and
+    /// ones that aren't worth replaying, are not logged.
+    pub async fn log_event_logic(
+        &self,
+        broadcast: &mut EventBroadcast,
+        max_events: u64,
+    ) -> ServerResult<()> {
+        if let (EventSub::Guild(guild_id), Event::Chat(event)) = (broadcast.sub, &broadcast.event) {
+            if max_events == 0 || is_logged_event(event).not() {
+                return Ok(());
+            }
+
+            let entry = encode_logged_event(broadcast, event.clone());
+            let sequence = self
+                .append_event_log_logic(guild_id, entry, max_events)
+                .await?;
+            broadcast.sequence = Some(sequence);
+        }
+
+        Ok(())
+    }
+
+    /// Logs an event, removing the oldest event if the log has more than
+    /// `max_events`. Returns the sequence of the event.
+    ///
+    /// The sequence is claimed by inserting the entry only if its key is free,
+    /// so nodes logging events for the same guild at the same time can't get
+    /// the same sequence, and an event is in the log once it has a sequence.
+    pub async fn append_event_log_logic(
+        &self,
+        guild_id: u64,
+        entry: Vec<u8>,
+        max_events: u64,
+    ) -> ServerResult<u64> {
+        let mut sequence = self.get_event_log_sequence_logic(guild_id).await?;
+        loop {
+            sequence += 1;
+            if self
+                .compare_and_swap(
+                    make_event_log_key(guild_id, sequence),
+                    None,
+                    Some(entry.as_slice()),
+                )
+                .await?
+            {
+                break;
+            }
+        }
+
+        self.update_and_fetch(make_event_log_seq_key(guild_id), |latest| {
+            let latest = latest.map_or(0, deser_id).max(sequence);
+            Some(latest.to_be_bytes().to_vec())
+        })
+        .await?;
+        if sequence > max_events {
+            self.remove(make_event_log_key(guild_id, sequence - max_events))
+                .await?;
+        }
+
+        Ok(sequence)
+    }
+
+    /// Gets all logged events of a guild that come after `since`. Returns `None`
+    /// if the log doesn't go back far enough to contain all of them.
+    pub async fn get_event_log_since_logic(
+        &self,
+        guild_id: u64,
+        since: u64,
+    ) -> ServerResult<Option<Vec<LoggedEvent>>> {
+        let next = since.saturating_add(1);
+        let start = make_event_log_key(guild_id, next);
+        let end = make_event_log_key(guild_id, u64::MAX);
+        let prefix_len = make_event_log_prefix(guild_id).len();
+
+        let mut events = Vec::new();
+        let mut found_start = false;
+        for res in self.chat_tree.range(&start[..]..=&end[..]).await {
+            let (key, value) = res.map_err(ServerError::DbError)?;
+            let sequence = deser_id(&key[prefix_len..]);
+            if found_start.not() && sequence > next {
+                // oldest logged event is newer than the next one the client needs
+                return Ok(None);
+            }
+            found_start = true;
+            match decode_logged_event(sequence, &value) {
+                Some(event) => events.push(event),
+                None => tracing::error!(
+                    "couldnt decode event {} of guild {}, skipping",
+                    sequence,
+                    guild_id
+                ),
+            }
+        }
+        if found_start {
+            return Ok(Some(events));
+        }
+
+        let latest = self.get_event_log_sequence_logic(guild_id).await?;
+        let is_latest = match since.cmp(&latest) {
+            // there were events after `since`, so they were all pruned
+            Ordering::Less => false,
+            Ordering::Equal => true,
+            // the latest sequence is updated after an event is logged, so it
+            // can be behind for a moment
+            Ordering::Greater => {
+                self.contains_key(make_event_log_key(guild_id, since))
+                    .await?
+            }
+        };
+
+        Ok(is_latest.then(Vec::new))
+    }
+
+    /// Gets the events of a guild a user missed since `since`, skipping the ones
+    /// they aren't allowed to see, along with the sequence of the last logged
+    /// event. Errors if some of the events are no longer logged.
+    pub async fn get_resumed_events_logic(
+        &self,
+        user_id: u64,
+        guild_id: u64,
+        since: u64,
+    ) -> ServerResult<(Vec<Event>, u64)> {
+        let logged = self
+            .get_event_log_since_logic(guild_id, since)
+            .await?
+            .ok_or(ServerError::EventGapTooLarge { guild_id, since })?;
+
+        let last_sequence = logged.last().map_or(since, |entry| entry.sequence);
+        let mut events = Vec::with_capacity(logged.len());
+        for entry in logged {
+            let perm_check = entry
+                .perm_check
+                .as_ref()
+                .map(|(channel_id, check_for, owner)| {
+                    PermCheck::new(guild_id, *channel_id, check_for, *owner)
+                });
+            if self
+                .can_user_receive_event(user_id, &entry.user_ids, perm_check)
+                .await
+            {
+                events.push(Event::Chat(entry.event));
+            }
+        }
+
+        Ok((events, last_sequence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn event_log_keeps_latest_events() {
+        const GUILD_ID: u64 = 1;
+
+        let db = db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+
+        for message_id in 1..=3 {
+            let mut broadcast = EventBroadcast::new(
+                EventSub::Guild(GUILD_ID),
+                Event::Chat(stream_event::Event::DeletedMessage(
+                    stream_event::MessageDeleted {
+                        guild_id: GUILD_ID,
+                        channel_id: 2,
+                        message_id,
+                    },
+                )),
+                None,
+                EventContext::empty(),
+            );
+            chat_tree.log_event_logic(&mut broadcast, 2).await.unwrap();
+            assert_eq!(broadcast.sequence, Some(message_id));
+        }
+
+        let since = |since| chat_tree.get_event_log_since_logic(GUILD_ID, since);
+        // the first event was pruned
+        assert!(since(0).await.unwrap().is_none());
+        let sequences = since(1)
+            .await
+            .unwrap()
+            .expect("must be logged")
+            .into_iter()
+            .map(|event| event.sequence)
+            .collect::<Vec<_>>();
+        assert_eq!(sequences, vec![2, 3]);
+        assert!(since(3).await.unwrap().expect("must be latest").is_empty());
+        assert!(since(4).await.unwrap().is_none());
+
+        // decoding must not panic on truncated entries
+        assert!(decode_logged_event(1, &[1, 0, 0]).is_none());
+    }
+}
@@ -1,8 +1,62 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<CreateDirectMessageRequest>,
+    svc: &ChatServer,
+    request: Request<CreateDirectMessageRequest>,
 ) -> ServerResult<Response<CreateDirectMessageResponse>> {
-    Err(ServerError::NotImplemented.into())
+    let user_id = svc.deps.auth(&request).await?;
+    svc.deps.chat_tree.check_not_guest(user_id).await?;
+
+    let CreateDirectMessageRequest {
+        user_name,
+        server_id,
+    } = request.into_message().await?;
+
+    let server_id = server_id.filter(|id| id.is_empty().not());
+    let profile_tree = &svc.deps.profile_tree;
+
+    let peer_id = profile_tree
+        .get_user_id_by_name(&user_name, server_id.as_deref())
+        .await?
+        .ok_or_else(|| {
+            (
+                "h.bad-user-name",
+                format!("no user with the name {} found", user_name),
+            )
+        })?;
+
+    if peer_id == user_id {
+        bail!((
+            "h.cant-dm-yourself",
+            "you can't create a direct message with yourself"
+        ));
+    }
+
+    // both members see the same guild name, so it has to name both of them
+    let own_name = profile_tree.get_profile_logic(user_id).await?.user_name;
+    let peer_name = profile_tree.get_profile_logic(peer_id).await?.user_name;
+    let name = format!("{}, {}", own_name, peer_name);
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    let guild_id = chat_tree
+        .create_guild_logic(
+            user_id,
+            name,
+            None,
+            None,
+            guild_kind::Kind::new_direct_message(guild_kind::DirectMessage::new(false)),
+        )
+        .await?;
+
+    // the peer doesn't need to accept anything, they are a member from the start
+    chat_tree
+        .insert(make_member_key(guild_id, peer_id), [])
+        .await?;
+    chat_tree.add_default_role_to(guild_id, peer_id).await?;
+
+    svc.dispatch_guild_join(guild_id, user_id).await?;
+    svc.dispatch_guild_join(guild_id, peer_id).await?;
+
+    Ok((CreateDirectMessageResponse { guild_id }).into_response())
 }
@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<CreateGuildRequest>,
 ) -> ServerResult<Response<CreateGuildResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    svc.deps.chat_tree.check_not_guest(user_id).await?;
 
     let CreateGuildRequest {
         metadata,
@@ -1,8 +1,39 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<CreateRoomRequest>,
+    svc: &ChatServer,
+    request: Request<CreateRoomRequest>,
 ) -> ServerResult<Response<CreateRoomResponse>> {
-    Err(ServerError::NotImplemented.into())
+    let user_id = svc.deps.auth(&request).await?;
+    svc.deps.chat_tree.check_not_guest(user_id).await?;
+
+    let CreateRoomRequest {
+        metadata,
+        name,
+        picture,
+    } = request.into_message().await?;
+
+    if name.is_empty() {
+        bail!(("h.bad-room-name", "room name can't be empty"));
+    }
+
+    if picture.as_ref().map_or(false, String::is_empty) {
+        bail!(("h.bad-room-picture", "room picture can't be empty if set"));
+    }
+
+    let guild_id = svc
+        .deps
+        .chat_tree
+        .create_guild_logic(
+            user_id,
+            name,
+            picture,
+            metadata,
+            guild_kind::Kind::new_room(guild_kind::Room::new()),
+        )
+        .await?;
+
+    svc.dispatch_guild_join(guild_id, user_id).await?;
+
+    Ok((CreateRoomResponse { guild_id }).into_response())
 }
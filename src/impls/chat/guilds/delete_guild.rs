@@ -34,13 +34,15 @@ pub async fn handler(
         .apply_batch(batch)
         .await
         .map_err(ServerError::DbError)?;
+    chat_tree.delete_guild_invites_logic(guild_id).await?;
 
     svc.send_event_through_chan(
         EventSub::Guild(guild_id),
         stream_event::Event::DeletedGuild(stream_event::GuildDeleted { guild_id }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
 
     let mut local_ids = Vec::new();
     for member_id in guild_members {
@@ -68,7 +70,8 @@ pub async fn handler(
         }),
         None,
         EventContext::new(local_ids),
-    );
+    )
+    .await;
 
     Ok((DeleteGuildResponse {}).into_response())
 }
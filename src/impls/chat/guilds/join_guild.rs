@@ -16,6 +16,14 @@ pub async fn handler(
     } else {
         return Err(ServerError::NoSuchInvite(invite_id.into()).into());
     };
+    let now = get_time_secs();
+    chat_tree.check_invite_not_expired(&invite_id, now).await?;
+
+    // invites made with `invite_user_to_guild` can only be used by the invited user
+    let user_invite = chat_tree.get_user_invite_logic(&invite_id).await?;
+    if matches!(user_invite, Some((_, invitee_id, _)) if invitee_id != user_id) {
+        return Err(ServerError::NoSuchInvite(invite_id.into()).into());
+    }
 
     if chat_tree.is_user_banned_in_guild(guild_id, user_id).await? {
         return Err(ServerError::UserBanned.into());
@@ -37,8 +45,17 @@ pub async fn handler(
         .insert(make_member_key(guild_id, user_id), [])
         .await?;
     chat_tree.add_default_role_to(guild_id, user_id).await?;
+    chat_tree
+        .record_invite_use_logic(guild_id, &invite_id, user_id, now)
+        .await?;
     invite.use_count += 1;
 
+    if user_invite.is_some() {
+        chat_tree
+            .remove_pending_invite_logic(user_id, "", &invite_id)
+            .await?;
+    }
+
     let is_invite_consumed = is_infinite.not() && invite.use_count >= invite.possible_uses;
     if is_invite_consumed {
         chat_tree.delete_invite_logic(invite_id).await?;
@@ -52,10 +69,19 @@ pub async fn handler(
         }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
 
     svc.dispatch_guild_join(guild_id, user_id).await?;
 
+    if let Some((_, _, inviter_id)) = user_invite {
+        let content = content::Content::InviteAccepted(content::InviteAccepted {
+            invitee_id: user_id,
+            inviter_id,
+        });
+        svc.send_system_message(guild_id, content).await?;
+    }
+
     if !is_invite_consumed {
         let buf = rkyv_ser(&invite);
         chat_tree
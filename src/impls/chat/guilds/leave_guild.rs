@@ -31,7 +31,8 @@ pub async fn handler(
         }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
 
     svc.dispatch_guild_leave(guild_id, user_id).await?;
 
@@ -8,6 +8,9 @@ pub async fn handler(
 
     let chat_tree = &svc.deps.chat_tree;
 
+    chat_tree
+        .check_invite_not_expired(&invite_id, get_time_secs())
+        .await?;
     let key = make_invite_key(&invite_id);
     let guild_id = chat_tree
         .get(&key)
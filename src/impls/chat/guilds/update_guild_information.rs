@@ -54,7 +54,8 @@ pub async fn handler(
         }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((UpdateGuildInformationResponse {}).into_response())
 }
@@ -1,8 +1,39 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<v1::UpgradeRoomToGuildRequest>,
-) -> ServerResult<Response<v1::UpgradeRoomToGuildResponse>> {
-    Err(ServerError::NotImplemented.into())
+    svc: &ChatServer,
+    request: Request<UpgradeRoomToGuildRequest>,
+) -> ServerResult<Response<UpgradeRoomToGuildResponse>> {
+    let user_id = svc.deps.auth(&request).await?;
+
+    let UpgradeRoomToGuildRequest { guild_id } = request.into_message().await?;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+    chat_tree
+        .check_perms(guild_id, None, user_id, "", true)
+        .await?;
+
+    chat_tree.upgrade_room_to_guild_logic(guild_id).await?;
+
+    svc.send_event_through_chan(
+        EventSub::Guild(guild_id),
+        stream_event::Event::EditedGuild(stream_event::GuildUpdated {
+            guild_id,
+            new_name: None,
+            new_picture: None,
+            new_metadata: None,
+        }),
+        None,
+        EventContext::empty(),
+    )
+    .await;
+
+    let content = content::Content::RoomUpgradedToGuild(content::RoomUpgradedToGuild {
+        upgraded_by: user_id,
+    });
+    svc.send_system_message(guild_id, content).await?;
+
+    Ok((UpgradeRoomToGuildResponse {}).into_response())
 }
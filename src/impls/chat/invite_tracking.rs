@@ -0,0 +1,196 @@
+//! Invite expiry and usage tracking. Invites can expire at a set time, after
+//! which they can't be used and are deleted periodically. Each use of an
+//! invite is recorded, along with who used it and when.
+
+use super::*;
+
+/// Header clients can set on create invite requests to make the invite
+/// expire. The value is how long the invite lasts, in seconds.
+pub const INVITE_EXPIRES_IN_HEADER: &str = "scherzo-invite-expires-in";
+/// Permission node needed for viewing who joined through an invite.
+pub const INVITES_VIEW: &str = "invites.view";
+
+/// Parses the [`INVITE_EXPIRES_IN_HEADER`] header of a request. Returns
+/// `None` if the invite shouldn't expire.
+pub fn get_invite_lifetime<T>(request: &Request<T>) -> Result<Option<u64>, ServerError> {
+    get_duration_header(request, INVITE_EXPIRES_IN_HEADER)
+}
+
+impl ChatTree {
+    /// Gets when an invite expires, if it does.
+    pub async fn get_invite_expiry_logic(&self, name: &str) -> Result<Option<u64>, ServerError> {
+        Ok(self.get(make_invite_expires_key(name)).await?.map(deser_id))
+    }
+
+    /// Errors with [`ServerError::InviteExpired`] if the invite expired at
+    /// `now`.
+    pub async fn check_invite_not_expired(&self, name: &str, now: u64) -> Result<(), ServerError> {
+        match self.get_invite_expiry_logic(name).await? {
+            Some(expires_at) if expires_at <= now => Err(ServerError::InviteExpired),
+            _ => Ok(()),
+        }
+    }
+
+    /// Makes an invite expire at `expires_at`.
+    pub async fn set_invite_expiry_logic(
+        &self,
+        name: &str,
+        expires_at: u64,
+    ) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        batch.insert(make_invite_expires_key(name), expires_at.to_be_bytes());
+        batch.insert(make_invite_expiry_key(expires_at, name), []);
+        self.apply_batch(batch).await
+    }
+
+    /// Forgets when an invite expires.
+    pub async fn remove_invite_expiry_logic(&self, name: &str) -> Result<(), ServerError> {
+        let key = make_invite_expires_key(name);
+        let Some(raw) = self.get(&key).await? else {
+            return Ok(());
+        };
+
+        let mut batch = Batch::default();
+        batch.remove(key);
+        batch.remove(make_invite_expiry_key(deser_id(raw), name));
+        self.apply_batch(batch).await
+    }
+
+    /// Records that a user joined a guild through an invite at `joined_at`.
+    pub async fn record_invite_use_logic(
+        &self,
+        guild_id: u64,
+        name: &str,
+        user_id: u64,
+        joined_at: u64,
+    ) -> Result<(), ServerError> {
+        self.insert(
+            make_invite_use_key(guild_id, name, user_id),
+            joined_at.to_be_bytes(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Gets who joined a guild through an invite and when, in no particular
+    /// order.
+    pub async fn get_invite_uses_logic(
+        &self,
+        guild_id: u64,
+        name: &str,
+    ) -> Result<Vec<(u64, u64)>, ServerError> {
+        let prefix = make_invite_uses_prefix(guild_id, name);
+        let mut uses = Vec::new();
+        for res in self.scan_prefix(&prefix).await {
+            let (key, value) = res?;
+            uses.push((deser_id(&key[prefix.len()..]), deser_id(value)));
+        }
+        Ok(uses)
+    }
+
+    /// Forgets who joined through an invite, so that a new invite with the
+    /// same name starts without uses.
+    pub async fn clear_invite_uses_logic(
+        &self,
+        guild_id: u64,
+        name: &str,
+    ) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        for res in self
+            .scan_prefix(&make_invite_uses_prefix(guild_id, name))
+            .await
+        {
+            let (key, _) = res?;
+            batch.remove(key);
+        }
+        self.apply_batch(batch).await
+    }
+
+    /// Deletes the invites that expired at `now`, returning their names.
+    pub async fn expire_invites_logic(&self, now: u64) -> Result<Vec<String>, ServerError> {
+        let mut entries = Vec::new();
+        for res in self.scan_prefix(INVITE_EXPIRY_PREFIX).await {
+            let (key, _) = res?;
+            let (expires_at, name) = key[INVITE_EXPIRY_PREFIX.len()..].split_at(size_of::<u64>());
+            let expires_at = deser_id(expires_at);
+            // the index is sorted by expiry, so the rest hasn't expired either
+            if expires_at > now {
+                break;
+            }
+            entries.push((expires_at, String::from_utf8_lossy(name).into_owned()));
+        }
+
+        let mut expired = Vec::new();
+        for (expires_at, name) in entries {
+            // the entry is stale if the invite was replaced by one that expires at another time
+            if self.get_invite_expiry_logic(&name).await? == Some(expires_at) {
+                self.delete_invite_logic(name.clone()).await?;
+                expired.push(name);
+            } else {
+                self.remove(make_invite_expiry_key(expires_at, &name))
+                    .await?;
+            }
+        }
+
+        Ok(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn invites_expire_and_keep_their_uses() {
+        const NOW: u64 = 1_700_000_000;
+        const GUILD_ID: u64 = 1;
+        const USER_ID: u64 = 2;
+
+        let db = db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+
+        chat_tree
+            .create_invite_logic(GUILD_ID, "a", 0, Some(NOW + 60))
+            .await
+            .unwrap();
+        chat_tree
+            .create_invite_logic(GUILD_ID, "ab", 0, Some(NOW + 120))
+            .await
+            .unwrap();
+        chat_tree
+            .record_invite_use_logic(GUILD_ID, "ab", USER_ID, NOW)
+            .await
+            .unwrap();
+
+        assert!(chat_tree.check_invite_not_expired("a", NOW).await.is_ok());
+        assert!(chat_tree
+            .check_invite_not_expired("a", NOW + 60)
+            .await
+            .is_err());
+        assert!(chat_tree
+            .get_invite_uses_logic(GUILD_ID, "a")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let expired = chat_tree.expire_invites_logic(NOW + 60).await.unwrap();
+        assert_eq!(expired, vec!["a".to_string()]);
+        assert!(chat_tree.get(make_invite_key("a")).await.unwrap().is_none());
+
+        let expired = chat_tree.expire_invites_logic(NOW + 120).await.unwrap();
+        assert_eq!(expired, vec!["ab".to_string()]);
+        assert_eq!(
+            chat_tree
+                .get_invite_uses_logic(GUILD_ID, "ab")
+                .await
+                .unwrap(),
+            vec![(USER_ID, NOW)]
+        );
+        assert!(chat_tree
+            .expire_invites_logic(NOW + 120)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
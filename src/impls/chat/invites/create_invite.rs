@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<CreateInviteRequest>,
 ) -> ServerResult<Response<CreateInviteResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let expires_at = get_invite_lifetime(&request)?.map(|lifetime| get_time_secs() + lifetime);
 
     let CreateInviteRequest {
         guild_id,
@@ -19,8 +20,15 @@ pub async fn handler(
         .check_perms(guild_id, None, user_id, "invites.manage.create", false)
         .await?;
 
+    if let guild_kind::Kind::DirectMessage(_) = chat_tree.get_guild_kind_logic(guild_id).await? {
+        bail!((
+            "h.cant-invite-to-direct-message",
+            "direct messages can't have invites"
+        ));
+    }
+
     chat_tree
-        .create_invite_logic(guild_id, name.as_str(), possible_uses)
+        .create_invite_logic(guild_id, name.as_str(), possible_uses, expires_at)
         .await?;
 
     Ok((CreateInviteResponse { invite_id: name }).into_response())
@@ -1,8 +1,16 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<v1::GetPendingInvitesRequest>,
-) -> ServerResult<Response<v1::GetPendingInvitesResponse>> {
-    Err(ServerError::NotImplemented.into())
+    svc: &ChatServer,
+    request: Request<GetPendingInvitesRequest>,
+) -> ServerResult<Response<GetPendingInvitesResponse>> {
+    let user_id = svc.deps.auth(&request).await?;
+
+    let pending_invites = svc
+        .deps
+        .chat_tree
+        .get_pending_invites_logic(user_id)
+        .await?;
+
+    Ok((GetPendingInvitesResponse { pending_invites }).into_response())
 }
@@ -1,8 +1,24 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<v1::IgnorePendingInviteRequest>,
-) -> ServerResult<Response<v1::IgnorePendingInviteResponse>> {
-    Err(ServerError::NotImplemented.into())
+    svc: &ChatServer,
+    request: Request<IgnorePendingInviteRequest>,
+) -> ServerResult<Response<IgnorePendingInviteResponse>> {
+    let user_id = svc.deps.auth(&request).await?;
+
+    let IgnorePendingInviteRequest {
+        invite_id,
+        server_id,
+    } = request.into_message().await?;
+
+    let server_id = server_id.filter(|id| id.is_empty().not());
+
+    // ignoring an invite only removes it from the inbox, the inviter isn't notified
+    svc.deps
+        .chat_tree
+        .remove_pending_invite_logic(user_id, server_id.as_deref().unwrap_or(""), &invite_id)
+        .await?
+        .ok_or_else(|| ServerError::NoSuchInvite(invite_id.into()))?;
+
+    Ok((IgnorePendingInviteResponse {}).into_response())
 }
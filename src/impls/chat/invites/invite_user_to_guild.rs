@@ -1,8 +1,91 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<v1::InviteUserToGuildRequest>,
-) -> ServerResult<Response<v1::InviteUserToGuildResponse>> {
-    Err(ServerError::NotImplemented.into())
+    svc: &ChatServer,
+    request: Request<InviteUserToGuildRequest>,
+) -> ServerResult<Response<InviteUserToGuildResponse>> {
+    let user_id = svc.deps.auth(&request).await?;
+
+    let InviteUserToGuildRequest {
+        user_name,
+        guild_id,
+        server_id,
+    } = request.into_message().await?;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree.check_guild_user(guild_id, user_id).await?;
+    chat_tree
+        .check_perms(guild_id, None, user_id, "invites.manage.create", false)
+        .await?;
+
+    if let guild_kind::Kind::DirectMessage(_) = chat_tree.get_guild_kind_logic(guild_id).await? {
+        bail!((
+            "h.cant-invite-to-direct-message",
+            "direct messages can't have invites"
+        ));
+    }
+
+    let server_id = server_id.filter(|id| id.is_empty().not());
+    let profile_tree = &svc.deps.profile_tree;
+
+    let invitee_id = profile_tree
+        .get_user_id_by_name(&user_name, server_id.as_deref())
+        .await?
+        .ok_or_else(|| {
+            (
+                "h.bad-user-name",
+                format!("no user with the name {} found", user_name),
+            )
+        })?;
+
+    if chat_tree
+        .is_user_banned_in_guild(guild_id, invitee_id)
+        .await?
+    {
+        return Err(ServerError::UserBanned.into());
+    }
+
+    chat_tree
+        .is_user_in_guild(guild_id, invitee_id)
+        .await
+        .ok()
+        .map_or(Ok(()), |_| Err(ServerError::UserAlreadyInGuild))?;
+
+    let invite_id = gen_rand_inline_str();
+    chat_tree
+        .create_invite_logic(guild_id, invite_id.as_str(), 1, None)
+        .await?;
+    chat_tree
+        .add_user_invite_logic(invite_id.as_str(), guild_id, invitee_id, user_id)
+        .await?;
+
+    match profile_tree.local_to_foreign_id(invitee_id).await? {
+        Some((foreign_id, target)) => svc.dispatch_event(
+            target,
+            DispatchKind::UserInvited(SyncUserInvited {
+                invite_id: invite_id.to_string(),
+                user_id: foreign_id,
+                inviter_id: user_id,
+            }),
+        ),
+        None => {
+            chat_tree
+                .add_pending_invite_logic(invitee_id, "", invite_id.as_str(), user_id)
+                .await?;
+            svc.send_event_through_chan(
+                EventSub::Homeserver,
+                stream_event::Event::InviteReceived(stream_event::InviteReceived {
+                    invite_id: invite_id.to_string(),
+                    server_id: None,
+                    inviter_id: user_id,
+                }),
+                None,
+                EventContext::new(vec![invitee_id]),
+            )
+            .await;
+        }
+    }
+
+    Ok((InviteUserToGuildResponse {}).into_response())
 }
@@ -1,8 +1,36 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<v1::RejectPendingInviteRequest>,
-) -> ServerResult<Response<v1::RejectPendingInviteResponse>> {
-    Err(ServerError::NotImplemented.into())
+    svc: &ChatServer,
+    request: Request<RejectPendingInviteRequest>,
+) -> ServerResult<Response<RejectPendingInviteResponse>> {
+    let user_id = svc.deps.auth(&request).await?;
+
+    let RejectPendingInviteRequest {
+        invite_id,
+        server_id,
+    } = request.into_message().await?;
+
+    let server_id = server_id.filter(|id| id.is_empty().not());
+
+    let inviter_id = svc
+        .deps
+        .chat_tree
+        .remove_pending_invite_logic(user_id, server_id.as_deref().unwrap_or(""), &invite_id)
+        .await?
+        .ok_or_else(|| ServerError::NoSuchInvite(invite_id.as_str().into()))?;
+
+    match server_id {
+        Some(server_id) => svc.dispatch_event(
+            server_id.into(),
+            DispatchKind::UserRejectedInvite(SyncUserRejectedInvite {
+                invite_id,
+                user_id,
+                inviter_id,
+            }),
+        ),
+        None => svc.reject_user_invite(&invite_id, user_id).await?,
+    }
+
+    Ok((RejectPendingInviteResponse {}).into_response())
 }
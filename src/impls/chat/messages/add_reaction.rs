@@ -29,7 +29,8 @@ pub async fn handler(
         let reaction = chat_tree
             .update_reaction(user_id, guild_id, channel_id, message_id, emote, true)
             .await?;
-        svc.send_reaction_event(guild_id, channel_id, message_id, reaction);
+        svc.send_reaction_event(guild_id, channel_id, message_id, reaction)
+            .await;
     }
 
     Ok((AddReactionResponse {}).into_response())
@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<DeleteMessageRequest>,
 ) -> ServerResult<Response<DeleteMessageResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
 
     let DeleteMessageRequest {
         guild_id,
@@ -17,13 +18,10 @@ pub async fn handler(
     chat_tree
         .check_guild_user_channel(guild_id, user_id, channel_id)
         .await?;
-    if chat_tree
+    let (message, key) = chat_tree
         .get_message_logic(guild_id, channel_id, message_id)
-        .await?
-        .0
-        .author_id
-        != user_id
-    {
+        .await?;
+    if message.author_id != user_id {
         chat_tree
             .check_perms(
                 guild_id,
@@ -35,9 +33,12 @@ pub async fn handler(
             .await?;
     }
 
+    let mut batch = Batch::default();
+    batch.remove(key);
+    search::unindex_message(&mut batch, guild_id, channel_id, message_id, &message);
     chat_tree
         .chat_tree
-        .remove(&make_msg_key(guild_id, channel_id, message_id))
+        .apply_batch(batch)
         .await
         .map_err(ServerError::DbError)?;
 
@@ -55,7 +56,20 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
+
+    if message.author_id != user_id {
+        svc.record_audit_log(
+            guild_id,
+            user_id,
+            AuditAction::MessageDeleted,
+            message.author_id,
+            Some(channel_id),
+            reason,
+        )
+        .await?;
+    }
 
     Ok((DeleteMessageResponse {}).into_response())
 }
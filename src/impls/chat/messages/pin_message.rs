@@ -47,7 +47,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok(PinMessageResponse::new().into_response())
 }
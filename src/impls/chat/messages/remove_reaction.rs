@@ -30,7 +30,8 @@ pub async fn handler(
             .update_reaction(user_id, guild_id, channel_id, message_id, emote, false)
             .await?;
         if reaction.is_some() {
-            svc.send_reaction_event(guild_id, channel_id, message_id, reaction);
+            svc.send_reaction_event(guild_id, channel_id, message_id, reaction)
+                .await;
         }
     }
 
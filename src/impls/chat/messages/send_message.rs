@@ -30,6 +30,18 @@ pub async fn handler(
             &svc.deps.config.host,
         )
         .await?;
+    // messages blocked by automod still count for slow mode, so it can't be
+    // used to check messages against the rules faster
+    chat_tree
+        .use_slow_mode_logic(guild_id, channel_id, user_id, get_time_secs())
+        .await?;
+    if let Some(content::Content::TextMessage(content::TextContent {
+        content: Some(text),
+    })) = content.content.as_ref()
+    {
+        svc.run_automod(guild_id, channel_id, user_id, None, text)
+            .await?;
+    }
     request.content = Some(content);
     let (message_id, message) = chat_tree.send_message_logic(user_id, request).await?;
 
@@ -43,7 +55,7 @@ pub async fn handler(
             content: Some(FormattedText { text, .. }),
         })) = message.content.as_ref().and_then(|c| c.content.as_ref())
         {
-            let msg = admin_action::run_str(svc.deps.as_ref(), text)
+            let msg = admin_action::run_str(svc.deps.as_ref(), user_id, text)
                 .await
                 .unwrap_or_else(|err| format!("error: {}", err));
             Some(msg)
@@ -70,7 +82,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     if let Some(msg) = action_content {
         let content = content::Content::TextMessage(content::TextContent {
@@ -95,7 +108,8 @@ pub async fn handler(
                 false,
             )),
             EventContext::empty(),
-        );
+        )
+        .await;
     }
 
     Ok((SendMessageResponse { message_id }).into_response())
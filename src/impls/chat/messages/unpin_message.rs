@@ -53,7 +53,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok(UnpinMessageResponse::new().into_response())
 }
@@ -26,9 +26,9 @@ pub async fn handler(
         .check_perms(guild_id, Some(channel_id), user_id, "messages.send", false)
         .await?;
 
-    if new_content.as_ref().map_or(true, |f| f.text.is_empty()) {
+    let Some(text) = new_content.as_ref().filter(|f| f.text.is_empty().not()) else {
         return Err(ServerError::MessageContentCantBeEmpty.into());
-    }
+    };
 
     let key = make_msg_key(guild_id, channel_id, message_id);
     let Some(message_raw) = chat_tree.get(key).await? else {
@@ -43,10 +43,16 @@ pub async fn handler(
         ));
     }
 
+    svc.run_automod(guild_id, channel_id, user_id, Some(message_id), text)
+        .await?;
+
     let mut message: Message = message_archived
         .deserialize(&mut SharedDeserializeMap::default())
         .unwrap();
 
+    let mut batch = Batch::default();
+    search::unindex_message(&mut batch, guild_id, channel_id, message_id, &message);
+
     let msg_content = if let Some(content) = &mut message.content {
         content
     } else {
@@ -60,8 +66,13 @@ pub async fn handler(
     let edited_at = get_time_secs();
     message.edited_at = Some(edited_at);
 
-    let buf = rkyv_ser(&message);
-    chat_tree.insert(key, buf).await?;
+    search::index_message(&mut batch, guild_id, channel_id, message_id, &message);
+    batch.insert(key, rkyv_ser(&message));
+    chat_tree
+        .chat_tree
+        .apply_batch(batch)
+        .await
+        .map_err(ServerError::DbError)?;
 
     svc.send_event_through_chan(
         EventSub::Guild(guild_id),
@@ -79,7 +90,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((UpdateMessageTextResponse {}).into_response())
 }
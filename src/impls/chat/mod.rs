@@ -15,6 +15,7 @@ use crate::api::{
     sync::{
         event::{
             Kind as DispatchKind, UserAddedToGuild as SyncUserAddedToGuild,
+            UserInvited as SyncUserInvited, UserRejectedInvite as SyncUserRejectedInvite,
             UserRemovedFromGuild as SyncUserRemovedFromGuild,
         },
         Event as DispatchEvent,
@@ -25,13 +26,11 @@ use image::GenericImageView;
 use rand::{Rng, SeedableRng};
 use scherzo_derive::*;
 use smol_str::SmolStr;
-use tokio::{
-    sync::{broadcast::Sender as BroadcastSend, mpsc::UnboundedSender},
-    task::JoinHandle,
-};
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
 use triomphe::Arc;
 
 use crate::{
+    config::RetentionPolicy,
     db::{self, chat::*, rkyv_ser, Batch, Db, DbResult},
     impls::{
         get_time_millisecs,
@@ -41,24 +40,54 @@ use crate::{
     },
 };
 
+use audit_log::*;
+use automod::*;
 use channels::*;
+use event_log::*;
 use guilds::*;
+use invite_tracking::*;
 use invites::*;
 use messages::*;
 use moderation::*;
 use permissions::*;
+use slow_mode::*;
+use timeouts::*;
 
+pub mod audit_log;
+pub mod automod;
 pub mod channels;
+pub mod event_bus;
+pub mod event_log;
 pub mod guilds;
+pub mod invite_tracking;
 pub mod invites;
 pub mod messages;
 pub mod moderation;
 pub mod permissions;
+pub mod search;
+pub mod slow_mode;
 pub mod stream_events;
+pub mod timeouts;
 pub mod trigger_action;
 
 pub const DEFAULT_ROLE_ID: u64 = 0;
 
+/// Permissions the "everyone" role gets when a guild is created.
+pub fn default_permissions() -> Vec<Permission> {
+    [
+        "messages.send",
+        "messages.view",
+        "roles.get",
+        "roles.user.get",
+    ]
+    .iter()
+    .map(|m| Permission {
+        matches: m.to_string(),
+        ok: true,
+    })
+    .collect()
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum EventSub {
     Guild(u64),
@@ -113,6 +142,8 @@ pub struct EventBroadcast {
     event: Event,
     perm_check: Option<PermCheck<'static>>,
     context: EventContext,
+    /// Sequence of the event in the event log of its guild, if it was logged
+    sequence: Option<u64>,
 }
 
 impl EventBroadcast {
@@ -127,12 +158,40 @@ impl EventBroadcast {
             event,
             perm_check,
             context,
+            sequence: None,
         }
     }
 }
 
-pub type EventCanceller = BroadcastSend<u64>;
-pub type EventSender = BroadcastSend<Arc<EventBroadcast>>;
+/// Tells the event streams of a user to close. If `session_id` is set, only
+/// the streams opened with that session are closed.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamCancel {
+    pub user_id: u64,
+    pub session_id: Option<u64>,
+}
+
+impl StreamCancel {
+    pub const fn user(user_id: u64) -> Self {
+        Self {
+            user_id,
+            session_id: None,
+        }
+    }
+
+    pub const fn session(user_id: u64, session_id: u64) -> Self {
+        Self {
+            user_id,
+            session_id: Some(session_id),
+        }
+    }
+
+    pub fn matches(&self, user_id: u64, session_id: u64) -> bool {
+        self.user_id == user_id && self.session_id.map_or(true, |id| id == session_id)
+    }
+}
+
+pub type EventSender = std::sync::Arc<dyn event_bus::EventBus>;
 pub type EventDispatcher = UnboundedSender<EventDispatch>;
 
 #[derive(Clone)]
@@ -157,185 +216,240 @@ impl ChatServer {
     fn spawn_event_stream_processor(
         &self,
         user_id: u64,
+        session_id: u64,
+        mut resume_from: ResumePoints,
         socket: Socket<StreamEventsResponse, StreamEventsRequest>,
     ) -> JoinHandle<Result<(), HrpcError>> {
         let (mut sock_tx, mut sock_rx) = socket.split();
 
         let mut rx = self.deps.chat_event_sender.subscribe();
         let chat_tree = self.deps.chat_tree.clone();
+        let auth_tree = self.deps.auth_tree.clone();
 
         let fut = async move {
-            let mut subs = HashSet::with_hasher(ahash::RandomState::new());
-
-            // add initial subs
-            // TODO: optimize local guild fetching
-            let user_guilds = chat_tree.get_user_guilds(user_id).await?;
-            let initial_subs = user_guilds
-                .into_iter()
-                .filter(|g| g.server_id.is_empty())
-                .map(|g| EventSub::Guild(g.guild_id));
-            let initial_subs = initial_subs
-                .chain(iter::once(EventSub::Actions))
-                .chain(iter::once(EventSub::Homeserver));
-            for sub in initial_subs {
-                subs.insert(sub);
-            }
-
-            // keep track of failed writes and reads to decide if closing the socket is worth it
-            let mut failed_writes: u8 = 0;
-            let mut failed_reads: u8 = 0;
-            // keep track of unsubscribed event sub, if we are then don't add new guilds
-            let mut manual_sub_handling = false;
-
-            loop {
-                tokio::select! {
-                    res = sock_rx.receive_message() => {
-                        let req = match res {
-                            Ok(req) => {
-                                failed_reads = 0;
-                                req
-                            },
-                            Err(err) => {
-                                tracing::error!(
-                                    { failed_reads = %failed_reads },
-                                    "failed to read from sub read socket: {}", err,
-                                );
+            // sequence of the last logged event this stream went through for
+            // each guild, saved when it ends so the client can resume from there
+            let mut seen = ResumePoints::default();
+
+            let res: Result<(), HrpcError> = async {
+                let mut subs = HashSet::with_hasher(ahash::RandomState::new());
+
+                // add initial subs
+                // TODO: optimize local guild fetching
+                let user_guilds = chat_tree.get_user_guilds(user_id).await?;
+                let initial_subs = user_guilds
+                    .into_iter()
+                    .filter(|g| g.server_id.is_empty())
+                    .map(|g| EventSub::Guild(g.guild_id));
+                let initial_subs = initial_subs
+                    .chain(iter::once(EventSub::Actions))
+                    .chain(iter::once(EventSub::Homeserver));
+                for sub in initial_subs {
+                    subs.insert(sub);
+                }
 
-                                failed_reads += 1;
-                                if failed_reads > 5 {
-                                    return Err(err.into());
-                                } else {
-                                    continue;
+                // replay missed events of guilds the client wants to resume
+                for sub in &subs {
+                    if let EventSub::Guild(guild_id) = sub {
+                        let sequence = match resume_from.remove(guild_id) {
+                            Some(since) => {
+                                let (events, sequence) = chat_tree
+                                    .get_resumed_events_logic(user_id, *guild_id, since)
+                                    .await?;
+                                for event in events {
+                                    sock_tx
+                                        .send_message(StreamEventsResponse {
+                                            event: Some(event.into()),
+                                        })
+                                        .await?;
                                 }
+                                sequence
                             }
+                            None => chat_tree.get_event_log_sequence_logic(*guild_id).await?,
                         };
-                        if let Some(req) = req.request {
-                            use stream_events_request::*;
-
-                            tracing::debug!("got new stream events request");
+                        seen.insert(*guild_id, sequence);
+                    }
+                }
 
-                            let sub = match req {
-                                Request::SubscribeToGuild(SubscribeToGuild { guild_id }) => {
-                                    match chat_tree.check_guild_user(guild_id, user_id).await {
-                                        Ok(_) => EventSub::Guild(guild_id),
-                                        Err(err) => {
-                                            tracing::error!("{}", err);
-                                            continue;
-                                        }
+                // keep track of failed writes and reads to decide if closing the socket is worth it
+                let mut failed_writes: u8 = 0;
+                let mut failed_reads: u8 = 0;
+                // keep track of unsubscribed event sub, if we are then don't add new guilds
+                let mut manual_sub_handling = false;
+
+                loop {
+                    tokio::select! {
+                        res = sock_rx.receive_message() => {
+                            let req = match res {
+                                Ok(req) => {
+                                    failed_reads = 0;
+                                    req
+                                },
+                                Err(err) => {
+                                    tracing::error!(
+                                        { failed_reads = %failed_reads },
+                                        "failed to read from sub read socket: {}", err,
+                                    );
+
+                                    failed_reads += 1;
+                                    if failed_reads > 5 {
+                                        return Err(err.into());
+                                    } else {
+                                        continue;
                                     }
                                 }
-                                Request::SubscribeToActions(SubscribeToActions {}) => EventSub::Actions,
-                                Request::SubscribeToHomeserverEvents(SubscribeToHomeserverEvents {}) => {
-                                    EventSub::Homeserver
-                                }
-                                Request::UnsubscribeFromAll(UnsubscribeFromAll {}) => {
-                                    subs.clear();
-                                    manual_sub_handling = true;
-                                    continue;
-                                }
                             };
+                            if let Some(req) = req.request {
+                                use stream_events_request::*;
 
-                            subs.insert(sub);
-                        }
-                    }
-                    Ok(broadcast) = rx.recv() => {
-                        // handle automatic sub handling BEFORE all the other logic because otherwise
-                        // `subs.contains()` will just return
-                        if manual_sub_handling.not() {
-                            match &broadcast.event {
-                                Event::Chat(stream_event::Event::GuildAddedToList(guild)) => subs.insert(EventSub::Guild(guild.guild_id)),
-                                Event::Chat(stream_event::Event::GuildRemovedFromList(guild)) => subs.remove(&EventSub::Guild(guild.guild_id)),
-                                _ => false,
-                            };
-                        }
+                                tracing::debug!("got new stream events request");
 
-                        if !subs.contains(&broadcast.sub) {
-                            continue;
-                        }
+                                let sub = match req {
+                                    Request::SubscribeToGuild(SubscribeToGuild { guild_id }) => {
+                                        if let Err(err) = chat_tree.check_guild_user(guild_id, user_id).await {
+                                            tracing::error!("{}", err);
+                                            continue;
+                                        }
+                                        let sequence = match resume_from.remove(&guild_id) {
+                                            Some(since) => {
+                                                let (events, sequence) = chat_tree.get_resumed_events_logic(user_id, guild_id, since).await?;
+                                                for event in events {
+                                                    sock_tx.send_message(StreamEventsResponse { event: Some(event.into()) }).await?;
+                                                }
+                                                sequence
+                                            }
+                                            None => chat_tree.get_event_log_sequence_logic(guild_id).await?,
+                                        };
+                                        seen.insert(guild_id, sequence);
+                                        EventSub::Guild(guild_id)
+                                    }
+                                    Request::SubscribeToActions(SubscribeToActions {}) => EventSub::Actions,
+                                    Request::SubscribeToHomeserverEvents(SubscribeToHomeserverEvents {}) => {
+                                        EventSub::Homeserver
+                                    }
+                                    Request::UnsubscribeFromAll(UnsubscribeFromAll {}) => {
+                                        subs.clear();
+                                        manual_sub_handling = true;
+                                        continue;
+                                    }
+                                };
 
-                        if !broadcast.context.user_ids.is_empty() && !broadcast.context.user_ids.contains(&user_id) {
-                            continue;
+                                subs.insert(sub);
+                            }
                         }
+                        Ok(broadcast) = rx.recv() => {
+                            // handle automatic sub handling BEFORE all the other logic because otherwise
+                            // `subs.contains()` will just return
+                            if manual_sub_handling.not() {
+                                match &broadcast.event {
+                                    Event::Chat(stream_event::Event::GuildAddedToList(guild)) => subs.insert(EventSub::Guild(guild.guild_id)),
+                                    Event::Chat(stream_event::Event::GuildRemovedFromList(guild)) => subs.remove(&EventSub::Guild(guild.guild_id)),
+                                    _ => false,
+                                };
+                            }
 
-                        let perm_check = match broadcast.perm_check {
-                            Some(PermCheck {
-                                guild_id,
-                                channel_id,
-                                check_for,
-                                must_be_guild_owner,
-                            }) => {
-                                let perm = chat_tree.check_perms(
-                                    guild_id,
-                                    channel_id,
-                                    user_id,
-                                    check_for,
-                                    must_be_guild_owner,
-                                ).await;
-
-                                matches!(perm, Ok(_) | Err(ServerError::EmptyPermissionQuery))
+                            if !subs.contains(&broadcast.sub) {
+                                continue;
                             }
-                            None => true,
-                        };
 
+                            if let (EventSub::Guild(guild_id), Some(sequence)) = (broadcast.sub, broadcast.sequence) {
+                                match seen.get(&guild_id).copied() {
+                                    // already sent while resuming
+                                    Some(last) if sequence <= last => continue,
+                                    // events were missed, eg. because this stream lagged behind, so
+                                    // send them from the log, which includes this event too
+                                    Some(last) if sequence > last + 1 => {
+                                        let (events, sequence) = chat_tree.get_resumed_events_logic(user_id, guild_id, last).await?;
+                                        for event in events {
+                                            sock_tx.send_message(StreamEventsResponse { event: Some(event.into()) }).await?;
+                                        }
+                                        seen.insert(guild_id, sequence);
+                                        continue;
+                                    }
+                                    _ => {
+                                        seen.insert(guild_id, sequence);
+                                    }
+                                }
+                            }
 
-                        if !perm_check {
-                            continue;
-                        }
+                            let can_receive = chat_tree
+                                .can_user_receive_event(user_id, &broadcast.context.user_ids, broadcast.perm_check)
+                                .await;
 
-                        tracing::debug!("writing event to socket");
+                            if !can_receive {
+                                continue;
+                            }
 
-                        let write_res = sock_tx
-                            .send_message(StreamEventsResponse {
-                                event: Some(broadcast.event.clone().into()),
-                            })
-                            .await;
+                            tracing::debug!("writing event to socket");
 
-                        match write_res {
-                            Ok(_) => failed_writes = 0,
-                            Err(err) => {
-                                tracing::error!(
-                                    "couldnt write to stream events socket: {}",
-                                    err
-                                );
-                                failed_writes += 1;
-                                if failed_writes > 5 {
-                                    return Err(err.into());
+                            let write_res = sock_tx
+                                .send_message(StreamEventsResponse {
+                                    event: Some(broadcast.event.clone().into()),
+                                })
+                                .await;
+
+                            match write_res {
+                                Ok(_) => failed_writes = 0,
+                                Err(err) => {
+                                    tracing::error!(
+                                        "couldnt write to stream events socket: {}",
+                                        err
+                                    );
+                                    failed_writes += 1;
+                                    if failed_writes > 5 {
+                                        return Err(err.into());
+                                    }
                                 }
                             }
                         }
+                        else => tokio::task::yield_now().await,
                     }
-                    else => tokio::task::yield_now().await,
                 }
+
+                #[allow(unreachable_code)]
+                Ok(())
+            }
+            .await;
+
+            if let Err(err) = auth_tree
+                .set_stream_resume_points_logic(user_id, session_id, &seen)
+                .await
+            {
+                tracing::error!("couldnt save stream resume points: {}", err);
             }
 
-            #[allow(unreachable_code)]
-            Ok(())
+            res
         };
 
         tokio::spawn(fut)
     }
 
-    #[inline(always)]
-    fn send_event_through_chan(
+    /// Logs the event if it's a guild event, then broadcasts it to streams.
+    async fn send_event_through_chan(
         &self,
         sub: EventSub,
         event: stream_event::Event,
         perm_check: Option<PermCheck<'static>>,
         context: EventContext,
     ) {
-        let broadcast = EventBroadcast {
-            sub,
-            event: Event::Chat(event),
-            perm_check,
-            context,
-        };
+        let mut broadcast = EventBroadcast::new(sub, Event::Chat(event), perm_check, context);
+
+        let max_events = self.deps.config.policy.event_log.max_events;
+        if let Err(err) = self
+            .deps
+            .chat_tree
+            .log_event_logic(&mut broadcast, max_events)
+            .await
+        {
+            tracing::error!("couldnt log event: {}", err);
+        }
 
         tracing::debug!(
             "broadcasting events to {} receivers",
             self.deps.chat_event_sender.receiver_count()
         );
 
-        drop(self.deps.chat_event_sender.send(Arc::new(broadcast)));
+        self.deps.chat_event_sender.send(Arc::new(broadcast));
     }
 
     #[inline(always)]
@@ -369,7 +483,8 @@ impl ChatServer {
                     }),
                     None,
                     EventContext::new(vec![user_id]),
-                );
+                )
+                .await;
             }
         }
         Ok(())
@@ -397,13 +512,151 @@ impl ChatServer {
                     }),
                     None,
                     EventContext::new(vec![user_id]),
-                );
+                )
+                .await;
             }
         }
         Ok(())
     }
 
-    fn send_reaction_event(
+    /// Sends a system message to the first channel of a guild, if it has any channels.
+    async fn send_system_message(
+        &self,
+        guild_id: u64,
+        content: content::Content,
+    ) -> ServerResult<()> {
+        let chat_tree = &self.deps.chat_tree;
+
+        let maybe_channel_id = chat_tree
+            .get_list_u64_logic(&make_guild_chan_ordering_key(guild_id))
+            .await?
+            .first()
+            .copied();
+
+        if let Some(channel_id) = maybe_channel_id {
+            let (message_id, message) = chat_tree
+                .send_with_system(guild_id, channel_id, content)
+                .await?;
+            self.send_event_through_chan(
+                EventSub::Guild(guild_id),
+                stream_event::Event::SentMessage(stream_event::MessageSent {
+                    echo_id: None,
+                    guild_id,
+                    channel_id,
+                    message_id,
+                    message: Some(message),
+                }),
+                Some(PermCheck::new(
+                    guild_id,
+                    Some(channel_id),
+                    "messages.view",
+                    false,
+                )),
+                EventContext::empty(),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes an invite that was targeted at `user_id` and notifies the guild
+    /// that the user rejected it.
+    pub(crate) async fn reject_user_invite(
+        &self,
+        invite_id: &str,
+        user_id: u64,
+    ) -> ServerResult<()> {
+        let chat_tree = &self.deps.chat_tree;
+
+        let (guild_id, invitee_id, inviter_id) =
+            match chat_tree.get_user_invite_logic(invite_id).await? {
+                Some(ids) if ids.1 == user_id => ids,
+                _ => bail!(ServerError::NoSuchInvite(invite_id.into())),
+            };
+
+        chat_tree.delete_invite_logic(invite_id.to_string()).await?;
+
+        self.send_event_through_chan(
+            EventSub::Guild(guild_id),
+            stream_event::Event::InviteRejected(stream_event::InviteRejected {
+                guild_id,
+                invite_id: invite_id.to_string(),
+                user_id,
+            }),
+            Some(PermCheck::new(guild_id, None, "invites.view", false)),
+            EventContext::empty(),
+        )
+        .await;
+
+        let content = content::Content::InviteRejected(content::InviteRejected {
+            invitee_id,
+            inviter_id,
+        });
+        self.send_system_message(guild_id, content).await
+    }
+
+    /// Adds a user to a guild without an invite, eg. for guilds that users
+    /// join when they register. Returns `false` if the user is already in the
+    /// guild or is banned from it.
+    pub(crate) async fn add_member(&self, guild_id: u64, user_id: u64) -> ServerResult<bool> {
+        let chat_tree = &self.deps.chat_tree;
+
+        chat_tree.does_guild_exist(guild_id).await?;
+        if chat_tree.is_user_in_guild(guild_id, user_id).await.is_ok()
+            || chat_tree.is_user_banned_in_guild(guild_id, user_id).await?
+        {
+            return Ok(false);
+        }
+
+        chat_tree
+            .insert(make_member_key(guild_id, user_id), [])
+            .await?;
+        chat_tree.add_default_role_to(guild_id, user_id).await?;
+
+        self.send_event_through_chan(
+            EventSub::Guild(guild_id),
+            stream_event::Event::JoinedMember(stream_event::MemberJoined {
+                guild_id,
+                member_id: user_id,
+            }),
+            None,
+            EventContext::empty(),
+        )
+        .await;
+        self.dispatch_guild_join(guild_id, user_id).await?;
+
+        Ok(true)
+    }
+
+    /// Removes a user from a guild as if they were kicked. Returns `false` if
+    /// the user wasn't in the guild.
+    pub(crate) async fn remove_member(&self, guild_id: u64, user_id: u64) -> ServerResult<bool> {
+        let chat_tree = &self.deps.chat_tree;
+
+        if chat_tree.is_user_in_guild(guild_id, user_id).await.is_err() {
+            return Ok(false);
+        }
+
+        chat_tree.kick_user_logic(guild_id, user_id).await?;
+
+        self.send_event_through_chan(
+            EventSub::Guild(guild_id),
+            stream_event::Event::LeftMember(stream_event::MemberLeft {
+                guild_id,
+                member_id: user_id,
+                leave_reason: LeaveReason::Kicked.into(),
+            }),
+            None,
+            EventContext::empty(),
+        )
+        .await;
+        self.dispatch_guild_leave(guild_id, user_id).await?;
+
+        Ok(true)
+    }
+
+    async fn send_reaction_event(
         &self,
         guild_id: u64,
         channel_id: u64,
@@ -425,7 +678,8 @@ impl ChatServer {
                 must_be_guild_owner: false,
             }),
             EventContext::empty(),
-        );
+        )
+        .await;
     }
 }
 
@@ -520,12 +774,18 @@ impl chat_service_server::ChatService for ChatServer {
         grant_ownership, GrantOwnershipRequest, GrantOwnershipResponse;
         #[rate(2, 60)]
         give_up_ownership, GiveUpOwnershipRequest, GiveUpOwnershipResponse;
+        #[rate(1, 5)]
         create_room, CreateRoomRequest, CreateRoomResponse;
+        #[rate(1, 5)]
         create_direct_message, CreateDirectMessageRequest, CreateDirectMessageResponse;
         upgrade_room_to_guild, UpgradeRoomToGuildRequest, UpgradeRoomToGuildResponse;
+        #[rate(4, 5)]
         invite_user_to_guild, InviteUserToGuildRequest, InviteUserToGuildResponse;
+        #[rate(7, 5)]
         get_pending_invites, GetPendingInvitesRequest, GetPendingInvitesResponse;
+        #[rate(4, 5)]
         reject_pending_invite, RejectPendingInviteRequest, RejectPendingInviteResponse;
+        #[rate(4, 5)]
         ignore_pending_invite, IgnorePendingInviteRequest, IgnorePendingInviteResponse;
     }
 
@@ -561,6 +821,10 @@ impl AdminGuildKeys {
 pub struct ChatTree {
     pub chat_tree: Tree,
     pub admin_guild_keys: SyncOnceCell<AdminGuildKeys>,
+    /// Permissions guests are limited to
+    pub guest_permissions: SyncOnceCell<Vec<String>>,
+    /// Compiled regex automod rules of guilds, by their pattern
+    pub automod_regexes: Arc<dashmap::DashMap<u64, automod::RuleRegexes, ahash::RandomState>>,
 }
 
 impl ChatTree {
@@ -571,6 +835,8 @@ impl ChatTree {
         Ok(Self {
             chat_tree,
             admin_guild_keys: SyncOnceCell::new(),
+            guest_permissions: SyncOnceCell::new(),
+            automod_regexes: Default::default(),
         })
     }
 
@@ -724,6 +990,18 @@ impl ChatTree {
         Ok(guild)
     }
 
+    pub async fn get_guild_kind_logic(&self, guild_id: u64) -> ServerResult<guild_kind::Kind> {
+        let kind = self
+            .get_guild_logic(guild_id)
+            .await?
+            .kind
+            .and_then(|k| k.kind)
+            // guilds created before kinds were introduced are normal guilds
+            .unwrap_or_else(|| guild_kind::Kind::new_normal(guild_kind::Normal::new()));
+
+        Ok(kind)
+    }
+
     pub async fn put_guild_logic(&self, guild_id: u64, guild: Guild) -> ServerResult<()> {
         let buf = rkyv_ser(&guild);
         self.insert(guild_id.to_be_bytes(), buf)
@@ -1055,15 +1333,16 @@ impl ChatTree {
         must_be_guild_owner: bool,
     ) -> Result<(), ServerError> {
         let is_owner = self.is_user_guild_owner(guild_id, user_id).await?;
-        if must_be_guild_owner {
-            if is_owner {
-                return Ok(());
-            }
-        } else if is_owner
-            || self
-                .query_has_permission_logic(guild_id, channel_id, user_id, check_for)
-                .await?
-        {
+        let is_allowed = if must_be_guild_owner {
+            is_owner
+        } else {
+            is_owner
+                || self
+                    .query_has_permission_logic(guild_id, channel_id, user_id, check_for)
+                    .await?
+        };
+        if is_allowed && self.is_guest_allowed(user_id, check_for).await? {
+            self.check_not_timed_out(guild_id, user_id, check_for).await?;
             return Ok(());
         }
         Err(ServerError::NotEnoughPermissions {
@@ -1072,6 +1351,63 @@ impl ChatTree {
         })
     }
 
+    pub async fn is_guest_logic(&self, user_id: u64) -> Result<bool, ServerError> {
+        self.contains_key(make_guest_key(user_id)).await
+    }
+
+    /// Errors with [`ServerError::GuestsNotAllowed`] if the user is a guest.
+    pub async fn check_not_guest(&self, user_id: u64) -> Result<(), ServerError> {
+        if self.is_guest_logic(user_id).await? {
+            return Err(ServerError::GuestsNotAllowed);
+        }
+        Ok(())
+    }
+
+    /// Guests can only have the permissions in the guest config, no matter
+    /// what their roles give them.
+    async fn is_guest_allowed(&self, user_id: u64, check_for: &str) -> Result<bool, ServerError> {
+        if self.is_guest_logic(user_id).await?.not() {
+            return Ok(true);
+        }
+        let guest_perms = self.guest_permissions.get().map_or(&[][..], Vec::as_slice);
+        let is_allowed = has_permission(guest_perms.iter().map(|m| (m.as_str(), true)), check_for);
+        Ok(matches!(is_allowed, Some(true)))
+    }
+
+    /// Checks if an event with the given context and permission check should be sent to a user.
+    pub async fn can_user_receive_event(
+        &self,
+        user_id: u64,
+        context_user_ids: &HashSet<u64, ahash::RandomState>,
+        perm_check: Option<PermCheck<'_>>,
+    ) -> bool {
+        if !context_user_ids.is_empty() && !context_user_ids.contains(&user_id) {
+            return false;
+        }
+
+        match perm_check {
+            Some(PermCheck {
+                guild_id,
+                channel_id,
+                check_for,
+                must_be_guild_owner,
+            }) => {
+                let perm = self
+                    .check_perms(
+                        guild_id,
+                        channel_id,
+                        user_id,
+                        check_for,
+                        must_be_guild_owner,
+                    )
+                    .await;
+
+                matches!(perm, Ok(_) | Err(ServerError::EmptyPermissionQuery))
+            }
+            None => true,
+        }
+    }
+
     pub async fn kick_user_logic(&self, guild_id: u64, user_id: u64) -> ServerResult<()> {
         let mut batch = Batch::default();
         batch.remove(make_member_key(guild_id, user_id));
@@ -1239,35 +1575,10 @@ impl ChatTree {
         }
 
         // Some basic default setup
-        let everyone_role_id = self
-            .add_guild_role_logic(
-                guild_id,
-                // "everyone" role must have id 0 according to protocol
-                Some(DEFAULT_ROLE_ID),
-                Role {
-                    name: "everyone".to_string(),
-                    pingable: false,
-                    ..Default::default()
-                },
-            )
-            .await?;
+        self.add_everyone_role_logic(guild_id).await?;
         if user_id != 0 {
             self.add_default_role_to(guild_id, user_id).await?;
         }
-        let def_perms = [
-            "messages.send",
-            "messages.view",
-            "roles.get",
-            "roles.user.get",
-        ]
-        .iter()
-        .map(|m| Permission {
-            matches: m.to_string(),
-            ok: true,
-        })
-        .collect::<Vec<_>>();
-        self.set_permissions_logic(guild_id, None, everyone_role_id, def_perms.clone())
-            .await?;
         let channel_id = self
             .create_channel_logic(
                 guild_id,
@@ -1278,17 +1589,76 @@ impl ChatTree {
             )
             .await?;
 
-        self.set_permissions_logic(guild_id, Some(channel_id), everyone_role_id, def_perms)
-            .await?;
+        self.set_permissions_logic(
+            guild_id,
+            Some(channel_id),
+            DEFAULT_ROLE_ID,
+            default_permissions(),
+        )
+        .await?;
 
         Ok(guild_id)
     }
 
+    /// Creates the "everyone" role of a guild with the default permissions.
+    pub async fn add_everyone_role_logic(&self, guild_id: u64) -> ServerResult<()> {
+        let everyone_role_id = self
+            .add_guild_role_logic(
+                guild_id,
+                // "everyone" role must have id 0 according to protocol
+                Some(DEFAULT_ROLE_ID),
+                Role {
+                    name: "everyone".to_string(),
+                    pingable: false,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        self.set_permissions_logic(guild_id, None, everyone_role_id, default_permissions())
+            .await
+    }
+
+    pub async fn upgrade_room_to_guild_logic(&self, guild_id: u64) -> ServerResult<()> {
+        let mut guild = self.get_guild_logic(guild_id).await?;
+
+        if !matches!(
+            guild.kind.as_ref().and_then(|k| k.kind.as_ref()),
+            Some(guild_kind::Kind::Room(_))
+        ) {
+            bail!(("h.not-a-room", "only rooms can be upgraded to a guild"));
+        }
+
+        guild.kind = Some(GuildKind {
+            kind: Some(guild_kind::Kind::new_normal(guild_kind::Normal::new())),
+        });
+        self.put_guild_logic(guild_id, guild).await?;
+
+        // make sure the guild has the same roles and permissions a newly created guild would have
+        if self
+            .does_role_exist(guild_id, DEFAULT_ROLE_ID)
+            .await
+            .is_err()
+        {
+            self.add_everyone_role_logic(guild_id).await?;
+        }
+        for member_id in self.get_guild_members_logic(guild_id).await?.members {
+            let roles = self.get_user_roles_logic(guild_id, member_id).await?;
+            if !roles.contains(&DEFAULT_ROLE_ID) {
+                self.add_default_role_to(guild_id, member_id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates an invite to a guild. The invite expires at `expires_at` if
+    /// it's set.
     pub async fn create_invite_logic(
         &self,
         guild_id: u64,
         name: &str,
         possible_uses: u32,
+        expires_at: Option<u64>,
     ) -> ServerResult<()> {
         let key = make_invite_key(name);
 
@@ -1311,10 +1681,97 @@ impl ChatTree {
             [guild_id.to_be_bytes().as_ref(), buf.as_ref()].concat(),
         )
         .await?;
+        self.clear_invite_uses_logic(guild_id, name).await?;
+        if let Some(expires_at) = expires_at {
+            self.set_invite_expiry_logic(name, expires_at).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks an invite as being targeted at a specific user.
+    pub async fn add_user_invite_logic(
+        &self,
+        invite_id: &str,
+        guild_id: u64,
+        invitee_id: u64,
+        inviter_id: u64,
+    ) -> ServerResult<()> {
+        let value = [
+            guild_id.to_be_bytes(),
+            invitee_id.to_be_bytes(),
+            inviter_id.to_be_bytes(),
+        ]
+        .concat();
+        self.insert(make_user_invite_key(invite_id), value).await?;
+        Ok(())
+    }
+
+    /// Returns the guild ID, invitee ID and inviter ID of an invite that was
+    /// targeted at a specific user.
+    pub async fn get_user_invite_logic(
+        &self,
+        invite_id: &str,
+    ) -> ServerResult<Option<(u64, u64, u64)>> {
+        Ok(self.get(make_user_invite_key(invite_id)).await?.map(|raw| {
+            // Safety: we only store three u64s for these keys
+            let mut ids = raw.chunks_exact(size_of::<u64>()).map(deser_id);
+            (
+                ids.next().unwrap(),
+                ids.next().unwrap(),
+                ids.next().unwrap(),
+            )
+        }))
+    }
 
+    pub async fn add_pending_invite_logic(
+        &self,
+        user_id: u64,
+        server_id: &str,
+        invite_id: &str,
+        inviter_id: u64,
+    ) -> ServerResult<()> {
+        let key = make_pending_invite_key(user_id, server_id, invite_id);
+        self.insert(key, inviter_id.to_be_bytes()).await?;
         Ok(())
     }
 
+    /// Removes a pending invite, returning the inviter ID if it existed.
+    pub async fn remove_pending_invite_logic(
+        &self,
+        user_id: u64,
+        server_id: &str,
+        invite_id: &str,
+    ) -> ServerResult<Option<u64>> {
+        let key = make_pending_invite_key(user_id, server_id, invite_id);
+        Ok(self.remove(&key).await?.map(deser_id))
+    }
+
+    pub async fn get_pending_invites_logic(
+        &self,
+        user_id: u64,
+    ) -> ServerResult<Vec<PendingInvite>> {
+        let prefix = make_pending_invite_prefix(user_id);
+        self.scan_prefix(&prefix)
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, value) = res?;
+                let (_, raw) = key.split_at(prefix.len());
+                let separator = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+                let (raw_server_id, raw_invite_id) = raw.split_at(separator);
+                // Safety: all stored hosts and invite IDs are valid UTF-8
+                let server_id = unsafe { std::str::from_utf8_unchecked(raw_server_id) };
+                let invite_id =
+                    unsafe { std::str::from_utf8_unchecked(raw_invite_id.get(1..).unwrap_or(&[])) };
+                all.push(PendingInvite {
+                    invite_id: invite_id.to_string(),
+                    server_id: server_id.is_empty().not().then(|| server_id.to_string()),
+                    inviter_id: deser_id(value),
+                });
+                ServerResult::Ok(all)
+            })
+    }
+
     /// Calculates all users which can "see" the given user
     pub async fn calculate_users_seeing_user(&self, user_id: u64) -> ServerResult<Vec<u64>> {
         let prefix = make_guild_list_key_prefix(user_id);
@@ -1520,7 +1977,13 @@ impl ChatTree {
         };
 
         let value = db::rkyv_ser(&message);
-        self.insert(key, value).await?;
+        let mut batch = Batch::default();
+        batch.insert(key, value);
+        search::index_message(&mut batch, guild_id, channel_id, message_id, &message);
+        self.chat_tree
+            .apply_batch(batch)
+            .await
+            .map_err(ServerError::DbError)?;
 
         Ok((message_id, message))
     }
@@ -1894,8 +2357,242 @@ impl ChatTree {
         }))
     }
 
+    /// Gets the retention policy set for a guild, or for a channel if `channel_id` is set.
+    /// This doesn't take the policies it inherits into account.
+    pub async fn get_retention_policy_logic(
+        &self,
+        guild_id: u64,
+        channel_id: Option<u64>,
+    ) -> ServerResult<RetentionPolicy> {
+        let raw = match channel_id {
+            Some(channel_id) => {
+                self.get(make_chan_retention_key(guild_id, channel_id))
+                    .await?
+            }
+            None => self.get(make_guild_retention_key(guild_id)).await?,
+        };
+
+        Ok(raw.map_or_else(RetentionPolicy::default, |raw| {
+            // Safety: we only store two u64s for these keys, 0 meaning that the limit isn't set
+            let mut limits = raw
+                .chunks_exact(size_of::<u64>())
+                .map(deser_id)
+                .map(|limit| (limit != 0).then(|| limit));
+            RetentionPolicy {
+                max_age: limits.next().flatten(),
+                max_count: limits.next().flatten(),
+            }
+        }))
+    }
+
+    pub async fn set_retention_policy_logic(
+        &self,
+        guild_id: u64,
+        channel_id: Option<u64>,
+        policy: RetentionPolicy,
+    ) -> ServerResult<()> {
+        let key = match channel_id {
+            Some(channel_id) => {
+                self.does_channel_exist(guild_id, channel_id).await?;
+                make_chan_retention_key(guild_id, channel_id).to_vec()
+            }
+            None => {
+                self.check_guild(guild_id).await?;
+                make_guild_retention_key(guild_id).to_vec()
+            }
+        };
+
+        if policy.is_unlimited() {
+            self.remove(key).await?;
+        } else {
+            let value = [
+                policy.max_age.unwrap_or(0).to_be_bytes(),
+                policy.max_count.unwrap_or(0).to_be_bytes(),
+            ]
+            .concat();
+            let mut batch = Batch::default();
+            batch.insert(key, value);
+            batch.insert(make_retention_guild_key(guild_id), []);
+            self.apply_batch(batch).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the guilds that have a retention policy set on them or one of
+    /// their channels. Might include guilds that don't have one anymore.
+    pub async fn get_retention_guild_ids_logic(&self) -> Result<Vec<u64>, ServerError> {
+        let mut guild_ids = Vec::new();
+        for res in self.scan_prefix(RETENTION_GUILD_PREFIX).await {
+            let (key, _) = res?;
+            guild_ids.push(deser_id(&key[RETENTION_GUILD_PREFIX.len()..]));
+        }
+        Ok(guild_ids)
+    }
+
+    /// Removes messages that are outside of the given retention policy from a channel.
+    /// Pinned messages are never removed. Returns the IDs of the removed messages.
+    pub async fn prune_channel_messages_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        policy: RetentionPolicy,
+    ) -> ServerResult<Vec<u64>> {
+        if policy.is_unlimited() {
+            return Ok(Vec::new());
+        }
+
+        let pinned = self
+            .get_list_u64_logic(&make_pinned_msgs_key(guild_id, channel_id))
+            .await?;
+        let oldest_allowed = policy
+            .max_age
+            .map(|max_age| get_time_millisecs().saturating_sub(max_age.saturating_mul(1000)));
+
+        let prefix = make_msg_prefix(guild_id, channel_id);
+        let msg_key_len = prefix.len() + size_of::<u64>();
+
+        // message IDs are sequential, so these are sorted from oldest to newest
+        let mut messages = Vec::new();
+        let mut message_keys = Vec::new();
+        for res in self.scan_prefix(&prefix).await {
+            let (key, value) = res?;
+            if key.len() == msg_key_len {
+                let message_id = deser_id(key.split_at(prefix.len()).1);
+                if pinned.contains(&message_id).not() {
+                    let created_at = rkyv_arch::<HarmonyMessage>(&value).created_at;
+                    messages.push((message_id, created_at));
+                    message_keys.push(vec![key]);
+                }
+            } else if let Some(keys) = message_keys.last_mut() {
+                // keys for reactions are prefixed by their message key
+                if key.starts_with(&keys[0]) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        let over_count = policy.max_count.map_or(0, |max_count| {
+            (messages.len() as u64).saturating_sub(max_count) as usize
+        });
+
+        let mut batch = Batch::default();
+        let mut pruned = Vec::new();
+        for (index, ((message_id, created_at), keys)) in
+            messages.into_iter().zip(message_keys).enumerate()
+        {
+            let too_old = oldest_allowed.map_or(false, |oldest| created_at < oldest);
+            if index < over_count || too_old {
+                if let Some(raw) = self.get(&keys[0]).await? {
+                    let message = db::deser_message(raw);
+                    search::unindex_message(&mut batch, guild_id, channel_id, message_id, &message);
+                }
+                for key in keys {
+                    batch.remove(key);
+                }
+                pruned.push(message_id);
+            }
+        }
+
+        if pruned.is_empty().not() {
+            self.chat_tree
+                .apply_batch(batch)
+                .await
+                .map_err(ServerError::DbError)?;
+        }
+
+        Ok(pruned)
+    }
+
+    pub async fn get_all_guild_ids_logic(&self) -> Result<Vec<u64>, ServerError> {
+        self.chat_tree
+            .iter()
+            .await
+            .try_fold(Vec::new(), |mut all, res| {
+                let (key, _) = res.map_err(ServerError::DbError)?;
+                // only guild keys are exactly one u64 long
+                if key.len() == size_of::<u64>() {
+                    all.push(deser_id(key));
+                }
+                Ok(all)
+            })
+    }
+
+    /// Applies retention policies to all channels in all guilds, using `default` for
+    /// the limits that aren't set by a guild or channel. Returns the guild, channel and
+    /// message IDs of the removed messages.
+    ///
+    /// If `default` doesn't limit anything, only the guilds that have a policy
+    /// set are looked at, otherwise every guild is.
+    pub async fn prune_messages_logic(
+        &self,
+        default: RetentionPolicy,
+    ) -> ServerResult<Vec<(u64, u64, u64)>> {
+        let guild_ids = if default.is_unlimited() {
+            self.get_retention_guild_ids_logic().await?
+        } else {
+            self.get_all_guild_ids_logic().await?
+        };
+
+        let mut pruned = Vec::new();
+        for guild_id in guild_ids {
+            let guild_policy = self.get_retention_policy_logic(guild_id, None).await?;
+            let mut has_policy = guild_policy.is_unlimited().not();
+            let guild_policy = guild_policy.or(default);
+            let channel_ids = self
+                .get_list_u64_logic(&make_guild_chan_ordering_key(guild_id))
+                .await?;
+            for channel_id in channel_ids {
+                let policy = self
+                    .get_retention_policy_logic(guild_id, Some(channel_id))
+                    .await?;
+                has_policy |= policy.is_unlimited().not();
+                let message_ids = self
+                    .prune_channel_messages_logic(guild_id, channel_id, policy.or(guild_policy))
+                    .await?;
+                pruned.extend(
+                    message_ids
+                        .into_iter()
+                        .map(|message_id| (guild_id, channel_id, message_id)),
+                );
+            }
+
+            // the policies were removed, or the guild was deleted
+            if has_policy.not() {
+                self.remove(make_retention_guild_key(guild_id)).await?;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Deletes all invites of a guild, along with the pending invites of the
+    /// local users they were sent to.
+    pub async fn delete_guild_invites_logic(&self, guild_id: u64) -> ServerResult<()> {
+        let mut invite_ids = Vec::new();
+        for res in self.scan_prefix(INVITE_PREFIX).await {
+            let (key, value) = res?;
+            if db::deser_invite_entry_guild_id(&value) == guild_id {
+                invite_ids.push(String::from_utf8_lossy(&key[INVITE_PREFIX.len()..]).into_owned());
+            }
+        }
+
+        for invite_id in invite_ids {
+            if let Some((_, invitee_id, _)) = self.get_user_invite_logic(&invite_id).await? {
+                self.remove_pending_invite_logic(invitee_id, "", &invite_id)
+                    .await?;
+            }
+            self.delete_invite_logic(invite_id).await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn delete_invite_logic(&self, invite_id: String) -> Result<(), ServerError> {
         self.remove(make_invite_key(invite_id.as_str())).await?;
+        self.remove(make_user_invite_key(invite_id.as_str()))
+            .await?;
+        self.remove_invite_expiry_logic(invite_id.as_str()).await?;
         Ok(())
     }
 }
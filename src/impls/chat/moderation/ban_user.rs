@@ -5,6 +5,8 @@ pub async fn handler(
     request: Request<BanUserRequest>,
 ) -> ServerResult<Response<BanUserResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
+    let duration = get_ban_duration(&request)?;
 
     let BanUserRequest {
         guild_id,
@@ -25,10 +27,13 @@ pub async fn handler(
 
     chat_tree.kick_user_logic(guild_id, user_to_ban).await?;
 
+    let now = get_time_secs();
     chat_tree
-        .insert(
-            make_banned_member_key(guild_id, user_to_ban),
-            get_time_secs().to_be_bytes(),
+        .ban_user_logic(
+            guild_id,
+            user_to_ban,
+            now,
+            duration.map(|duration| now + duration),
         )
         .await?;
 
@@ -41,9 +46,20 @@ pub async fn handler(
         }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
 
     svc.dispatch_guild_leave(guild_id, user_to_ban).await?;
 
+    svc.record_audit_log(
+        guild_id,
+        user_id,
+        AuditAction::Ban,
+        user_to_ban,
+        None,
+        reason,
+    )
+    .await?;
+
     Ok((BanUserResponse {}).into_response())
 }
@@ -1,5 +1,7 @@
 use super::*;
 
+/// Lists the users banned from a guild. The response can't say when their bans
+/// expire, clients can get that from the `/_harmony/guilds/bans` endpoint.
 pub async fn handler(
     svc: &ChatServer,
     request: Request<GetBannedUsersRequest>,
@@ -8,19 +10,14 @@ pub async fn handler(
 
     let GetBannedUsersRequest { guild_id } = request.into_message().await?;
 
-    let prefix = make_guild_banned_mem_prefix(guild_id);
-    let banned_users =
-        svc.deps
-            .chat_tree
-            .scan_prefix(&prefix)
-            .await
-            .try_fold(Vec::new(), |mut all, res| {
-                let (key, _) = res?;
-                if key.len() == make_banned_member_key(0, 0).len() {
-                    all.push(deser_id(key.split_at(prefix.len()).1));
-                }
-                ServerResult::Ok(all)
-            })?;
+    let banned_users = svc
+        .deps
+        .chat_tree
+        .get_guild_bans_logic(guild_id)
+        .await?
+        .into_iter()
+        .map(|(user_id, _, _)| user_id)
+        .collect();
 
     Ok((GetBannedUsersResponse { banned_users }).into_response())
 }
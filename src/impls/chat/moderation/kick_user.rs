@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<KickUserRequest>,
 ) -> ServerResult<Response<KickUserResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
 
     let KickUserRequest {
         guild_id,
@@ -34,9 +35,20 @@ pub async fn handler(
         }),
         None,
         EventContext::empty(),
-    );
+    )
+    .await;
 
     svc.dispatch_guild_leave(guild_id, user_to_kick).await?;
 
+    svc.record_audit_log(
+        guild_id,
+        user_id,
+        AuditAction::Kick,
+        user_to_kick,
+        None,
+        reason,
+    )
+    .await?;
+
     Ok((KickUserResponse {}).into_response())
 }
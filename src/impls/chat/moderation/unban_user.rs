@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<UnbanUserRequest>,
 ) -> ServerResult<Response<UnbanUserResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
 
     let UnbanUserRequest {
         guild_id,
@@ -18,9 +19,17 @@ pub async fn handler(
         .check_perms(guild_id, None, user_id, "user.manage.unban", false)
         .await?;
 
-    chat_tree
-        .remove(make_banned_member_key(guild_id, user_to_unban))
-        .await?;
+    chat_tree.unban_user_logic(guild_id, user_to_unban).await?;
+
+    svc.record_audit_log(
+        guild_id,
+        user_id,
+        AuditAction::Unban,
+        user_to_unban,
+        None,
+        reason,
+    )
+    .await?;
 
     Ok((UnbanUserResponse {}).into_response())
 }
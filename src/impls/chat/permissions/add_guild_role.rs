@@ -46,7 +46,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((AddGuildRoleResponse { role_id }).into_response())
 }
@@ -32,7 +32,8 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
 
     Ok((DeleteGuildRoleResponse {}).into_response())
 }
@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<ManageUserRolesRequest>,
 ) -> ServerResult<Response<ManageUserRolesResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
 
     let ManageUserRolesRequest {
         guild_id,
@@ -39,7 +40,18 @@ pub async fn handler(
         }),
         Some(PermCheck::new(guild_id, None, "roles.user.get", false)),
         EventContext::empty(),
-    );
+    )
+    .await;
+
+    svc.record_audit_log(
+        guild_id,
+        user_id,
+        AuditAction::UserRolesUpdated,
+        user_to_manage,
+        None,
+        reason,
+    )
+    .await?;
 
     Ok((ManageUserRolesResponse {}).into_response())
 }
@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<ModifyGuildRoleRequest>,
 ) -> ServerResult<Response<ModifyGuildRoleResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
 
     let ModifyGuildRoleRequest {
         guild_id,
@@ -62,7 +63,18 @@ pub async fn handler(
             false,
         )),
         EventContext::empty(),
-    );
+    )
+    .await;
+
+    svc.record_audit_log(
+        guild_id,
+        user_id,
+        AuditAction::RoleModified,
+        role_id,
+        None,
+        reason,
+    )
+    .await?;
 
     Ok((ModifyGuildRoleResponse {}).into_response())
 }
@@ -38,7 +38,8 @@ pub async fn handler(
                 false,
             )),
             EventContext::empty(),
-        );
+        )
+        .await;
     }
 
     Ok((MoveRoleResponse {}).into_response())
@@ -5,6 +5,7 @@ pub async fn handler(
     request: Request<SetPermissionsRequest>,
 ) -> ServerResult<Response<SetPermissionsResponse>> {
     let user_id = svc.deps.auth(&request).await?;
+    let reason = get_audit_log_reason(&request);
 
     let SetPermissionsRequest {
         guild_id,
@@ -57,7 +58,8 @@ pub async fn handler(
                 }),
                 None,
                 EventContext::new(for_users.clone()),
-            );
+            )
+            .await;
         }
         svc.send_event_through_chan(
             EventSub::Guild(guild_id),
@@ -74,7 +76,19 @@ pub async fn handler(
                 false,
             )),
             EventContext::empty(),
-        );
+        )
+        .await;
+
+        svc.record_audit_log(
+            guild_id,
+            user_id,
+            AuditAction::PermissionsSet,
+            role_id,
+            channel_id,
+            reason,
+        )
+        .await?;
+
         Ok((SetPermissionsResponse {}).into_response())
     } else {
         Err(ServerError::NoPermissionsSpecified.into())
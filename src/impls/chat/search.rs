@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// Terms longer than this are not put in the search index.
+const MAX_TERM_LEN: usize = 64;
+const DEFAULT_SEARCH_LIMIT: usize = 25;
+const MAX_SEARCH_LIMIT: usize = 100;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchQuery {
+    pub guild_id: u64,
+    pub query: String,
+    pub channel_id: Option<u64>,
+    pub author_id: Option<u64>,
+    /// Only return messages created before this, in milliseconds since unix epoch
+    pub before: Option<u64>,
+    /// Only return messages created after this, in milliseconds since unix epoch
+    pub after: Option<u64>,
+    pub has_attachment: Option<bool>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub author_id: u64,
+    pub created_at: u64,
+}
+
+/// Splits text into the lowercase alphanumeric terms used by the search index.
+pub fn split_search_terms(text: &str) -> HashSet<String, ahash::RandomState> {
+    text.split(|c: char| c.is_alphanumeric().not())
+        .filter(|term| term.is_empty().not() && term.len() <= MAX_TERM_LEN)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn message_search_terms(message: &HarmonyMessage) -> HashSet<String, ahash::RandomState> {
+    match message.content.as_ref().and_then(|c| c.content.as_ref()) {
+        Some(content::Content::TextMessage(content::TextContent {
+            content: Some(text),
+        })) => split_search_terms(&text.text),
+        Some(content::Content::AttachmentMessage(attachments)) => attachments
+            .files
+            .iter()
+            .flat_map(|file| split_search_terms(&file.name))
+            .collect(),
+        _ => HashSet::default(),
+    }
+}
+
+/// Index entries store the author, creation time and whether the message has
+/// attachments, so that queries can be filtered without fetching every message.
+fn make_search_index_value(message: &HarmonyMessage) -> Vec<u8> {
+    let has_attachment = matches!(
+        message.content.as_ref().and_then(|c| c.content.as_ref()),
+        Some(content::Content::AttachmentMessage(_))
+    );
+    [
+        message.author_id.to_be_bytes().as_ref(),
+        message.created_at.to_be_bytes().as_ref(),
+        &[has_attachment as u8],
+    ]
+    .concat()
+}
+
+pub fn index_message(
+    batch: &mut Batch,
+    guild_id: u64,
+    channel_id: u64,
+    message_id: u64,
+    message: &HarmonyMessage,
+) {
+    let value = make_search_index_value(message);
+    for term in message_search_terms(message) {
+        batch.insert(
+            make_search_index_key(guild_id, &term, channel_id, message_id),
+            value.clone(),
+        );
+    }
+}
+
+pub fn unindex_message(
+    batch: &mut Batch,
+    guild_id: u64,
+    channel_id: u64,
+    message_id: u64,
+    message: &HarmonyMessage,
+) {
+    for term in message_search_terms(message) {
+        batch.remove(make_search_index_key(
+            guild_id, &term, channel_id, message_id,
+        ));
+    }
+}
+
+impl ChatTree {
+    /// Searches the messages of a guild, only returning messages in channels
+    /// the user can view. Results are sorted from newest to oldest.
+    pub async fn search_messages_logic(
+        &self,
+        user_id: u64,
+        query: &SearchQuery,
+    ) -> Result<Vec<SearchResult>, ServerError> {
+        let guild_id = query.guild_id;
+
+        if self
+            .contains_key(&make_member_key(guild_id, user_id))
+            .await?
+            .not()
+        {
+            return Err(ServerError::UserNotInGuild { guild_id, user_id });
+        }
+
+        let terms = split_search_terms(&query.query);
+        if terms.is_empty() {
+            return Err(ServerError::EmptySearchQuery);
+        }
+
+        // only keep messages which contain all of the terms
+        let mut matches: Option<HashMap<(u64, u64), SearchResult, ahash::RandomState>> = None;
+        for term in &terms {
+            let prefix = make_search_term_prefix(guild_id, term);
+            let mut term_matches = HashMap::default();
+            for res in self.scan_prefix(&prefix).await {
+                let (key, value) = res?;
+                let (_, raw_ids) = key.split_at(prefix.len());
+                let (raw_channel_id, raw_message_id) = raw_ids.split_at(size_of::<u64>());
+                // Safety: we split at u64 boundaries
+                let ids = (deser_id(raw_channel_id), deser_id(raw_message_id));
+
+                if matches.as_ref().map_or(true, |m| m.contains_key(&ids)) {
+                    let (raw_author_id, rest) = value.split_at(size_of::<u64>());
+                    let (raw_created_at, raw_has_attachment) = rest.split_at(size_of::<u64>());
+                    let has_attachment = raw_has_attachment.first().map_or(false, |b| *b == 1);
+
+                    let matches_filters = query.channel_id.map_or(true, |id| id == ids.0)
+                        && query
+                            .author_id
+                            .map_or(true, |id| id == deser_id(raw_author_id))
+                        && query.before.map_or(true, |t| deser_id(raw_created_at) < t)
+                        && query.after.map_or(true, |t| deser_id(raw_created_at) > t)
+                        && query.has_attachment.map_or(true, |h| h == has_attachment);
+
+                    if matches_filters {
+                        term_matches.insert(
+                            ids,
+                            SearchResult {
+                                channel_id: ids.0,
+                                message_id: ids.1,
+                                author_id: deser_id(raw_author_id),
+                                created_at: deser_id(raw_created_at),
+                            },
+                        );
+                    }
+                }
+            }
+            matches = Some(term_matches);
+        }
+
+        let mut candidates = matches
+            .unwrap_or_default()
+            .into_values()
+            .collect::<Vec<_>>();
+        candidates.sort_unstable_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        let limit = query
+            .limit
+            .map_or(DEFAULT_SEARCH_LIMIT, |limit| limit.min(MAX_SEARCH_LIMIT));
+        let mut can_view = HashMap::<u64, bool, ahash::RandomState>::default();
+        let mut results = Vec::with_capacity(limit.min(candidates.len()));
+
+        for result in candidates {
+            if results.len() >= limit {
+                break;
+            }
+
+            let allowed = match can_view.get(&result.channel_id) {
+                Some(allowed) => *allowed,
+                None => {
+                    let allowed = match self
+                        .check_perms(
+                            guild_id,
+                            Some(result.channel_id),
+                            user_id,
+                            "messages.view",
+                            false,
+                        )
+                        .await
+                    {
+                        Ok(_) => true,
+                        Err(ServerError::NotEnoughPermissions { .. }) => false,
+                        Err(err) => return Err(err),
+                    };
+                    can_view.insert(result.channel_id, allowed);
+                    allowed
+                }
+            };
+
+            // the index can have entries for messages in deleted channels, skip those
+            let exists = self
+                .contains_key(&make_msg_key(
+                    guild_id,
+                    result.channel_id,
+                    result.message_id,
+                ))
+                .await?;
+
+            if allowed && exists {
+                results.push(result);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Drops the search index of every guild and builds it again from the stored
+    /// messages. Returns the amount of messages that were indexed.
+    pub async fn rebuild_search_index_logic(&self) -> Result<u64, ServerError> {
+        let mut indexed = 0;
+
+        for guild_id in self.get_all_guild_ids_logic().await? {
+            let mut batch = Batch::default();
+
+            for res in self.scan_prefix(&make_search_index_prefix(guild_id)).await {
+                let (key, _) = res?;
+                batch.remove(key);
+            }
+
+            let channel_ids = self
+                .get_list_u64_logic(&make_guild_chan_ordering_key(guild_id))
+                .await?;
+            for channel_id in channel_ids {
+                let prefix = make_msg_prefix(guild_id, channel_id);
+                for res in self.scan_prefix(&prefix).await {
+                    let (key, value) = res?;
+                    if key.len() == prefix.len() + size_of::<u64>() {
+                        let message_id = deser_id(key.split_at(prefix.len()).1);
+                        let message = db::deser_message(value);
+                        index_message(&mut batch, guild_id, channel_id, message_id, &message);
+                        indexed += 1;
+                    }
+                }
+            }
+
+            self.chat_tree
+                .apply_batch(batch)
+                .await
+                .map_err(ServerError::DbError)?;
+        }
+
+        Ok(indexed)
+    }
+}
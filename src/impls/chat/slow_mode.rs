@@ -0,0 +1,159 @@
+//! Slow mode of channels, which limits how often each user can send a message
+//! in a channel. Users who can bypass slow mode aren't limited.
+
+use std::time::Duration;
+
+use super::*;
+
+/// Permission node that lets users send messages in slow mode channels
+/// without waiting.
+pub const SLOW_MODE_BYPASS: &str = "messages.slow-mode.bypass";
+/// Longest a slow mode interval can be, in seconds.
+pub const MAX_SLOW_MODE_INTERVAL: u64 = 60 * 60 * 6;
+
+impl ChatTree {
+    /// Gets the slow mode interval of a channel in seconds. `0` means the
+    /// channel isn't in slow mode.
+    pub async fn get_slow_mode_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> Result<u64, ServerError> {
+        Ok(self
+            .get(make_chan_slow_mode_key(guild_id, channel_id))
+            .await?
+            .map_or(0, deser_id))
+    }
+
+    /// Sets the slow mode interval of a channel in seconds. `0` turns slow
+    /// mode off, and forgets when users last sent messages in the channel.
+    pub async fn set_slow_mode_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        interval: u64,
+    ) -> Result<(), ServerError> {
+        if interval > MAX_SLOW_MODE_INTERVAL {
+            return Err(ServerError::InvalidDuration);
+        }
+        self.does_channel_exist(guild_id, channel_id).await?;
+
+        let key = make_chan_slow_mode_key(guild_id, channel_id);
+        if interval == 0 {
+            let mut batch = Batch::default();
+            batch.remove(key);
+            for res in self
+                .scan_prefix(make_chan_slow_mode_users_prefix(guild_id, channel_id))
+                .await
+            {
+                let (key, _) = res?;
+                batch.remove(key);
+            }
+            self.apply_batch(batch).await?;
+        } else {
+            self.insert(key, interval.to_be_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether a user can send a message in a channel at `now`, and
+    /// if so, starts their wait for the next one. Errors with
+    /// [`ServerError::TooFast`] if they have to wait longer.
+    pub async fn use_slow_mode_logic(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+        now: u64,
+    ) -> Result<(), ServerError> {
+        let interval = self.get_slow_mode_logic(guild_id, channel_id).await?;
+        if interval == 0 {
+            return Ok(());
+        }
+
+        let bypass = self
+            .check_perms(guild_id, Some(channel_id), user_id, SLOW_MODE_BYPASS, false)
+            .await;
+        match bypass {
+            Ok(_) => return Ok(()),
+            Err(ServerError::NotEnoughPermissions { .. }) => {}
+            Err(err) => return Err(err),
+        }
+
+        // only one of the messages sent at the same time can start the wait
+        let key = make_chan_slow_mode_user_key(guild_id, channel_id, user_id);
+        loop {
+            let raw = self.get(key).await?;
+            if let Some(last_sent) = raw.as_ref().map(deser_id) {
+                let next_allowed = last_sent + interval;
+                if next_allowed > now {
+                    return Err(ServerError::TooFast(Duration::from_secs(
+                        next_allowed - now,
+                    )));
+                }
+            }
+            if self
+                .compare_and_swap(key, raw.as_deref(), Some(&now.to_be_bytes()[..]))
+                .await?
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn slow_mode_makes_users_wait() {
+        const NOW: u64 = 1_700_000_000;
+        const GUILD_ID: u64 = 1;
+        const CHANNEL_ID: u64 = 1;
+        const OWNER_ID: u64 = 1;
+        const USER_ID: u64 = 2;
+
+        let db = db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+        let guild = Guild {
+            owner_ids: vec![OWNER_ID],
+            ..Default::default()
+        };
+        chat_tree
+            .insert(GUILD_ID.to_be_bytes(), rkyv_ser(&guild))
+            .await
+            .unwrap();
+        chat_tree
+            .insert(make_chan_key(GUILD_ID, CHANNEL_ID), [])
+            .await
+            .unwrap();
+
+        let set = |interval| chat_tree.set_slow_mode_logic(GUILD_ID, CHANNEL_ID, interval);
+        assert!(matches!(
+            set(MAX_SLOW_MODE_INTERVAL + 1).await,
+            Err(ServerError::InvalidDuration)
+        ));
+        set(30).await.unwrap();
+
+        let send = |user_id, now| chat_tree.use_slow_mode_logic(GUILD_ID, CHANNEL_ID, user_id, now);
+        send(USER_ID, NOW).await.unwrap();
+        match send(USER_ID, NOW + 10).await {
+            Err(ServerError::TooFast(wait)) => assert_eq!(wait, Duration::from_secs(20)),
+            res => panic!("expected to be too fast, got {:?}", res),
+        }
+        send(USER_ID, NOW + 30).await.unwrap();
+
+        // the owner has every permission, including the bypass
+        send(OWNER_ID, NOW).await.unwrap();
+        send(OWNER_ID, NOW).await.unwrap();
+
+        set(0).await.unwrap();
+        assert!(!chat_tree
+            .contains_key(make_chan_slow_mode_user_key(GUILD_ID, CHANNEL_ID, USER_ID))
+            .await
+            .unwrap());
+    }
+}
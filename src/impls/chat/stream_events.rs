@@ -7,27 +7,40 @@ pub async fn handler(
     request: Request<()>,
     socket: Socket<StreamEventsResponse, StreamEventsRequest>,
 ) -> Result<(), HrpcServerError> {
-    let user_id = svc.deps.auth(&request).await?;
+    let (user_id, session_id) = svc.deps.auth_session(&request).await?;
+    let resume_from = request
+        .header_map()
+        .and_then(|headers| headers.get(RESUME_EVENTS_HEADER))
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| ServerError::InvalidResumePoints)
+                .and_then(parse_resume_points)
+        })
+        .transpose()?
+        .unwrap_or_default();
 
     let fut = async move {
         tracing::debug!("stream events validated");
 
-        let mut cancel_recv = svc.deps.chat_event_canceller.subscribe();
+        let mut cancel_recv = svc.deps.chat_event_sender.subscribe_cancels();
 
         tracing::debug!("creating stream events processor");
-        let mut send_task = svc.spawn_event_stream_processor(user_id, socket);
+        let mut send_task =
+            svc.spawn_event_stream_processor(user_id, session_id, resume_from, socket);
 
         loop {
             tokio::select! {
-                Ok(cancelled_user_id) = cancel_recv.recv() => {
-                    if cancelled_user_id == user_id {
+                Ok(cancel) = cancel_recv.recv() => {
+                    if cancel.matches(user_id, session_id) {
                         return Err(("scherzo.stream-cancelled", "stream events cancelled manually").into());
                     }
                 }
                 res = &mut send_task => {
                     match res {
                         Err(err) => return Err(format!("stream events send loop task panicked: {}, aborting", err).into()),
-                        Ok(_) => break,
+                        Ok(Err(err)) => return Err(err),
+                        Ok(Ok(_)) => break,
                     }
                 }
                 else => tokio::task::yield_now().await,
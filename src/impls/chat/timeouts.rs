@@ -0,0 +1,454 @@
+//! Timeouts and temporary bans. Timed out users can't send messages, type or
+//! react in a guild until their timeout expires. Expired bans and timeouts are
+//! lifted periodically.
+
+use super::*;
+
+/// Header clients can set on ban requests to make the ban temporary. The value
+/// is how long the ban lasts, in seconds.
+pub const BAN_DURATION_HEADER: &str = "scherzo-ban-duration";
+/// Permission node needed for timing out users and lifting their timeouts.
+pub const TIMEOUT_MANAGE: &str = "user.manage.timeout";
+/// Permissions timed out users lose until their timeout expires.
+pub const TIMEOUT_BLOCKED_PERMISSIONS: [&str; 3] = [
+    "messages.send",
+    all_permissions::MESSAGES_REACTIONS_ADD,
+    all_permissions::MESSAGES_REACTIONS_REMOVE,
+];
+/// Longest a timeout can last, in seconds.
+pub const MAX_TIMEOUT_DURATION: u64 = 60 * 60 * 24 * 28;
+
+/// Parses the [`BAN_DURATION_HEADER`] header of a request. Returns `None` if
+/// the ban should be permanent.
+pub fn get_ban_duration<T>(request: &Request<T>) -> Result<Option<u64>, ServerError> {
+    get_duration_header(request, BAN_DURATION_HEADER)
+}
+
+/// Parses a header of a request that holds a duration in seconds. Returns
+/// `None` if the header isn't set.
+pub fn get_duration_header<T>(
+    request: &Request<T>,
+    header: &str,
+) -> Result<Option<u64>, ServerError> {
+    request
+        .header_map()
+        .and_then(|headers| headers.get(header))
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|duration| *duration > 0)
+                .ok_or(ServerError::InvalidDuration)
+        })
+        .transpose()
+}
+
+fn deser_ban(raw: &[u8]) -> (u64, Option<u64>) {
+    let (banned_at, expires_at) = raw.split_at(size_of::<u64>());
+    let expires_at = expires_at.is_empty().not().then(|| deser_id(expires_at));
+    (deser_id(banned_at), expires_at)
+}
+
+/// Expiry keys are the prefix, followed by when it expires, the guild ID and
+/// the user ID.
+fn deser_expiry_key(prefix: &[u8], key: &[u8]) -> (u64, u64, u64) {
+    let (expires_at, ids) = key[prefix.len()..].split_at(size_of::<u64>());
+    let (guild_id, user_id) = ids.split_at(size_of::<u64>());
+    (deser_id(expires_at), deser_id(guild_id), deser_id(user_id))
+}
+
+impl ChatTree {
+    /// Bans a user from a guild, replacing their current ban if they have
+    /// one. The ban is lifted at `expires_at` if it's set.
+    pub async fn ban_user_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        banned_at: u64,
+        expires_at: Option<u64>,
+    ) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        if let Some((_, Some(old_expires_at))) = self.get_ban_logic(guild_id, user_id).await? {
+            batch.remove(make_ban_expiry_key(old_expires_at, guild_id, user_id));
+        }
+
+        let mut value = banned_at.to_be_bytes().to_vec();
+        if let Some(expires_at) = expires_at {
+            value.extend_from_slice(&expires_at.to_be_bytes());
+            batch.insert(make_ban_expiry_key(expires_at, guild_id, user_id), []);
+        }
+        batch.insert(make_banned_member_key(guild_id, user_id), value);
+
+        self.apply_batch(batch).await
+    }
+
+    /// Gets when a user was banned from a guild and when the ban expires, if
+    /// they are banned.
+    pub async fn get_ban_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<Option<(u64, Option<u64>)>, ServerError> {
+        Ok(self
+            .get(make_banned_member_key(guild_id, user_id))
+            .await?
+            .map(|raw| deser_ban(&raw)))
+    }
+
+    /// Lifts the ban of a user. Returns `false` if they weren't banned.
+    pub async fn unban_user_logic(&self, guild_id: u64, user_id: u64) -> Result<bool, ServerError> {
+        let expires_at = match self.get_ban_logic(guild_id, user_id).await? {
+            Some((_, expires_at)) => expires_at,
+            None => return Ok(false),
+        };
+
+        let mut batch = Batch::default();
+        batch.remove(make_banned_member_key(guild_id, user_id));
+        if let Some(expires_at) = expires_at {
+            batch.remove(make_ban_expiry_key(expires_at, guild_id, user_id));
+        }
+        self.apply_batch(batch).await?;
+
+        Ok(true)
+    }
+
+    /// Gets the users banned from a guild, along with when they were banned
+    /// and when their ban expires.
+    pub async fn get_guild_bans_logic(
+        &self,
+        guild_id: u64,
+    ) -> Result<Vec<(u64, u64, Option<u64>)>, ServerError> {
+        let prefix = make_guild_banned_mem_prefix(guild_id);
+        let mut bans = Vec::new();
+        for res in self.scan_prefix(&prefix).await {
+            let (key, value) = res?;
+            if key.len() == make_banned_member_key(0, 0).len() {
+                let (banned_at, expires_at) = deser_ban(&value);
+                bans.push((deser_id(&key[prefix.len()..]), banned_at, expires_at));
+            }
+        }
+        Ok(bans)
+    }
+
+    /// Times out a user in a guild until `expires_at`, replacing their current
+    /// timeout if they have one.
+    pub async fn timeout_user_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        expires_at: u64,
+    ) -> Result<(), ServerError> {
+        let mut batch = Batch::default();
+        if let Some(old_expires_at) = self.get_timeout_logic(guild_id, user_id).await? {
+            batch.remove(make_timeout_expiry_key(old_expires_at, guild_id, user_id));
+        }
+        batch.insert(
+            make_timeout_key(guild_id, user_id),
+            expires_at.to_be_bytes(),
+        );
+        batch.insert(make_timeout_expiry_key(expires_at, guild_id, user_id), []);
+        self.apply_batch(batch).await
+    }
+
+    /// Gets when the timeout of a user in a guild expires, if they have one.
+    pub async fn get_timeout_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<Option<u64>, ServerError> {
+        Ok(self
+            .get(make_timeout_key(guild_id, user_id))
+            .await?
+            .map(deser_id))
+    }
+
+    /// Lifts the timeout of a user. Returns `false` if they weren't timed out.
+    pub async fn remove_timeout_logic(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<bool, ServerError> {
+        let expires_at = match self.get_timeout_logic(guild_id, user_id).await? {
+            Some(expires_at) => expires_at,
+            None => return Ok(false),
+        };
+
+        let mut batch = Batch::default();
+        batch.remove(make_timeout_key(guild_id, user_id));
+        batch.remove(make_timeout_expiry_key(expires_at, guild_id, user_id));
+        self.apply_batch(batch).await?;
+
+        Ok(true)
+    }
+
+    /// Errors with [`ServerError::TimedOut`] if the user is timed out in the
+    /// guild and `check_for` is one of the permissions they lose.
+    pub async fn check_not_timed_out(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        check_for: &str,
+    ) -> Result<(), ServerError> {
+        if TIMEOUT_BLOCKED_PERMISSIONS.contains(&check_for).not() {
+            return Ok(());
+        }
+        match self.get_timeout_logic(guild_id, user_id).await? {
+            Some(until) if until > get_time_secs() => {
+                Err(ServerError::TimedOut { guild_id, until })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets the entries of an expiry index that expired at `now`. The index is
+    /// sorted by expiry, so this stops at the first entry that hasn't expired.
+    async fn get_expired_logic(
+        &self,
+        prefix: &[u8],
+        now: u64,
+    ) -> Result<Vec<(u64, u64, u64)>, ServerError> {
+        let mut expired = Vec::new();
+        for res in self.scan_prefix(prefix).await {
+            let (key, _) = res?;
+            let entry = deser_expiry_key(prefix, &key);
+            if entry.0 > now {
+                break;
+            }
+            expired.push(entry);
+        }
+        Ok(expired)
+    }
+
+    /// Gets the temporary bans that expired at `now`, as when they expired,
+    /// the guild ID and the user ID.
+    pub async fn get_expired_bans_logic(
+        &self,
+        now: u64,
+    ) -> Result<Vec<(u64, u64, u64)>, ServerError> {
+        self.get_expired_logic(BAN_EXPIRY_PREFIX, now).await
+    }
+
+    /// Gets the timeouts that expired at `now`, as when they expired, the
+    /// guild ID and the user ID.
+    pub async fn get_expired_timeouts_logic(
+        &self,
+        now: u64,
+    ) -> Result<Vec<(u64, u64, u64)>, ServerError> {
+        self.get_expired_logic(TIMEOUT_EXPIRY_PREFIX, now).await
+    }
+}
+
+impl ChatServer {
+    /// Tells a user whether they can send messages and react in a guild, after
+    /// they were timed out or their timeout was lifted.
+    async fn send_timeout_permissions(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<(), ServerError> {
+        for perm in TIMEOUT_BLOCKED_PERMISSIONS {
+            let ok = self
+                .deps
+                .chat_tree
+                .check_perms(guild_id, None, user_id, perm, false)
+                .await
+                .is_ok();
+            self.send_event_through_chan(
+                EventSub::Guild(guild_id),
+                stream_event::Event::PermissionUpdated(stream_event::PermissionUpdated {
+                    guild_id,
+                    channel_id: None,
+                    query: perm.to_string(),
+                    ok,
+                }),
+                None,
+                EventContext::new(vec![user_id]),
+            )
+            .await;
+        }
+        Ok(())
+    }
+
+    /// Times out a user in a guild for `duration` seconds.
+    pub async fn timeout_user(
+        &self,
+        guild_id: u64,
+        actor_id: u64,
+        user_id: u64,
+        duration: u64,
+        reason: Option<String>,
+    ) -> Result<(), ServerError> {
+        if duration == 0 || duration > MAX_TIMEOUT_DURATION {
+            return Err(ServerError::InvalidDuration);
+        }
+
+        self.deps
+            .chat_tree
+            .timeout_user_logic(guild_id, user_id, get_time_secs() + duration)
+            .await?;
+        self.send_timeout_permissions(guild_id, user_id).await?;
+
+        self.record_audit_log(
+            guild_id,
+            actor_id,
+            AuditAction::Timeout,
+            user_id,
+            None,
+            reason,
+        )
+        .await
+    }
+
+    /// Lifts the timeout of a user. Returns `false` if they weren't timed out.
+    pub async fn remove_timeout(
+        &self,
+        guild_id: u64,
+        actor_id: u64,
+        user_id: u64,
+        reason: Option<String>,
+    ) -> Result<bool, ServerError> {
+        if self
+            .deps
+            .chat_tree
+            .remove_timeout_logic(guild_id, user_id)
+            .await?
+            .not()
+        {
+            return Ok(false);
+        }
+        self.send_timeout_permissions(guild_id, user_id).await?;
+
+        self.record_audit_log(
+            guild_id,
+            actor_id,
+            AuditAction::TimeoutRemoved,
+            user_id,
+            None,
+            reason,
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Lifts the bans and timeouts that expired at `now`. They are recorded in
+    /// the audit log as done by the server, which has the user ID 0. Failing
+    /// to lift one is logged, and doesn't stop the others from being lifted.
+    pub async fn lift_expired_logic(&self, now: u64) -> Result<(), ServerError> {
+        let chat_tree = &self.deps.chat_tree;
+        let reason = || Some("expired".to_string());
+
+        // bans and timeouts are already gone if their guild was deleted, but
+        // their expiry entries are left behind
+        for (expires_at, guild_id, user_id) in chat_tree.get_expired_bans_logic(now).await? {
+            let res: Result<(), ServerError> = async {
+                if chat_tree.unban_user_logic(guild_id, user_id).await? {
+                    self.record_audit_log(guild_id, 0, AuditAction::Unban, user_id, None, reason())
+                        .await?;
+                } else {
+                    chat_tree
+                        .remove(make_ban_expiry_key(expires_at, guild_id, user_id))
+                        .await?;
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(err) = res {
+                tracing::error!(
+                    "couldnt lift expired ban of user {} in guild {}: {}",
+                    user_id,
+                    guild_id,
+                    err
+                );
+            }
+        }
+
+        for (expires_at, guild_id, user_id) in chat_tree.get_expired_timeouts_logic(now).await? {
+            let res: Result<(), ServerError> = async {
+                if self
+                    .remove_timeout(guild_id, 0, user_id, reason())
+                    .await?
+                    .not()
+                {
+                    chat_tree
+                        .remove(make_timeout_expiry_key(expires_at, guild_id, user_id))
+                        .await?;
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(err) = res {
+                tracing::error!(
+                    "couldnt lift expired timeout of user {} in guild {}: {}",
+                    user_id,
+                    guild_id,
+                    err
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sled")]
+    #[tokio::test]
+    async fn expired_bans_and_timeouts_are_found() {
+        const NOW: u64 = 1_700_000_000;
+        const GUILD_ID: u64 = 1;
+
+        let db = db::open_temp();
+        let chat_tree = ChatTree::new(&db).await.unwrap();
+
+        chat_tree
+            .ban_user_logic(GUILD_ID, 1, NOW, Some(NOW + 60))
+            .await
+            .unwrap();
+        chat_tree
+            .ban_user_logic(GUILD_ID, 2, NOW, None)
+            .await
+            .unwrap();
+        chat_tree
+            .timeout_user_logic(GUILD_ID, 3, NOW + 30)
+            .await
+            .unwrap();
+        // a new timeout replaces the old one
+        chat_tree
+            .timeout_user_logic(GUILD_ID, 3, NOW + 90)
+            .await
+            .unwrap();
+
+        let mut bans = chat_tree.get_guild_bans_logic(GUILD_ID).await.unwrap();
+        bans.sort_unstable();
+        assert_eq!(bans, vec![(1, NOW, Some(NOW + 60)), (2, NOW, None)]);
+
+        assert_eq!(
+            chat_tree.get_expired_bans_logic(NOW + 60).await.unwrap(),
+            vec![(NOW + 60, GUILD_ID, 1)]
+        );
+        assert!(chat_tree
+            .get_expired_timeouts_logic(NOW + 60)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            chat_tree
+                .get_expired_timeouts_logic(NOW + 90)
+                .await
+                .unwrap(),
+            vec![(NOW + 90, GUILD_ID, 3)]
+        );
+
+        assert!(chat_tree.unban_user_logic(GUILD_ID, 1).await.unwrap());
+        assert!(chat_tree
+            .get_expired_bans_logic(NOW + 60)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,8 +1,63 @@
 use super::*;
 
 pub async fn handler(
-    _svc: &ChatServer,
-    _request: Request<TriggerActionRequest>,
+    svc: &ChatServer,
+    request: Request<TriggerActionRequest>,
 ) -> ServerResult<Response<TriggerActionResponse>> {
-    Err(ServerError::NotImplemented.into())
+    let user_id = svc.deps.auth(&request).await?;
+
+    let TriggerActionRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        action_id,
+        payload,
+    } = request.into_message().await?;
+
+    let chat_tree = &svc.deps.chat_tree;
+
+    chat_tree
+        .check_guild_user_channel(guild_id, user_id, channel_id)
+        .await?;
+    chat_tree
+        .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
+        .await?;
+
+    let (message, _) = chat_tree
+        .get_message_logic(guild_id, channel_id, message_id)
+        .await?;
+
+    // actions can only be attached to embed fields
+    let action_exists = match message.content.and_then(|c| c.content) {
+        Some(content::Content::EmbedMessage(embed)) => embed
+            .embeds
+            .iter()
+            .flat_map(|embed| embed.fields.iter())
+            .flat_map(|field| field.actions.iter())
+            .any(|action| action.action_id == action_id),
+        _ => false,
+    };
+    if action_exists.not() {
+        bail!((
+            "h.bad-action-id",
+            format!("no action with id {} on this message", action_id)
+        ));
+    }
+
+    svc.send_event_through_chan(
+        EventSub::Actions,
+        stream_event::Event::ActionPerformed(stream_event::ActionPerformed {
+            guild_id,
+            channel_id,
+            message_id,
+            action_id,
+            payload,
+            user_id,
+        }),
+        None,
+        EventContext::new(vec![message.author_id]),
+    )
+    .await;
+
+    Ok((TriggerActionResponse {}).into_response())
 }
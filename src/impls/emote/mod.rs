@@ -52,7 +52,7 @@ impl EmoteServer {
     ) {
         let broadcast = EventBroadcast::new(sub, Event::Emote(event), perm_check, context);
 
-        drop(self.deps.chat_event_sender.send(Arc::new(broadcast)));
+        self.deps.chat_event_sender.send(Arc::new(broadcast));
     }
 }
 
@@ -16,6 +16,8 @@ use prelude::*;
 use std::str::FromStr;
 
 use crate::api::HomeserverIdentifier;
+use ahash::RandomState;
+use dashmap::DashMap;
 use hyper::{http, Uri};
 use lettre::{
     message::{header, Mailbox, MultiPart, SinglePart},
@@ -24,7 +26,7 @@ use lettre::{
 };
 use parking_lot::Mutex;
 use reqwest::Client as HttpClient;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::mpsc;
 
 use crate::{config::Config, key, SharedConfig, SharedConfigData};
 
@@ -74,7 +76,8 @@ pub struct Dependencies {
     pub sync_tree: Tree,
 
     pub chat_event_sender: chat::EventSender,
-    pub chat_event_canceller: chat::EventCanceller,
+    /// Last time each `(user_id, session_id)` was used, not yet written to the db
+    pub session_activity: DashMap<(u64, u64), u64, RandomState>,
     pub fed_event_dispatcher: FedEventDispatcher,
     pub key_manager: Option<Arc<key::Manager>>,
     pub http: HttpClient,
@@ -107,15 +110,20 @@ impl Dependencies {
             None
         };
 
+        let chat_tree = ChatTree::new(db).await?;
+        let _ = chat_tree
+            .guest_permissions
+            .set(config.policy.guests.permissions.clone());
+
         let this = Self {
             auth_tree: auth_tree.clone(),
-            chat_tree: ChatTree::new(db).await?,
+            chat_tree,
             profile_tree: profile_tree.clone(),
             emote_tree: EmoteTree::new(db).await?,
             sync_tree: db.open_tree(b"sync").await?,
 
-            chat_event_sender: broadcast::channel(2048).0,
-            chat_event_canceller: broadcast::channel(2048).0,
+            chat_event_sender: chat::event_bus::make_event_bus(config.event_bus.as_ref()),
+            session_activity: DashMap::default(),
             fed_event_dispatcher,
             key_manager: config
                 .federation
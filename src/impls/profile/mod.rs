@@ -43,7 +43,7 @@ impl ProfileServer {
     ) {
         let broadcast = EventBroadcast::new(sub, Event::Profile(event), perm_check, context);
 
-        drop(self.deps.chat_event_sender.send(Arc::new(broadcast)));
+        self.deps.chat_event_sender.send(Arc::new(broadcast));
     }
 }
 
@@ -80,7 +80,7 @@ impl ProfileTree {
         new_user_avatar: Option<String>,
         new_user_status: Option<i32>,
         new_is_bot: Option<bool>,
-    ) -> ServerResult<()> {
+    ) -> Result<(), ServerError> {
         let key = make_user_profile_key(user_id);
 
         let mut profile = self
@@ -88,7 +88,17 @@ impl ProfileTree {
             .await?
             .map_or_else(Profile::default, db::deser_profile);
 
+        let mut batch = Batch::default();
         if let Some(new_username) = new_user_name {
+            let old_name_key = self.user_name_key(user_id, &profile.user_name).await?;
+            // another user might have had this name before, don't remove their entry
+            if self.get(&old_name_key).await?.map(deser_id) == Some(user_id) {
+                batch.remove(old_name_key);
+            }
+            batch.insert(
+                self.user_name_key(user_id, &new_username).await?,
+                user_id.to_be_bytes(),
+            );
             profile.user_name = new_username;
         }
         if let Some(new_avatar) = new_user_avatar {
@@ -106,24 +116,64 @@ impl ProfileTree {
         }
 
         let buf = rkyv_ser(&profile);
-        self.insert(key, buf).await?;
+        batch.insert(key, buf);
+        self.apply_batch(batch).await?;
+
+        Ok(())
+    }
+
+    /// Blanks the profile of a deleted user and removes them from the username
+    /// index. The profile is renamed to "Deleted User", but that name isn't
+    /// indexed, so it never resolves to a deleted user.
+    pub async fn set_deleted_profile_logic(&self, user_id: u64) -> Result<(), ServerError> {
+        let key = make_user_profile_key(user_id);
+
+        let mut profile = self
+            .get(key)
+            .await?
+            .map_or_else(Profile::default, db::deser_profile);
+
+        let mut batch = Batch::default();
+        let name_key = self.user_name_key(user_id, &profile.user_name).await?;
+        if self.get(&name_key).await?.map(deser_id) == Some(user_id) {
+            batch.remove(name_key);
+        }
+
+        profile.user_name = "Deleted User".to_string();
+        profile.user_avatar = None;
+        profile.user_status = UserStatus::OfflineUnspecified.into();
+        profile.is_bot = false;
+        batch.insert(key, rkyv_ser(&profile));
+        self.apply_batch(batch).await?;
 
         Ok(())
     }
 
-    pub async fn get_profile_logic(&self, user_id: u64) -> ServerResult<Profile> {
+    /// Makes the username index key of a user, which includes their host if
+    /// they are a foreign user.
+    async fn user_name_key(&self, user_id: u64, user_name: &str) -> Result<Vec<u8>, ServerError> {
+        let raw = self.get(make_local_to_foreign_user_key(user_id)).await?;
+        let host = raw
+            .as_ref()
+            .and_then(|raw| raw.get(size_of::<u64>()..))
+            .and_then(|raw_host| std::str::from_utf8(raw_host).ok())
+            .unwrap_or_default();
+        Ok(make_user_name_key(user_name, host))
+    }
+
+    pub async fn get_profile_logic(&self, user_id: u64) -> Result<Profile, ServerError> {
         let key = make_user_profile_key(user_id);
 
         let profile = if let Some(profile_raw) = self.get(key).await? {
             db::deser_profile(profile_raw)
         } else {
-            return Err(ServerError::NoSuchUser(user_id).into());
+            return Err(ServerError::NoSuchUser(user_id));
         };
 
         Ok(profile)
     }
 
-    pub async fn does_username_exist(&self, username: &str) -> ServerResult<bool> {
+    pub async fn does_username_exist(&self, username: &str) -> Result<bool, ServerError> {
         for res in self.scan_prefix(USER_PREFIX).await {
             let (_, value) = res?;
             let profile = db::rkyv_arch::<Profile>(&value);
@@ -134,6 +184,17 @@ impl ProfileTree {
         Ok(false)
     }
 
+    /// Finds the local ID of the user with the given username. If `server_id` is
+    /// set, only foreign users from that host are considered, otherwise only local users.
+    pub async fn get_user_id_by_name(
+        &self,
+        username: &str,
+        server_id: Option<&str>,
+    ) -> ServerResult<Option<u64>> {
+        let key = make_user_name_key(username, server_id.unwrap_or_default());
+        Ok(self.get(key).await?.map(deser_id))
+    }
+
     pub async fn does_user_exist(&self, user_id: u64) -> ServerResult<()> {
         self.contains_key(&make_user_profile_key(user_id))
             .await?
@@ -166,4 +227,29 @@ impl ProfileTree {
         // Safety: we store u64's only for these keys
         Ok(self.get(key).await?.map(deser_id))
     }
+
+    /// Converts the `sub` claim of a user from an OIDC provider to a local user ID
+    pub async fn oidc_to_local_id(&self, issuer: &str, sub: &str) -> ServerResult<Option<u64>> {
+        let key = make_oidc_to_local_user_key(issuer, sub);
+
+        // Safety: we store u64's only for these keys
+        Ok(self.get(key).await?.map(deser_id))
+    }
+
+    /// Gets the user that owns a bot. Bots that were made by setting
+    /// `is_bot` before bot accounts existed don't have an owner.
+    pub async fn get_bot_owner_logic(&self, bot_id: u64) -> Result<Option<u64>, ServerError> {
+        Ok(self.get(make_bot_owner_key(bot_id)).await?.map(deser_id))
+    }
+
+    /// Gets the IDs of the bots a user owns.
+    pub async fn get_user_bots_logic(&self, owner_id: u64) -> Result<Vec<u64>, ServerError> {
+        let prefix = make_user_bots_prefix(owner_id);
+        let mut bots = Vec::new();
+        for res in self.scan_prefix(prefix).await {
+            let (key, _) = res?;
+            bots.push(deser_id(&key[prefix.len()..]));
+        }
+        Ok(bots)
+    }
 }
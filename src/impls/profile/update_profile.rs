@@ -14,6 +14,18 @@ pub async fn handler(
         new_is_bot,
     } = request.into_message().await?;
 
+    // bots are created through the bots API, so users can't turn themselves
+    // into one (and skip session expiry) or turn their bots into users
+    if let Some(is_bot) = new_is_bot {
+        let profile = svc.deps.profile_tree.get_profile_logic(user_id).await?;
+        if profile.is_bot != is_bot {
+            bail!((
+                "h.cant-change-is-bot",
+                "bot status can't be changed, create a bot instead"
+            ));
+        }
+    }
+
     if let Some(username) = new_user_name.as_deref() {
         if svc.deps.profile_tree.does_username_exist(username).await? {
             bail!(ServerError::UserAlreadyExists);
@@ -11,14 +11,11 @@ use hrpc::{
 use tower::Service;
 
 pub fn handler(deps: Arc<Dependencies>) -> RateLimit<AboutService> {
-    let client_ip_header_name = deps.config.policy.ratelimit.client_ip_header_name.clone();
-    let allowed_ips = deps.config.policy.ratelimit.allowed_ips.clone();
-    RateLimit::new(
-        AboutService { deps },
+    RateLimit::from_config(
+        AboutService { deps: deps.clone() },
         3,
         Duration::from_secs(5),
-        client_ip_header_name,
-        allowed_ips,
+        &deps.config.policy.ratelimit,
     )
 }
 
@@ -0,0 +1,192 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use serde::Deserialize;
+use tower::Service;
+
+use crate::{
+    db::chat::make_member_key,
+    impls::{
+        auth::get_token_from_header_map,
+        chat::audit_log::{audit_log_entry_json, AUDIT_LOG_VIEW, MAX_AUDIT_LOG_ENTRIES},
+    },
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<AuditLogService> {
+    RateLimit::from_config(
+        AuditLogService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn channel_handler(deps: Arc<Dependencies>) -> RateLimit<AuditLogChannelService> {
+    RateLimit::from_config(
+        AuditLogChannelService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Returns the audit log of a guild, newest entries first. Entries older than
+/// the `before` entry ID can be fetched to go back further.
+pub struct AuditLogService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for AuditLogService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let uri = request.uri();
+            let guild_id = get_query_param(uri, "guild_id").and_then(|id| id.parse::<u64>().ok());
+            let Some(guild_id) = guild_id else {
+                return Ok(rest_error_response(
+                    "guild_id must be specified".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+            };
+            let before = match get_query_param(uri, "before").map(str::parse::<u64>) {
+                Some(Ok(before)) => Some(before),
+                Some(Err(_)) => {
+                    return Ok(rest_error_response(
+                        "before must be an entry ID".to_string(),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                }
+                None => None,
+            };
+            let limit = get_query_param(uri, "limit")
+                .and_then(|limit| limit.parse::<u64>().ok())
+                .unwrap_or(50)
+                .min(MAX_AUDIT_LOG_ENTRIES);
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                if !deps
+                    .chat_tree
+                    .contains_key(make_member_key(guild_id, user_id))
+                    .await?
+                {
+                    return Err(ServerError::UserNotInGuild { guild_id, user_id });
+                }
+                deps.chat_tree
+                    .check_perms(guild_id, None, user_id, AUDIT_LOG_VIEW, false)
+                    .await?;
+                deps.chat_tree
+                    .get_audit_log_logic(guild_id, before, limit)
+                    .await
+            }
+            .await;
+
+            let entries = match res {
+                Ok(entries) => entries,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let next_before = (entries.len() as u64 == limit)
+                .then(|| entries.last().map(|(entry_id, _)| *entry_id))
+                .flatten();
+            let entries = entries
+                .into_iter()
+                .map(|(entry_id, entry)| audit_log_entry_json(entry_id, &entry))
+                .collect::<Vec<_>>();
+
+            Ok(json_response(serde_json::json!({
+                "entries": entries,
+                "next_before": next_before,
+            })))
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetAuditLogChannel {
+    guild_id: u64,
+    channel_id: Option<u64>,
+}
+
+/// Sets the channel new audit log entries of a guild are posted to, or stops
+/// posting them if no channel is given. Only guild owners can use this.
+pub struct AuditLogChannelService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for AuditLogChannelService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let SetAuditLogChannel {
+                    guild_id,
+                    channel_id,
+                } = read_json(request).await?;
+                deps.chat_tree
+                    .check_perms(guild_id, None, user_id, "", true)
+                    .await?;
+                deps.chat_tree
+                    .set_audit_log_channel_logic(guild_id, channel_id)
+                    .await
+            }
+            .await;
+
+            match res {
+                Ok(()) => Ok(http::Response::builder()
+                    .status(StatusCode::OK)
+                    .body(box_body(Body::empty()))
+                    .unwrap()),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
@@ -0,0 +1,163 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use serde::Deserialize;
+use tower::Service;
+
+use crate::{
+    db::chat::{make_member_key, AutomodRule},
+    impls::{auth::get_token_from_header_map, chat::automod::AUTOMOD_MANAGE},
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<AutomodRulesService> {
+    RateLimit::from_config(
+        AutomodRulesService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn set_handler(deps: Arc<Dependencies>) -> RateLimit<SetAutomodRulesService> {
+    RateLimit::from_config(
+        SetAutomodRulesService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Checks that a user can view and change the automod rules of a guild.
+async fn check_can_manage_automod(
+    deps: &Dependencies,
+    guild_id: u64,
+    user_id: u64,
+) -> Result<(), ServerError> {
+    if !deps
+        .chat_tree
+        .contains_key(make_member_key(guild_id, user_id))
+        .await?
+    {
+        return Err(ServerError::UserNotInGuild { guild_id, user_id });
+    }
+    deps.chat_tree
+        .check_perms(guild_id, None, user_id, AUTOMOD_MANAGE, false)
+        .await
+}
+
+/// Returns the automod rules of a guild.
+pub struct AutomodRulesService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for AutomodRulesService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let guild_id = get_query_param(request.uri(), "guild_id")
+                .and_then(|guild_id| guild_id.parse::<u64>().ok());
+            let Some(guild_id) = guild_id else {
+                return Ok(rest_error_response(
+                    "guild_id must be specified".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+            };
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                check_can_manage_automod(&deps, guild_id, user_id).await?;
+                deps.chat_tree.get_automod_rules_logic(guild_id).await
+            }
+            .await;
+
+            match res {
+                Ok(rules) => Ok(json_response(serde_json::json!({ "rules": rules }))),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetAutomodRules {
+    guild_id: u64,
+    rules: Vec<AutomodRule>,
+}
+
+/// Replaces the automod rules of a guild. Rules are checked in the order they
+/// are given.
+pub struct SetAutomodRulesService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for SetAutomodRulesService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let SetAutomodRules { guild_id, rules } = read_json(request).await?;
+                check_can_manage_automod(&deps, guild_id, user_id).await?;
+                deps.chat_tree
+                    .set_automod_rules_logic(guild_id, rules)
+                    .await
+            }
+            .await;
+
+            match res {
+                Ok(()) => Ok(http::Response::builder()
+                    .status(StatusCode::OK)
+                    .body(box_body(Body::empty()))
+                    .unwrap()),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
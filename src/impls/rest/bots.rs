@@ -0,0 +1,340 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use serde::Deserialize;
+use tower::Service;
+
+use crate::{
+    impls::auth::{bots, get_token_from_header_map},
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<BotsService> {
+    RateLimit::from_config(
+        BotsService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn create_handler(deps: Arc<Dependencies>) -> RateLimit<CreateBotService> {
+    RateLimit::from_config(
+        CreateBotService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn rotate_token_handler(deps: Arc<Dependencies>) -> RateLimit<RotateBotTokenService> {
+    RateLimit::from_config(
+        RotateBotTokenService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn delete_handler(deps: Arc<Dependencies>) -> RateLimit<DeleteBotService> {
+    RateLimit::from_config(
+        DeleteBotService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn guild_handler(deps: Arc<Dependencies>) -> RateLimit<GuildBotsService> {
+    RateLimit::from_config(
+        GuildBotsService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Lists the bots of the user.
+pub struct BotsService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for BotsService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res: Result<_, ServerError> = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                bots::list_bots(&deps, user_id).await
+            }
+            .await;
+
+            let bots = match res {
+                Ok(bots) => bots,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let bots = bots
+                .into_iter()
+                .map(|(bot_id, profile)| {
+                    serde_json::json!({
+                        "bot_id": bot_id,
+                        "username": profile.user_name,
+                        "avatar": profile.user_avatar,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(json_response(serde_json::json!({ "bots": bots })))
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateBot {
+    username: String,
+}
+
+/// Creates a bot owned by the user, returning its ID and token.
+pub struct CreateBotService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for CreateBotService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let create: CreateBot = read_json(request).await?;
+                bots::create_bot(&deps, user_id, create.username).await
+            }
+            .await;
+
+            match res {
+                Ok((bot_id, token)) => Ok(json_response(serde_json::json!({
+                    "bot_id": bot_id,
+                    "token": token,
+                }))),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BotId {
+    bot_id: u64,
+}
+
+/// Revokes the token of a bot the user owns and returns a new one. Event
+/// streams opened with the old token are closed.
+pub struct RotateBotTokenService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for RotateBotTokenService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let BotId { bot_id } = read_json(request).await?;
+                bots::rotate_bot_token(&deps, user_id, bot_id).await
+            }
+            .await;
+
+            match res {
+                Ok(token) => Ok(json_response(serde_json::json!({ "token": token }))),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
+
+/// Deletes a bot the user owns.
+pub struct DeleteBotService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for DeleteBotService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let BotId { bot_id } = read_json(request).await?;
+                bots::delete_bot(&deps, user_id, bot_id).await
+            }
+            .await;
+
+            match res {
+                Ok(()) => Ok(http::Response::builder()
+                    .status(StatusCode::OK)
+                    .body(box_body(Body::empty()))
+                    .unwrap()),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
+
+/// Lists the bots in a guild and who owns them. Only guild owners can use
+/// this.
+pub struct GuildBotsService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for GuildBotsService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let guild_id = get_query_param(request.uri(), "guild_id")
+                .and_then(|guild_id| guild_id.parse::<u64>().ok());
+            let Some(guild_id) = guild_id else {
+                return Ok(rest_error_response(
+                    "guild_id must be specified".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+            };
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                deps.chat_tree
+                    .check_perms(guild_id, None, user_id, "", true)
+                    .await?;
+                bots::list_guild_bots(&deps, guild_id).await
+            }
+            .await;
+
+            let bots = match res {
+                Ok(bots) => bots,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let bots = bots
+                .into_iter()
+                .map(|(bot_id, owner_id)| {
+                    serde_json::json!({
+                        "bot_id": bot_id,
+                        "owner_id": owner_id,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(json_response(serde_json::json!({ "bots": bots })))
+        };
+
+        Box::pin(fut)
+    }
+}
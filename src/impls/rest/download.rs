@@ -169,14 +169,11 @@ impl Service<HttpRequest> for DownloadService {
 }
 
 pub fn handler(deps: Arc<Dependencies>) -> RateLimit<DownloadService> {
-    let client_ip_header_name = deps.config.policy.ratelimit.client_ip_header_name.clone();
-    let allowed_ips = deps.config.policy.ratelimit.allowed_ips.clone();
-    RateLimit::new(
-        DownloadService { deps },
+    RateLimit::from_config(
+        DownloadService { deps: deps.clone() },
         30,
         Duration::from_secs(5),
-        client_ip_header_name,
-        allowed_ips,
+        &deps.config.policy.ratelimit,
     )
 }
 
@@ -0,0 +1,85 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use tower::Service;
+
+use crate::{impls::auth::get_token_from_header_map, rest_error_response};
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<EventSequencesService> {
+    RateLimit::from_config(
+        EventSequencesService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Returns the event sequence to resume from for every local guild the user
+/// is in. That is the last event the previous event stream of the session went
+/// through, or the latest event of the guild if it never went through one.
+pub struct EventSequencesService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for EventSequencesService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let (user_id, session_id) = match deps
+                .auth_session_with(get_token_from_header_map(request.headers()))
+                .await
+            {
+                Ok(ids) => ids,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let sequences = match deps
+                .chat_tree
+                .get_user_event_log_sequences_logic(user_id)
+                .await
+            {
+                Ok(sequences) => sequences,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+            let resume_points = match deps
+                .auth_tree
+                .get_stream_resume_points_logic(user_id, session_id)
+                .await
+            {
+                Ok(points) => points,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let sequences = sequences
+                .into_iter()
+                .map(|(guild_id, latest)| {
+                    let sequence = resume_points.get(&guild_id).copied().unwrap_or(latest);
+                    serde_json::json!({ "guild_id": guild_id, "sequence": sequence })
+                })
+                .collect::<Vec<_>>();
+            Ok(json_response(serde_json::json!({ "sequences": sequences })))
+        };
+
+        Box::pin(fut)
+    }
+}
@@ -0,0 +1,123 @@
+use std::{borrow::Cow, convert::Infallible};
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use tower::Service;
+
+use crate::{
+    db::{
+        chat::{make_invite_key, make_member_key},
+        deser_invite_entry_guild_id,
+    },
+    impls::{auth::get_token_from_header_map, chat::invite_tracking::INVITES_VIEW},
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn uses_handler(deps: Arc<Dependencies>) -> RateLimit<InviteUsesService> {
+    RateLimit::from_config(
+        InviteUsesService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Returns who joined a guild through an invite and when, along with when the
+/// invite expires. Uses are kept after the invite is used up or expires.
+pub struct InviteUsesService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for InviteUsesService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let uri = request.uri();
+            let guild_id =
+                get_query_param(uri, "guild_id").and_then(|guild_id| guild_id.parse::<u64>().ok());
+            let invite_id = get_query_param(uri, "invite_id")
+                .map(|id| urlencoding::decode(id).unwrap_or(Cow::Borrowed(id)));
+            let (Some(guild_id), Some(invite_id)) = (guild_id, invite_id) else {
+                return Ok(rest_error_response(
+                    "guild_id and invite_id must be specified".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+            };
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                if !deps
+                    .chat_tree
+                    .contains_key(make_member_key(guild_id, user_id))
+                    .await?
+                {
+                    return Err(ServerError::UserNotInGuild { guild_id, user_id });
+                }
+                deps.chat_tree
+                    .check_perms(guild_id, None, user_id, INVITES_VIEW, false)
+                    .await?;
+
+                let uses = deps
+                    .chat_tree
+                    .get_invite_uses_logic(guild_id, &invite_id)
+                    .await?;
+                // invite names are global, so only show the expiry of this guild's invite
+                let is_guild_invite = deps
+                    .chat_tree
+                    .get(make_invite_key(&invite_id))
+                    .await?
+                    .map_or(false, |raw| deser_invite_entry_guild_id(&raw) == guild_id);
+                let expires_at = if is_guild_invite {
+                    deps.chat_tree.get_invite_expiry_logic(&invite_id).await?
+                } else {
+                    None
+                };
+                Ok((uses, expires_at))
+            }
+            .await;
+
+            let (uses, expires_at) = match res {
+                Ok(res) => res,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let uses = uses
+                .into_iter()
+                .map(|(user_id, joined_at)| {
+                    serde_json::json!({
+                        "user_id": user_id,
+                        "joined_at": joined_at,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(json_response(serde_json::json!({
+                "uses": uses,
+                "expires_at": expires_at,
+            })))
+        };
+
+        Box::pin(fut)
+    }
+}
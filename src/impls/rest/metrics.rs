@@ -0,0 +1,85 @@
+use std::{convert::Infallible, fmt::Write};
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use tower::Service;
+
+use crate::rest_error_response;
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<MetricsService> {
+    RateLimit::from_config(
+        MetricsService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Serves metrics in the Prometheus text format, if enabled in the config.
+pub struct MetricsService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for MetricsService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if !deps.config.metrics {
+                return Ok(rest_error_response(
+                    "metrics are disabled".to_string(),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let active_auth_flows =
+                match deps.auth_tree.count_auth_flows_logic(get_time_secs()).await {
+                    Ok(count) => count,
+                    Err(err) => return Ok(err.into_rest_http_response()),
+                };
+
+            let mut body = String::new();
+            write_gauge(
+                &mut body,
+                "scherzo_auth_flows_active",
+                "Logins, registrations and other auth flows that are in progress.",
+                active_auth_flows,
+            );
+
+            Ok(http::Response::builder()
+                .status(StatusCode::OK)
+                .header(
+                    http::header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; version=0.0.4"),
+                )
+                .body(box_body(Body::from(body)))
+                .unwrap())
+        };
+
+        Box::pin(fut)
+    }
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    writeln!(out, "{} {}", name, value).unwrap();
+}
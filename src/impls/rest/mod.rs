@@ -1,6 +1,26 @@
-use crate::{http, utils::http_ratelimit::RateLimit};
+use crate::{http, impls::auth::oidc::OIDC_CALLBACK_PATH, utils::http_ratelimit::RateLimit};
 
-use self::{about::AboutService, download::DownloadService, upload::UploadService};
+use self::{
+    about::AboutService,
+    audit_log::{AuditLogChannelService, AuditLogService},
+    automod::{AutomodRulesService, SetAutomodRulesService},
+    bots::{
+        BotsService, CreateBotService, DeleteBotService, GuildBotsService, RotateBotTokenService,
+    },
+    download::DownloadService,
+    event_sequences::EventSequencesService,
+    invites::InviteUsesService,
+    metrics::MetricsService,
+    moderation::{GuildBansService, RemoveTimeoutService, TimeoutService},
+    oidc::OidcCallbackService,
+    search::SearchService,
+    sessions::{
+        RefreshSessionService, RefreshTokenService, RevokeSessionsService, SessionsService,
+    },
+    slow_mode::{SetSlowModeService, SlowModeService},
+    totp::{TotpConfirmService, TotpEnrollService},
+    upload::UploadService,
+};
 
 use super::{gen_rand_inline_str, get_content_length, prelude::*};
 
@@ -43,13 +63,73 @@ use tower::{Layer, Service};
 use tracing::info;
 
 pub mod about;
+pub mod audit_log;
+pub mod automod;
+pub mod bots;
 pub mod download;
+pub mod event_sequences;
+pub mod invites;
+pub mod metrics;
+pub mod moderation;
+pub mod oidc;
+pub mod search;
+pub mod sessions;
+pub mod slow_mode;
+pub mod totp;
 pub mod upload;
 
 const SEPERATOR: u8 = b'\n';
 
 type Out = Result<HttpResponse, Infallible>;
 
+fn json_response(value: serde_json::Value) -> HttpResponse {
+    http::Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("text/json"),
+        )
+        .body(box_body(Body::from(serde_json::to_vec(&value).unwrap())))
+        .unwrap()
+}
+
+/// JSON request bodies are small, so anything bigger than this is rejected
+/// before it is buffered.
+const MAX_JSON_BODY_LEN: usize = 64 * 1024;
+
+async fn read_json<T: serde::de::DeserializeOwned>(request: HttpRequest) -> Result<T, ServerError> {
+    use hyper::body::HttpBody;
+
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<usize>().ok());
+    if content_length.map_or(false, |len| len > MAX_JSON_BODY_LEN) {
+        return Err(ServerError::BodyTooLarge(MAX_JSON_BODY_LEN));
+    }
+
+    // the length header can be missing or wrong, so the body is checked too
+    let mut body = request.into_body();
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if buf.len() + chunk.len() > MAX_JSON_BODY_LEN {
+            return Err(ServerError::BodyTooLarge(MAX_JSON_BODY_LEN));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+
+    serde_json::from_slice(&buf).map_err(ServerError::InvalidJsonBody)
+}
+
+/// Gets the value of a query parameter of an URI.
+fn get_query_param<'a>(uri: &'a Uri, name: &str) -> Option<&'a str> {
+    uri.query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| value)
+    })
+}
+
 #[derive(Clone)]
 pub struct RestServiceLayer {
     deps: Arc<Dependencies>,
@@ -72,6 +152,31 @@ where
             download: download::handler(self.deps.clone()),
             upload: upload::handler(self.deps.clone()),
             about: about::handler(self.deps.clone()),
+            search: search::handler(self.deps.clone()),
+            event_sequences: event_sequences::handler(self.deps.clone()),
+            sessions: sessions::handler(self.deps.clone()),
+            revoke_sessions: sessions::revoke_handler(self.deps.clone()),
+            refresh_token: sessions::refresh_token_handler(self.deps.clone()),
+            refresh_session: sessions::refresh_handler(self.deps.clone()),
+            totp_enroll: totp::enroll_handler(self.deps.clone()),
+            totp_confirm: totp::confirm_handler(self.deps.clone()),
+            metrics: metrics::handler(self.deps.clone()),
+            oidc_callback: oidc::handler(self.deps.clone()),
+            bots: bots::handler(self.deps.clone()),
+            create_bot: bots::create_handler(self.deps.clone()),
+            rotate_bot_token: bots::rotate_token_handler(self.deps.clone()),
+            delete_bot: bots::delete_handler(self.deps.clone()),
+            guild_bots: bots::guild_handler(self.deps.clone()),
+            audit_log: audit_log::handler(self.deps.clone()),
+            audit_log_channel: audit_log::channel_handler(self.deps.clone()),
+            timeout: moderation::timeout_handler(self.deps.clone()),
+            remove_timeout: moderation::remove_timeout_handler(self.deps.clone()),
+            guild_bans: moderation::bans_handler(self.deps.clone()),
+            slow_mode: slow_mode::handler(self.deps.clone()),
+            set_slow_mode: slow_mode::set_handler(self.deps.clone()),
+            automod_rules: automod::handler(self.deps.clone()),
+            set_automod_rules: automod::set_handler(self.deps.clone()),
+            invite_uses: invites::uses_handler(self.deps.clone()),
             inner,
         }
     }
@@ -81,6 +186,31 @@ pub struct RestService<S> {
     download: RateLimit<DownloadService>,
     upload: RateLimit<UploadService>,
     about: RateLimit<AboutService>,
+    search: RateLimit<SearchService>,
+    event_sequences: RateLimit<EventSequencesService>,
+    sessions: RateLimit<SessionsService>,
+    revoke_sessions: RateLimit<RevokeSessionsService>,
+    refresh_token: RateLimit<RefreshTokenService>,
+    refresh_session: RateLimit<RefreshSessionService>,
+    totp_enroll: RateLimit<TotpEnrollService>,
+    totp_confirm: RateLimit<TotpConfirmService>,
+    metrics: RateLimit<MetricsService>,
+    oidc_callback: RateLimit<OidcCallbackService>,
+    bots: RateLimit<BotsService>,
+    create_bot: RateLimit<CreateBotService>,
+    rotate_bot_token: RateLimit<RotateBotTokenService>,
+    delete_bot: RateLimit<DeleteBotService>,
+    guild_bots: RateLimit<GuildBotsService>,
+    audit_log: RateLimit<AuditLogService>,
+    audit_log_channel: RateLimit<AuditLogChannelService>,
+    timeout: RateLimit<TimeoutService>,
+    remove_timeout: RateLimit<RemoveTimeoutService>,
+    guild_bans: RateLimit<GuildBansService>,
+    slow_mode: RateLimit<SlowModeService>,
+    set_slow_mode: RateLimit<SetSlowModeService>,
+    automod_rules: RateLimit<AutomodRulesService>,
+    set_automod_rules: RateLimit<SetAutomodRulesService>,
+    invite_uses: RateLimit<InviteUsesService>,
     inner: S,
 }
 
@@ -98,7 +228,32 @@ where
         let pending = Service::poll_ready(&mut self.inner, cx).is_pending()
             | Service::poll_ready(&mut self.about, cx).is_pending()
             | Service::poll_ready(&mut self.download, cx).is_pending()
-            | Service::poll_ready(&mut self.upload, cx).is_pending();
+            | Service::poll_ready(&mut self.upload, cx).is_pending()
+            | Service::poll_ready(&mut self.search, cx).is_pending()
+            | Service::poll_ready(&mut self.event_sequences, cx).is_pending()
+            | Service::poll_ready(&mut self.sessions, cx).is_pending()
+            | Service::poll_ready(&mut self.revoke_sessions, cx).is_pending()
+            | Service::poll_ready(&mut self.refresh_token, cx).is_pending()
+            | Service::poll_ready(&mut self.refresh_session, cx).is_pending()
+            | Service::poll_ready(&mut self.totp_enroll, cx).is_pending()
+            | Service::poll_ready(&mut self.totp_confirm, cx).is_pending()
+            | Service::poll_ready(&mut self.metrics, cx).is_pending()
+            | Service::poll_ready(&mut self.oidc_callback, cx).is_pending()
+            | Service::poll_ready(&mut self.bots, cx).is_pending()
+            | Service::poll_ready(&mut self.create_bot, cx).is_pending()
+            | Service::poll_ready(&mut self.rotate_bot_token, cx).is_pending()
+            | Service::poll_ready(&mut self.delete_bot, cx).is_pending()
+            | Service::poll_ready(&mut self.guild_bots, cx).is_pending()
+            | Service::poll_ready(&mut self.audit_log, cx).is_pending()
+            | Service::poll_ready(&mut self.audit_log_channel, cx).is_pending()
+            | Service::poll_ready(&mut self.timeout, cx).is_pending()
+            | Service::poll_ready(&mut self.remove_timeout, cx).is_pending()
+            | Service::poll_ready(&mut self.guild_bans, cx).is_pending()
+            | Service::poll_ready(&mut self.slow_mode, cx).is_pending()
+            | Service::poll_ready(&mut self.set_slow_mode, cx).is_pending()
+            | Service::poll_ready(&mut self.automod_rules, cx).is_pending()
+            | Service::poll_ready(&mut self.set_automod_rules, cx).is_pending()
+            | Service::poll_ready(&mut self.invite_uses, cx).is_pending();
 
         pending
             .then(|| Poll::Pending)
@@ -114,6 +269,73 @@ where
             match path {
                 "/_harmony/media/upload" => RestFuture::Other(Service::call(&mut self.upload, req)),
                 "/_harmony/about" => RestFuture::About(Service::call(&mut self.about, req)),
+                "/_harmony/search" => RestFuture::Other(Service::call(&mut self.search, req)),
+                "/_harmony/events/sequences" => {
+                    RestFuture::Other(Service::call(&mut self.event_sequences, req))
+                }
+                "/_harmony/sessions" => RestFuture::Other(Service::call(&mut self.sessions, req)),
+                "/_harmony/sessions/revoke" => {
+                    RestFuture::Other(Service::call(&mut self.revoke_sessions, req))
+                }
+                "/_harmony/sessions/refresh-token" => {
+                    RestFuture::Other(Service::call(&mut self.refresh_token, req))
+                }
+                "/_harmony/sessions/refresh" => {
+                    RestFuture::Other(Service::call(&mut self.refresh_session, req))
+                }
+                "/_harmony/totp/enroll" => {
+                    RestFuture::Other(Service::call(&mut self.totp_enroll, req))
+                }
+                "/_harmony/totp/confirm" => {
+                    RestFuture::Other(Service::call(&mut self.totp_confirm, req))
+                }
+                "/_harmony/metrics" => RestFuture::Other(Service::call(&mut self.metrics, req)),
+                "/_harmony/bots" => RestFuture::Other(Service::call(&mut self.bots, req)),
+                "/_harmony/bots/create" => {
+                    RestFuture::Other(Service::call(&mut self.create_bot, req))
+                }
+                "/_harmony/bots/rotate-token" => {
+                    RestFuture::Other(Service::call(&mut self.rotate_bot_token, req))
+                }
+                "/_harmony/bots/delete" => {
+                    RestFuture::Other(Service::call(&mut self.delete_bot, req))
+                }
+                "/_harmony/guilds/bots" => {
+                    RestFuture::Other(Service::call(&mut self.guild_bots, req))
+                }
+                "/_harmony/guilds/audit-log" => {
+                    RestFuture::Other(Service::call(&mut self.audit_log, req))
+                }
+                "/_harmony/guilds/audit-log/channel" => {
+                    RestFuture::Other(Service::call(&mut self.audit_log_channel, req))
+                }
+                "/_harmony/guilds/timeout" => {
+                    RestFuture::Other(Service::call(&mut self.timeout, req))
+                }
+                "/_harmony/guilds/timeout/remove" => {
+                    RestFuture::Other(Service::call(&mut self.remove_timeout, req))
+                }
+                "/_harmony/guilds/bans" => {
+                    RestFuture::Other(Service::call(&mut self.guild_bans, req))
+                }
+                "/_harmony/guilds/automod" => {
+                    RestFuture::Other(Service::call(&mut self.automod_rules, req))
+                }
+                "/_harmony/guilds/automod/set" => {
+                    RestFuture::Other(Service::call(&mut self.set_automod_rules, req))
+                }
+                "/_harmony/guilds/invite-uses" => {
+                    RestFuture::Other(Service::call(&mut self.invite_uses, req))
+                }
+                "/_harmony/channels/slow-mode" => {
+                    RestFuture::Other(Service::call(&mut self.slow_mode, req))
+                }
+                "/_harmony/channels/slow-mode/set" => {
+                    RestFuture::Other(Service::call(&mut self.set_slow_mode, req))
+                }
+                OIDC_CALLBACK_PATH => {
+                    RestFuture::Other(Service::call(&mut self.oidc_callback, req))
+                }
                 _ => RestFuture::Inner(Service::call(&mut self.inner, req)),
             }
         }
@@ -0,0 +1,276 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use serde::Deserialize;
+use tower::Service;
+
+use crate::{
+    db::chat::make_member_key,
+    impls::{
+        auth::get_token_from_header_map,
+        chat::{audit_log::AUDIT_LOG_REASON_HEADER, timeouts::TIMEOUT_MANAGE, ChatServer},
+    },
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn timeout_handler(deps: Arc<Dependencies>) -> RateLimit<TimeoutService> {
+    RateLimit::from_config(
+        TimeoutService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn remove_timeout_handler(deps: Arc<Dependencies>) -> RateLimit<RemoveTimeoutService> {
+    RateLimit::from_config(
+        RemoveTimeoutService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn bans_handler(deps: Arc<Dependencies>) -> RateLimit<GuildBansService> {
+    RateLimit::from_config(
+        GuildBansService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+fn get_reason(request: &HttpRequest) -> Option<String> {
+    request
+        .headers()
+        .get(AUDIT_LOG_REASON_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .map(ToString::to_string)
+}
+
+/// Checks that a user can manage the timeouts of a member of a guild.
+async fn check_can_timeout(
+    deps: &Dependencies,
+    guild_id: u64,
+    user_id: u64,
+    target_id: u64,
+) -> Result<(), ServerError> {
+    for user_id in [user_id, target_id] {
+        if !deps
+            .chat_tree
+            .contains_key(make_member_key(guild_id, user_id))
+            .await?
+        {
+            return Err(ServerError::UserNotInGuild { guild_id, user_id });
+        }
+    }
+    deps.chat_tree
+        .check_perms(guild_id, None, user_id, TIMEOUT_MANAGE, false)
+        .await
+}
+
+#[derive(Debug, Deserialize)]
+struct TimeoutUser {
+    guild_id: u64,
+    user_id: u64,
+    /// In seconds
+    duration: u64,
+}
+
+/// Times out a member of a guild, so they can't send messages, type or react
+/// until the timeout expires.
+pub struct TimeoutService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for TimeoutService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let reason = get_reason(&request);
+                let TimeoutUser {
+                    guild_id,
+                    user_id: target_id,
+                    duration,
+                } = read_json(request).await?;
+                check_can_timeout(&deps, guild_id, user_id, target_id).await?;
+                ChatServer::new(deps.clone())
+                    .timeout_user(guild_id, user_id, target_id, duration, reason)
+                    .await
+            }
+            .await;
+
+            match res {
+                Ok(()) => Ok(http::Response::builder()
+                    .status(StatusCode::OK)
+                    .body(box_body(Body::empty()))
+                    .unwrap()),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoveTimeout {
+    guild_id: u64,
+    user_id: u64,
+}
+
+/// Lifts the timeout of a member of a guild before it expires.
+pub struct RemoveTimeoutService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for RemoveTimeoutService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let reason = get_reason(&request);
+                let RemoveTimeout {
+                    guild_id,
+                    user_id: target_id,
+                } = read_json(request).await?;
+                check_can_timeout(&deps, guild_id, user_id, target_id).await?;
+                ChatServer::new(deps.clone())
+                    .remove_timeout(guild_id, user_id, target_id, reason)
+                    .await
+            }
+            .await;
+
+            match res {
+                Ok(removed) => Ok(json_response(serde_json::json!({ "removed": removed }))),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
+
+/// Lists the users banned from a guild, along with when they were banned and
+/// when their ban expires. Permanent bans have no expiry.
+/// `GetBannedUsers` only has room for the IDs of banned users, so this is
+/// where clients get the rest.
+pub struct GuildBansService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for GuildBansService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let guild_id = get_query_param(request.uri(), "guild_id")
+                .and_then(|guild_id| guild_id.parse::<u64>().ok());
+            let Some(guild_id) = guild_id else {
+                return Ok(rest_error_response(
+                    "guild_id must be specified".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+            };
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                if !deps
+                    .chat_tree
+                    .contains_key(make_member_key(guild_id, user_id))
+                    .await?
+                {
+                    return Err(ServerError::UserNotInGuild { guild_id, user_id });
+                }
+                deps.chat_tree.get_guild_bans_logic(guild_id).await
+            }
+            .await;
+
+            let bans = match res {
+                Ok(bans) => bans,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let bans = bans
+                .into_iter()
+                .map(|(user_id, banned_at, expires_at)| {
+                    serde_json::json!({
+                        "user_id": user_id,
+                        "banned_at": banned_at,
+                        "expires_at": expires_at,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(json_response(serde_json::json!({ "bans": bans })))
+        };
+
+        Box::pin(fut)
+    }
+}
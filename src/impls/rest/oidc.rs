@@ -0,0 +1,125 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use tower::Service;
+
+use crate::rest_error_response;
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<OidcCallbackService> {
+    RateLimit::from_config(
+        OidcCallbackService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Where the identity provider redirects users after they log in. Shows them
+/// the code, which they finish the auth flow the `state` was created for with
+/// by submitting it in the `oidc` form. The code isn't exchanged here, since
+/// whoever opened the authorization URL doesn't have to be who started the flow.
+pub struct OidcCallbackService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for OidcCallbackService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if deps.config.oidc.is_none() {
+                return Ok(rest_error_response(
+                    "oidc is not configured".to_string(),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let query = request.uri().query().unwrap_or_default();
+            let param = |name: &str| {
+                query.split('&').find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    (key == name).then(|| urlencoding::decode(value).ok())?
+                })
+            };
+
+            if let Some(error) = param("error") {
+                return Ok(rest_error_response(
+                    format!("identity provider returned an error: {}", error),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            let (Some(code), Some(state)) = (param("code"), param("state")) else {
+                return Ok(rest_error_response(
+                    "code and state must be specified".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+            };
+
+            let res: Result<(), ServerError> = async {
+                let now = get_time_secs();
+                let auth_id = deps
+                    .auth_tree
+                    .take_oidc_state_logic(&state, now)
+                    .await?
+                    .ok_or(ServerError::InvalidAuthId)?;
+                // make sure the flow is still there before showing the code
+                deps.auth_tree.get_auth_flow_logic(&auth_id, now).await?;
+                Ok(())
+            }
+            .await;
+
+            if let Err(err) = res {
+                return Ok(err.into_rest_http_response());
+            }
+
+            let body = format!(
+                "<!DOCTYPE html><html><body><p>Logged in. Enter this code in your client to finish, and don't share it with anyone:</p><pre>{}</pre></body></html>",
+                escape_html(&code),
+            );
+            Ok(http::Response::builder()
+                .status(StatusCode::OK)
+                .header(
+                    http::header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/html"),
+                )
+                .body(box_body(Body::from(body)))
+                .unwrap())
+        };
+
+        Box::pin(fut)
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,71 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use tower::Service;
+
+use crate::{
+    impls::{auth::get_token_from_header_map, chat::search::SearchQuery},
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<SearchService> {
+    RateLimit::from_config(
+        SearchService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub struct SearchService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for SearchService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let user_id = match deps
+                .auth_with(get_token_from_header_map(request.headers()))
+                .await
+            {
+                Ok(user_id) => user_id,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let query: SearchQuery = match read_json(request).await {
+                Ok(query) => query,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let results = match deps.chat_tree.search_messages_logic(user_id, &query).await {
+                Ok(results) => results,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            Ok(json_response(serde_json::json!({ "results": results })))
+        };
+
+        Box::pin(fut)
+    }
+}
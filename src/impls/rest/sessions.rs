@@ -0,0 +1,317 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use serde::Deserialize;
+use tower::Service;
+
+use crate::{
+    impls::{auth::get_token_from_header_map, chat::StreamCancel},
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<SessionsService> {
+    RateLimit::from_config(
+        SessionsService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn revoke_handler(deps: Arc<Dependencies>) -> RateLimit<RevokeSessionsService> {
+    RateLimit::from_config(
+        RevokeSessionsService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Lists the sessions of the user, marking the one the request was made with
+/// as `current`.
+pub struct SessionsService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for SessionsService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let (user_id, current_session_id) = match deps
+                .auth_session_with(get_token_from_header_map(request.headers()))
+                .await
+            {
+                Ok(ids) => ids,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let sessions = match deps.auth_tree.get_sessions_logic(user_id).await {
+                Ok(sessions) => sessions,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let sessions = sessions
+                .into_iter()
+                .map(|(session_id, session)| {
+                    serde_json::json!({
+                        "session_id": session_id,
+                        "device_name": session.device_name,
+                        "ip": session.ip,
+                        "created_at": session.created_at,
+                        "last_seen": session.last_seen,
+                        "current": session_id == current_session_id,
+                    })
+                })
+                .collect::<Vec<_>>();
+            Ok(json_response(serde_json::json!({ "sessions": sessions })))
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RevokeSessions {
+    session_id: Option<u64>,
+    #[serde(default)]
+    all: bool,
+}
+
+/// Revokes one session of the user, or all of them if `all` is set, closing
+/// any event streams opened with them.
+pub struct RevokeSessionsService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for RevokeSessionsService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let user_id = match deps
+                .auth_with(get_token_from_header_map(request.headers()))
+                .await
+            {
+                Ok(user_id) => user_id,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let revoke: RevokeSessions = match read_json(request).await {
+                Ok(revoke) => revoke,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let cancel = match revoke {
+                RevokeSessions { all: true, .. } => {
+                    if let Err(err) = deps.auth_tree.revoke_all_sessions_logic(user_id).await {
+                        return Ok(err.into_rest_http_response());
+                    }
+                    StreamCancel::user(user_id)
+                }
+                RevokeSessions {
+                    session_id: Some(session_id),
+                    ..
+                } => match deps
+                    .auth_tree
+                    .revoke_session_logic(user_id, session_id)
+                    .await
+                {
+                    Ok(true) => StreamCancel::session(user_id, session_id),
+                    Ok(false) => {
+                        return Ok(ServerError::NoSuchSession(session_id).into_rest_http_response())
+                    }
+                    Err(err) => return Ok(err.into_rest_http_response()),
+                },
+                RevokeSessions {
+                    session_id: None, ..
+                } => {
+                    return Ok(rest_error_response(
+                        "either `session_id` or `all` must be set".to_string(),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                }
+            };
+            deps.chat_event_sender.cancel(cancel);
+
+            Ok(http::Response::builder()
+                .status(StatusCode::OK)
+                .body(box_body(Body::empty()))
+                .unwrap())
+        };
+
+        Box::pin(fut)
+    }
+}
+
+pub fn refresh_token_handler(deps: Arc<Dependencies>) -> RateLimit<RefreshTokenService> {
+    RateLimit::from_config(
+        RefreshTokenService { deps: deps.clone() },
+        3,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn refresh_handler(deps: Arc<Dependencies>) -> RateLimit<RefreshSessionService> {
+    RateLimit::from_config(
+        RefreshSessionService { deps: deps.clone() },
+        3,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Issues a refresh token for the session the request was made with, replacing
+/// its previous refresh token.
+pub struct RefreshTokenService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for RefreshTokenService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let (user_id, session_id) = match deps
+                .auth_session_with(get_token_from_header_map(request.headers()))
+                .await
+            {
+                Ok(ids) => ids,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let lifetime = deps.config.policy.sessions.refresh_token_lifetime;
+            let (refresh_token, expires_at) = match deps
+                .auth_tree
+                .issue_refresh_token_logic(user_id, session_id, lifetime)
+                .await
+            {
+                Ok(issued) => issued,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            Ok(json_response(serde_json::json!({
+                "refresh_token": refresh_token,
+                "expires_at": expires_at,
+            })))
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RefreshSession {
+    refresh_token: String,
+}
+
+/// Exchanges a refresh token for a new session token and refresh token, so
+/// clients don't need to go through the auth flow again when their session
+/// token expires. This doesn't need authentication.
+pub struct RefreshSessionService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for RefreshSessionService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let refresh: RefreshSession = match read_json(request).await {
+                Ok(refresh) => refresh,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let lifetime = deps.config.policy.sessions.refresh_token_lifetime;
+            let refreshed = match deps
+                .auth_tree
+                .refresh_session_logic(&refresh.refresh_token, lifetime)
+                .await
+            {
+                Ok(refreshed) => refreshed,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            Ok(json_response(serde_json::json!({
+                "user_id": refreshed.user_id,
+                "session_id": refreshed.session_id,
+                "session_token": refreshed.session_token,
+                "refresh_token": refreshed.refresh_token,
+                "expires_at": refreshed.refresh_expires_at,
+            })))
+        };
+
+        Box::pin(fut)
+    }
+}
@@ -0,0 +1,177 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use serde::Deserialize;
+use tower::Service;
+
+use crate::{
+    db::chat::make_member_key, impls::auth::get_token_from_header_map, rest_error_response,
+};
+
+use super::*;
+
+pub fn handler(deps: Arc<Dependencies>) -> RateLimit<SlowModeService> {
+    RateLimit::from_config(
+        SlowModeService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn set_handler(deps: Arc<Dependencies>) -> RateLimit<SetSlowModeService> {
+    RateLimit::from_config(
+        SetSlowModeService { deps: deps.clone() },
+        5,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Returns the slow mode interval of a channel in seconds, `0` if it isn't in
+/// slow mode.
+pub struct SlowModeService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for SlowModeService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::GET {
+                return Ok(rest_error_response(
+                    "method must be GET".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let uri = request.uri();
+            let get_id = |name| get_query_param(uri, name).and_then(|id| id.parse::<u64>().ok());
+            let ids = (get_id("guild_id"), get_id("channel_id"));
+            let (Some(guild_id), Some(channel_id)) = ids else {
+                return Ok(rest_error_response(
+                    "guild_id and channel_id must be specified".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+            };
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                if !deps
+                    .chat_tree
+                    .contains_key(make_member_key(guild_id, user_id))
+                    .await?
+                {
+                    return Err(ServerError::UserNotInGuild { guild_id, user_id });
+                }
+                deps.chat_tree
+                    .check_perms(guild_id, Some(channel_id), user_id, "messages.view", false)
+                    .await?;
+                deps.chat_tree
+                    .get_slow_mode_logic(guild_id, channel_id)
+                    .await
+            }
+            .await;
+
+            match res {
+                Ok(interval) => Ok(json_response(serde_json::json!({ "interval": interval }))),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetSlowMode {
+    guild_id: u64,
+    channel_id: u64,
+    /// In seconds, `0` turns slow mode off
+    interval: u64,
+}
+
+/// Sets the slow mode interval of a channel. Needs the same permission as
+/// changing the channel's information.
+pub struct SetSlowModeService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for SetSlowModeService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                let SetSlowMode {
+                    guild_id,
+                    channel_id,
+                    interval,
+                } = read_json(request).await?;
+                if !deps
+                    .chat_tree
+                    .contains_key(make_member_key(guild_id, user_id))
+                    .await?
+                {
+                    return Err(ServerError::UserNotInGuild { guild_id, user_id });
+                }
+                deps.chat_tree
+                    .check_perms(
+                        guild_id,
+                        Some(channel_id),
+                        user_id,
+                        "channels.manage.change-information",
+                        false,
+                    )
+                    .await?;
+                deps.chat_tree
+                    .set_slow_mode_logic(guild_id, channel_id, interval)
+                    .await
+            }
+            .await;
+
+            match res {
+                Ok(()) => Ok(http::Response::builder()
+                    .status(StatusCode::OK)
+                    .body(box_body(Body::empty()))
+                    .unwrap()),
+                Err(err) => Ok(err.into_rest_http_response()),
+            }
+        };
+
+        Box::pin(fut)
+    }
+}
@@ -0,0 +1,161 @@
+use std::convert::Infallible;
+
+use hrpc::{exports::futures_util::future::BoxFuture, server::transport::http::HttpResponse};
+use serde::Deserialize;
+use tower::Service;
+
+use crate::{
+    db::profile::make_user_profile_key,
+    impls::auth::{
+        get_token_from_header_map,
+        totp::{base32_encode, totp_provisioning_uri},
+    },
+    rest_error_response,
+};
+
+use super::*;
+
+pub fn enroll_handler(deps: Arc<Dependencies>) -> RateLimit<TotpEnrollService> {
+    RateLimit::from_config(
+        TotpEnrollService { deps: deps.clone() },
+        3,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+pub fn confirm_handler(deps: Arc<Dependencies>) -> RateLimit<TotpConfirmService> {
+    RateLimit::from_config(
+        TotpConfirmService { deps: deps.clone() },
+        3,
+        Duration::from_secs(5),
+        &deps.config.policy.ratelimit,
+    )
+}
+
+/// Starts TOTP enrollment for the user, returning the new secret and a
+/// provisioning URI for authenticator apps. Enrollment must be finished with
+/// a code from [`TotpConfirmService`].
+pub struct TotpEnrollService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for TotpEnrollService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let user_id = match deps
+                .auth_with(get_token_from_header_map(request.headers()))
+                .await
+            {
+                Ok(user_id) => user_id,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let account = match deps.profile_tree.get(make_user_profile_key(user_id)).await {
+                Ok(Some(raw)) => db::deser_profile(raw).user_name,
+                Ok(None) => user_id.to_string(),
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let secret = match deps.auth_tree.begin_totp_enrollment_logic(user_id).await {
+                Ok(secret) => secret,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let issuer = match deps.config.host.as_str() {
+                "" => "scherzo",
+                host => host,
+            };
+
+            Ok(json_response(serde_json::json!({
+                "secret": base32_encode(&secret),
+                "uri": totp_provisioning_uri(&secret, issuer, &account),
+            })))
+        };
+
+        Box::pin(fut)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TotpConfirm {
+    code: String,
+}
+
+/// Enables TOTP for the user if the code is valid, returning their one-time
+/// recovery codes.
+pub struct TotpConfirmService {
+    deps: Arc<Dependencies>,
+}
+
+impl Service<HttpRequest> for TotpConfirmService {
+    type Response = HttpResponse;
+
+    type Error = Infallible;
+
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let deps = self.deps.clone();
+
+        let fut = async move {
+            if request.method() != Method::POST {
+                return Ok(rest_error_response(
+                    "method must be POST".to_string(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ));
+            }
+
+            let user_id = match deps
+                .auth_with(get_token_from_header_map(request.headers()))
+                .await
+            {
+                Ok(user_id) => user_id,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let confirm: TotpConfirm = match read_json(request).await {
+                Ok(confirm) => confirm,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            let recovery_codes = match deps
+                .auth_tree
+                .confirm_totp_logic(user_id, confirm.code.trim(), get_time_secs())
+                .await
+            {
+                Ok(recovery_codes) => recovery_codes,
+                Err(err) => return Ok(err.into_rest_http_response()),
+            };
+
+            Ok(json_response(
+                serde_json::json!({ "recovery_codes": recovery_codes }),
+            ))
+        };
+
+        Box::pin(fut)
+    }
+}
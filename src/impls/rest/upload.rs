@@ -8,14 +8,11 @@ use crate::{impls::auth::get_token_from_header_map, rest_error_response};
 use super::*;
 
 pub fn handler(deps: Arc<Dependencies>) -> RateLimit<UploadService> {
-    let client_ip_header_name = deps.config.policy.ratelimit.client_ip_header_name.clone();
-    let allowed_ips = deps.config.policy.ratelimit.allowed_ips.clone();
-    RateLimit::new(
-        UploadService { deps },
+    RateLimit::from_config(
+        UploadService { deps: deps.clone() },
         3,
         Duration::from_secs(5),
-        client_ip_header_name,
-        allowed_ips,
+        &deps.config.policy.ratelimit,
     )
 }
 
@@ -38,10 +35,14 @@ impl Service<HttpRequest> for UploadService {
         let deps = self.deps.clone();
 
         let fut = async move {
-            if let Err(err) = deps
-                .auth_with(get_token_from_header_map(request.headers()))
-                .await
-            {
+            let auth_res = async {
+                let user_id = deps
+                    .auth_with(get_token_from_header_map(request.headers()))
+                    .await?;
+                deps.chat_tree.check_not_guest(user_id).await
+            }
+            .await;
+            if let Err(err) = auth_res {
                 return Ok(err.into_rest_http_response());
             }
             let boundary_res = request
@@ -3,6 +3,7 @@
 use std::time::Duration;
 
 use crate::api::{
+    chat::{stream_event, Event as StreamEvent},
     harmonytypes::Token,
     sync::{event::*, postbox_service_client::PostboxServiceClient, *},
 };
@@ -16,7 +17,11 @@ use tracing::error;
 
 use crate::key::{self, Manager as KeyManager};
 
-use super::{http, prelude::*};
+use super::{
+    chat::{ChatServer, EventBroadcast, EventContext, EventSub},
+    http,
+    prelude::*,
+};
 use db::sync::*;
 
 pub mod notify_new_id;
@@ -47,11 +52,13 @@ impl Clients {
 #[derive(Clone)]
 pub struct SyncServer {
     deps: Arc<Dependencies>,
+    chat: ChatServer,
 }
 
 impl SyncServer {
     pub fn new(deps: Arc<Dependencies>, mut dispatch_rx: UnboundedReceiver<EventDispatch>) -> Self {
-        let sync = Self { deps };
+        let chat = ChatServer::new(deps.clone());
+        let sync = Self { deps, chat };
         let sync2 = sync.clone();
         let clients = Clients(DashMap::default());
 
@@ -251,8 +258,40 @@ impl SyncServer {
                         .add_guild_to_guild_list(user_id, guild_id, host)
                         .await?;
                 }
-                Kind::UserInvited(_) => todo!(),
-                Kind::UserRejectedInvite(_) => todo!(),
+                Kind::UserInvited(UserInvited {
+                    invite_id,
+                    user_id,
+                    inviter_id,
+                }) => {
+                    self.deps
+                        .chat_tree
+                        .add_pending_invite_logic(user_id, host, &invite_id, inviter_id)
+                        .await?;
+                    let broadcast = EventBroadcast::new(
+                        EventSub::Homeserver,
+                        StreamEvent::Chat(stream_event::Event::InviteReceived(
+                            stream_event::InviteReceived {
+                                invite_id,
+                                server_id: Some(host.to_string()),
+                                inviter_id,
+                            },
+                        )),
+                        None,
+                        EventContext::new(vec![user_id]),
+                    );
+                    self.deps.chat_event_sender.send(Arc::new(broadcast));
+                }
+                Kind::UserRejectedInvite(UserRejectedInvite {
+                    invite_id, user_id, ..
+                }) => {
+                    let local_id = self
+                        .deps
+                        .profile_tree
+                        .foreign_to_local_id(user_id, host)
+                        .await?
+                        .ok_or(ServerError::NoSuchUser(user_id))?;
+                    self.chat.reject_user_invite(&invite_id, local_id).await?;
+                }
             }
         }
         Ok(())
@@ -6,12 +6,15 @@ static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 use std::{
     net::SocketAddr,
+    ops::Not,
     path::{Path, PathBuf},
     time::Duration,
 };
 
 use harmony_rust_sdk::api::{
-    chat::{content, guild_kind, ChannelKind, FormattedText, Permission},
+    chat::{
+        content, guild_kind, stream_event, ChannelKind, Event, FormattedText, Permission,
+    },
     exports::hrpc::server::transport::{http::Hyper, Transport},
 };
 use hrpc::{
@@ -33,13 +36,17 @@ use scherzo::{
     },
     impls::{
         admin_action, against,
-        chat::{AdminGuildKeys, DEFAULT_ROLE_ID},
+        chat::{
+            AdminGuildKeys, ChatServer, EventBroadcast, EventContext, EventSub, PermCheck,
+            DEFAULT_ROLE_ID,
+        },
         rest::RestServiceLayer,
         Dependencies,
     },
     utils, ServerError,
 };
 use tower::limit::ConcurrencyLimitLayer;
+use triomphe::Arc;
 use tower_http::{
     cors::CorsLayer,
     map_response_body::MapResponseBodyLayer,
@@ -55,6 +62,14 @@ use tracing_subscriber::{filter::Targets, fmt, prelude::*};
 // this is expensive if you have big DBs (>500mb uncompressed)
 const INTEGRITY_VERIFICATION_PERIOD: u64 = 60 * 60;
 
+// in seconds
+// do once every ten minutes
+const RETENTION_PRUNE_PERIOD: u64 = 60 * 10;
+
+// in seconds
+// do once every minute
+const EXPIRY_PERIOD: u64 = 60;
+
 fn main() {
     let mut db_path = "db".to_string();
     let mut console = false;
@@ -117,6 +132,14 @@ pub fn run(db_path: String, console: bool, jaeger: bool, log_level: Level) {
         }));
 
     let integrity = start_integrity_check_thread(deps.as_ref());
+    let retention = deps
+        .config
+        .runs_periodic_tasks()
+        .then(|| start_retention_task(deps.as_ref()));
+    let expiry = deps
+        .config
+        .runs_periodic_tasks()
+        .then(|| start_expiry_task(&deps));
 
     let transport = setup_transport(deps.as_ref(), rest);
     let serve = tokio::spawn(
@@ -140,6 +163,12 @@ pub fn run(db_path: String, console: bool, jaeger: bool, log_level: Level) {
     tracing::info!("shutting down...");
 
     integrity.abort();
+    if let Some(retention) = retention {
+        retention.abort();
+    }
+    if let Some(expiry) = expiry {
+        expiry.abort();
+    }
 
     if let Ok(Err(err)) = rt.block_on(tokio::time::timeout(Duration::from_secs(1), db.flush())) {
         panic!("failed to flush: {}", err);
@@ -180,7 +209,10 @@ async fn setup_db(db_path: String, config: &Config) -> (Db, usize) {
         .expect("something went wrong while checking if the db needs migrations!!!");
     if needs_migration {
         // Backup db before attempting to apply migrations
-        if current_db_version > 0 {
+        // databases that aren't stored locally (eg. postgres) must be backed up by the admin
+        if current_db_version > 0 && Path::new(&db_path).exists().not() {
+            warn!("database is not stored locally, so it won't be backed up before migrating!");
+        } else if current_db_version > 0 {
             let db_backup_name = format!("{}_backup_ver_{}", db_path, current_db_version);
             let db_backup_path = config.db.db_backup_path.as_ref().map_or_else(
                 || Path::new(&db_backup_name).to_path_buf(),
@@ -355,7 +387,7 @@ async fn setup_admin_guild(deps: &Dependencies) {
         .unwrap();
     let invite_id = format!("{}", guild_id);
     deps.chat_tree
-        .create_invite_logic(guild_id, &invite_id, 1)
+        .create_invite_logic(guild_id, &invite_id, 1, None)
         .await
         .unwrap();
     deps.chat_tree
@@ -409,6 +441,71 @@ fn start_integrity_check_thread(deps: &Dependencies) -> tokio::task::JoinHandle<
     tokio::spawn(fut.instrument(info_span!("scherzo::db")))
 }
 
+fn start_retention_task(deps: &Dependencies) -> tokio::task::JoinHandle<()> {
+    let ctt = deps.chat_tree.clone();
+    let event_sender = deps.chat_event_sender.clone();
+    let default_policy = deps.config.policy.retention;
+    let max_events = deps.config.policy.event_log.max_events;
+
+    let fut = async move {
+        info!("message retention task is running");
+        loop {
+            tokio::time::sleep(Duration::from_secs(RETENTION_PRUNE_PERIOD)).await;
+            match ctt.prune_messages_logic(default_policy).await {
+                Ok(pruned) => {
+                    debug!("pruned {} messages", pruned.len());
+                    for (guild_id, channel_id, message_id) in pruned {
+                        let mut broadcast = EventBroadcast::new(
+                            EventSub::Guild(guild_id),
+                            Event::Chat(stream_event::Event::DeletedMessage(
+                                stream_event::MessageDeleted {
+                                    guild_id,
+                                    channel_id,
+                                    message_id,
+                                },
+                            )),
+                            Some(PermCheck::new(
+                                guild_id,
+                                Some(channel_id),
+                                "messages.view",
+                                false,
+                            )),
+                            EventContext::empty(),
+                        );
+                        if let Err(err) = ctt.log_event_logic(&mut broadcast, max_events).await {
+                            error!("couldnt log event: {}", err);
+                        }
+                        event_sender.send(Arc::new(broadcast));
+                    }
+                }
+                Err(err) => error!("failed to prune messages: {}", err),
+            }
+        }
+    };
+
+    tokio::spawn(fut.instrument(info_span!("scherzo::retention")))
+}
+
+fn start_expiry_task(deps: &Arc<Dependencies>) -> tokio::task::JoinHandle<()> {
+    let chat = ChatServer::new(deps.clone());
+    let chat_tree = deps.chat_tree.clone();
+
+    let fut = async move {
+        info!("expiry task is running");
+        loop {
+            tokio::time::sleep(Duration::from_secs(EXPIRY_PERIOD)).await;
+            if let Err(err) = chat.lift_expired_logic(utils::get_time_secs()).await {
+                error!("failed to lift expired bans and timeouts: {}", err);
+            }
+            if let Err(err) = chat_tree.expire_invites_logic(utils::get_time_secs()).await {
+                error!("failed to expire invites: {}", err);
+            }
+        }
+    };
+
+    tokio::spawn(fut.instrument(info_span!("scherzo::expiry")))
+}
+
 fn copy_dir_all(src: PathBuf, dst: PathBuf) -> std::io::Result<()> {
     use std::fs;
 
@@ -15,6 +15,8 @@ use std::{
 };
 use tower::Service;
 
+use crate::config::RateLimitConfig;
+
 /// Enforces a rate limit on the number of requests the underlying
 /// service can handle over a period of time.
 pub struct RateLimit<T> {
@@ -68,6 +70,17 @@ impl<S> RateLimit<S> {
         }
     }
 
+    /// Create a new rate limiter that follows the rate limit policy in the config
+    pub fn from_config(inner: S, num: u64, per: Duration, config: &RateLimitConfig) -> Self {
+        Self::new(
+            inner,
+            num,
+            per,
+            config.client_ip_header_name.clone(),
+            config.allowed_ips.clone(),
+        )
+    }
+
     /// Get a reference to the inner service
     pub fn get_ref(&self) -> &S {
         &self.inner
@@ -5,7 +5,7 @@ use std::{
     time::Duration,
 };
 
-use hrpc::{request::BoxRequest, server::layer::ratelimit::RateLimitLayer};
+use hrpc::{request::BoxRequest, server::layer::ratelimit::RateLimitLayer, Request};
 
 pub type ExtractKey = impl Fn(&mut BoxRequest) -> Option<IpAddr> + Clone;
 pub type CheckKey = impl Fn(&IpAddr) -> bool + Clone;
@@ -23,21 +23,24 @@ pub fn rate_limit(
     });
 
     RateLimitLayer::new(num, per).set_key_fns(
-        move |req| {
-            check_header_for_ip
-                .as_deref()
-                .and_then(|header_name| get_ip_addr_from_header(req, header_name))
-                .or_else(|| get_ip_addr(req))
-        },
+        move |req| get_client_ip(req, check_header_for_ip.as_deref()),
         move |ip| allowed_ips.as_ref().map_or(false, |ips| ips.contains(ip)),
     )
 }
 
-fn get_ip_addr(req: &BoxRequest) -> Option<IpAddr> {
+/// Gets the IP address of the client that sent a request, preferring the one
+/// in `check_header_for_ip` if it's set.
+pub fn get_client_ip<T>(req: &Request<T>, check_header_for_ip: Option<&str>) -> Option<IpAddr> {
+    check_header_for_ip
+        .and_then(|header_name| get_ip_addr_from_header(req, header_name))
+        .or_else(|| get_ip_addr(req))
+}
+
+fn get_ip_addr<T>(req: &Request<T>) -> Option<IpAddr> {
     req.extensions().get::<SocketAddr>().map(|addr| addr.ip())
 }
 
-fn get_ip_addr_from_header(req: &BoxRequest, check_header_for_ip: &str) -> Option<IpAddr> {
+fn get_ip_addr_from_header<T>(req: &Request<T>, check_header_for_ip: &str) -> Option<IpAddr> {
     req.header_map()
         .and_then(|headers| headers.get(check_header_for_ip))
         .and_then(|val| val.to_str().ok())